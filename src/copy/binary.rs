@@ -0,0 +1,332 @@
+//! Binary COPY format.
+//!
+//! ```text
+//! Header:  "PGCOPY\n\xff\r\n\0" | flags: i32 | header extension length: i32
+//! Tuple:   field count: i16 | (length: i32, -1 for NULL | data)*
+//! Trailer: -1: i16
+//! ```
+
+use crate::conversion::{FromRow, ToParams};
+use crate::error::{Error, Result};
+use crate::protocol::backend::query::{DataRow, FieldDescription, FieldDescriptionTail};
+
+use super::column_descriptions;
+use crate::protocol::types::{FormatCode, Oid, preferred_format};
+
+/// Signature at the start of binary COPY data.
+pub const SIGNATURE: &[u8; 11] = b"PGCOPY\n\xff\r\n\0";
+
+/// Length of the fixed part of the header (signature, flags, header extension length).
+const HEADER_LEN: usize = SIGNATURE.len() + 8;
+
+/// Flags bit 16: each tuple has an OID field.
+const FLAG_HAS_OIDS: i32 = 1 << 16;
+
+/// Encoder for binary COPY data.
+///
+/// Rows are encoded with [`ToWireValue::encode`](crate::conversion::ToWireValue::encode)
+/// for the column type OIDs given to [`new()`](Self::new), so any type that can be
+/// bound as a parameter can be written.
+#[derive(Debug, Clone)]
+pub struct BinaryCopyEncoder {
+    oids: Vec<Oid>,
+}
+
+impl BinaryCopyEncoder {
+    /// Create an encoder for columns of the given type OIDs.
+    ///
+    /// Returns an error for types without a binary encoding (NUMERIC).
+    pub fn new(oids: &[Oid]) -> Result<Self> {
+        if i16::try_from(oids.len()).is_err() {
+            return Err(Error::InvalidUsage(format!(
+                "too many columns for binary COPY: {}",
+                oids.len()
+            )));
+        }
+        if let Some(oid) = oids
+            .iter()
+            .find(|oid| preferred_format(**oid) != FormatCode::Binary)
+        {
+            return Err(Error::Unsupported(format!(
+                "type OID {} has no binary encoding, use text COPY instead",
+                oid
+            )));
+        }
+        Ok(Self {
+            oids: oids.to_vec(),
+        })
+    }
+
+    /// Column type OIDs.
+    pub fn oids(&self) -> &[Oid] {
+        &self.oids
+    }
+
+    /// Write the header (signature, flags, empty header extension).
+    pub fn write_header(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(SIGNATURE);
+        buf.extend_from_slice(&0_i32.to_be_bytes());
+        buf.extend_from_slice(&0_i32.to_be_bytes());
+    }
+
+    /// Write one tuple.
+    ///
+    /// On error, `buf` is left unchanged.
+    pub fn write_row<P: ToParams>(&self, row: &P, buf: &mut Vec<u8>) -> Result<()> {
+        if row.param_count() != self.oids.len() {
+            return Err(Error::InvalidUsage(format!(
+                "row has {} values but binary COPY expects {} columns",
+                row.param_count(),
+                self.oids.len()
+            )));
+        }
+
+        let start = buf.len();
+        buf.extend_from_slice(&(self.oids.len() as i16).to_be_bytes());
+        let result = row.encode(&self.oids, buf);
+        if result.is_err() {
+            buf.truncate(start);
+        }
+        result
+    }
+
+    /// Write the trailer.
+    pub fn write_trailer(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&(-1_i16).to_be_bytes());
+    }
+}
+
+/// Decoder for binary COPY data.
+///
+/// Data is pushed in arbitrary chunks with [`push()`](Self::push), and complete
+/// tuples are decoded with [`next_row()`](Self::next_row) into any [`FromRow`]
+/// type using [`FromWireValue::from_binary`](crate::conversion::FromWireValue::from_binary)
+/// for the column type OIDs given to [`new()`](Self::new).
+#[derive(Debug, Clone)]
+pub struct BinaryCopyDecoder {
+    columns: Vec<FieldDescriptionTail>,
+    buf: Vec<u8>,
+    /// Start of the undecoded data in `buf`
+    pos: usize,
+    header_done: bool,
+    finished: bool,
+}
+
+impl BinaryCopyDecoder {
+    /// Create a decoder for columns of the given type OIDs.
+    pub fn new(oids: &[Oid]) -> Self {
+        Self {
+            columns: column_descriptions(oids, FormatCode::Binary),
+            buf: Vec::new(),
+            pos: 0,
+            header_done: false,
+            finished: false,
+        }
+    }
+
+    /// Append COPY data.
+    pub fn push(&mut self, data: &[u8]) {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Returns true once the trailer has been decoded.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Decode the next tuple.
+    ///
+    /// Returns `None` if more data is needed or the trailer has been reached
+    /// (see [`is_finished()`](Self::is_finished)).
+    pub fn next_row<'a, T: FromRow<'a>>(&'a mut self) -> Result<Option<T>> {
+        if !self.header_done && !self.decode_header()? {
+            return Ok(None);
+        }
+        if self.finished {
+            return Ok(None);
+        }
+
+        let data = self.buf.get(self.pos..).unwrap_or_default();
+        let Some((count, mut rest)) = data.split_first_chunk::<2>() else {
+            return Ok(None);
+        };
+        let count = i16::from_be_bytes(*count);
+        if count == -1 {
+            self.finished = true;
+            self.pos += 2;
+            return Ok(None);
+        }
+        if count as usize != self.columns.len() {
+            return Err(Error::Decode(format!(
+                "binary COPY tuple has {} fields but {} columns were expected",
+                count,
+                self.columns.len()
+            )));
+        }
+
+        for _ in 0..count {
+            let Some((len, remaining)) = rest.split_first_chunk::<4>() else {
+                return Ok(None);
+            };
+            let len = i32::from_be_bytes(*len);
+            rest = remaining;
+            if len >= 0 {
+                let Some(after_value) = rest.get(len as usize..) else {
+                    return Ok(None);
+                };
+                rest = after_value;
+            }
+        }
+
+        let start = self.pos;
+        let end = self.buf.len() - rest.len();
+        self.pos = end;
+
+        let this: &'a Self = self;
+        let row = DataRow::parse(this.buf.get(start..end).unwrap_or_default())?;
+        let cols: Vec<FieldDescription<'_>> = this
+            .columns
+            .iter()
+            .map(|tail| FieldDescription { name: "", tail })
+            .collect();
+        T::from_row_binary(&cols, row).map(Some)
+    }
+
+    /// Decode the header. Returns `false` if more data is needed.
+    fn decode_header(&mut self) -> Result<bool> {
+        let data = self.buf.get(self.pos..).unwrap_or_default();
+        let Some((header, rest)) = data.split_first_chunk::<HEADER_LEN>() else {
+            return Ok(false);
+        };
+        let (signature, rest_of_header) = header.split_at(SIGNATURE.len());
+        if signature != SIGNATURE {
+            return Err(Error::Decode("invalid binary COPY signature".into()));
+        }
+        let mut fields = rest_of_header.chunks_exact(4).map(|bytes| {
+            let mut int = [0u8; 4];
+            int.copy_from_slice(bytes);
+            i32::from_be_bytes(int)
+        });
+        let flags = fields.next().unwrap_or_default();
+        let extension_len = fields.next().unwrap_or_default();
+        if flags & FLAG_HAS_OIDS != 0 {
+            return Err(Error::Unsupported(
+                "binary COPY with OIDs is not supported".into(),
+            ));
+        }
+        let Some(extension_len) = usize::try_from(extension_len).ok() else {
+            return Err(Error::Decode(
+                "invalid binary COPY header extension length".into(),
+            ));
+        };
+        if rest.len() < extension_len {
+            return Ok(false);
+        }
+        self.pos += HEADER_LEN + extension_len;
+        self.header_done = true;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::types::oid;
+
+    #[test]
+    fn test_encode() {
+        let encoder = BinaryCopyEncoder::new(&[oid::INT4, oid::TEXT]).unwrap();
+        let mut buf = Vec::new();
+        encoder.write_header(&mut buf);
+        encoder.write_row(&(1_i32, "ab"), &mut buf).unwrap();
+        encoder.write_row(&(2_i32, None::<&str>), &mut buf).unwrap();
+        encoder.write_trailer(&mut buf);
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(b"PGCOPY\n\xff\r\n\0");
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[0, 2, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0, 0, 2, b'a', b'b']);
+        expected.extend_from_slice(&[0, 2, 0, 0, 0, 4, 0, 0, 0, 2, 0xff, 0xff, 0xff, 0xff]);
+        expected.extend_from_slice(&[0xff, 0xff]);
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_column_count_mismatch() {
+        let encoder = BinaryCopyEncoder::new(&[oid::INT4, oid::TEXT]).unwrap();
+        let mut buf = Vec::new();
+        assert!(encoder.write_row(&(1_i32,), &mut buf).is_err());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_encode_error_leaves_buffer() {
+        let encoder = BinaryCopyEncoder::new(&[oid::INT2]).unwrap();
+        let mut buf = vec![1, 2, 3];
+        assert!(encoder.write_row(&(100_000_i32,), &mut buf).is_err());
+        assert_eq!(buf, [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_decode() {
+        let encoder = BinaryCopyEncoder::new(&[oid::INT4, oid::TEXT]).unwrap();
+        let mut buf = Vec::new();
+        encoder.write_header(&mut buf);
+        encoder.write_row(&(1_i32, "ab"), &mut buf).unwrap();
+        encoder.write_row(&(2_i32, None::<&str>), &mut buf).unwrap();
+        encoder.write_trailer(&mut buf);
+
+        // Feed one byte at a time to exercise partial tuples
+        let mut decoder = BinaryCopyDecoder::new(&[oid::INT4, oid::TEXT]);
+        let mut rows = Vec::new();
+        for byte in &buf {
+            decoder.push(std::slice::from_ref(byte));
+            while let Some(row) = decoder.next_row::<(i32, Option<String>)>().unwrap() {
+                rows.push(row);
+            }
+        }
+        assert!(decoder.is_finished());
+        assert_eq!(rows, vec![(1, Some("ab".to_string())), (2, None)]);
+    }
+
+    #[test]
+    fn test_decode_borrowed() {
+        let encoder = BinaryCopyEncoder::new(&[oid::TEXT]).unwrap();
+        let mut buf = Vec::new();
+        encoder.write_header(&mut buf);
+        encoder.write_row(&("hello",), &mut buf).unwrap();
+
+        let mut decoder = BinaryCopyDecoder::new(&[oid::TEXT]);
+        decoder.push(&buf);
+        let row: (&str,) = decoder.next_row().unwrap().unwrap();
+        assert_eq!(row, ("hello",));
+    }
+
+    #[test]
+    fn test_decode_invalid_signature() {
+        let mut decoder = BinaryCopyDecoder::new(&[oid::INT4]);
+        decoder.push(b"1\talice\n2\tbob\n3\tcarol\n");
+        assert!(decoder.next_row::<(i32,)>().is_err());
+    }
+
+    #[test]
+    fn test_decode_column_count_mismatch() {
+        let encoder = BinaryCopyEncoder::new(&[oid::INT4, oid::INT4]).unwrap();
+        let mut buf = Vec::new();
+        encoder.write_header(&mut buf);
+        encoder.write_row(&(1_i32, 2_i32), &mut buf).unwrap();
+
+        let mut decoder = BinaryCopyDecoder::new(&[oid::INT4]);
+        decoder.push(&buf);
+        assert!(decoder.next_row::<(i32,)>().is_err());
+    }
+
+    #[test]
+    fn test_numeric_unsupported() {
+        assert!(BinaryCopyEncoder::new(&[oid::NUMERIC]).is_err());
+    }
+}
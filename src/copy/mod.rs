@@ -0,0 +1,31 @@
+//! COPY data formats.
+//!
+//! Encoders and decoders for the data carried in CopyData messages.
+//! They do not perform I/O and are shared by the sync and tokio COPY APIs.
+
+mod binary;
+mod text;
+
+use zerocopy::byteorder::big_endian::{I16 as I16BE, I32 as I32BE, U16 as U16BE, U32 as U32BE};
+
+use crate::protocol::backend::query::FieldDescriptionTail;
+use crate::protocol::types::{FormatCode, Oid};
+
+pub use binary::{BinaryCopyDecoder, BinaryCopyEncoder};
+pub use text::{
+    CopyTextDecoder, CopyTextEncoder, CopyTextFormat, CopyTextOptions, ToCopyRow, ToCopyText,
+};
+
+/// Build column descriptions for decoding COPY tuples with [`FromRow`](crate::conversion::FromRow).
+fn column_descriptions(oids: &[Oid], format: FormatCode) -> Vec<FieldDescriptionTail> {
+    oids.iter()
+        .map(|oid| FieldDescriptionTail {
+            table_oid: U32BE::new(0),
+            column_id: I16BE::new(0),
+            type_oid: U32BE::new(*oid),
+            type_size: I16BE::new(-1),
+            type_modifier: I32BE::new(-1),
+            format: U16BE::new(format as u16),
+        })
+        .collect()
+}
@@ -0,0 +1,849 @@
+//! Text and CSV COPY formats.
+//!
+//! Each row is one line. In text format, fields are separated by the delimiter
+//! and special characters are backslash-escaped. In CSV format, fields
+//! containing special characters are quoted.
+
+use std::io::Write;
+
+use crate::conversion::FromRow;
+use crate::error::{Error, Result};
+use crate::protocol::backend::query::{DataRow, FieldDescription, FieldDescriptionTail};
+use crate::protocol::types::{FormatCode, Oid};
+
+use super::column_descriptions;
+
+/// Format of text COPY data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CopyTextFormat {
+    /// `FORMAT text`: tab-separated with backslash escapes
+    #[default]
+    Text,
+    /// `FORMAT csv`: comma-separated with quoting
+    Csv,
+}
+
+/// Options of text COPY data.
+///
+/// These must match the options of the COPY statement.
+#[derive(Debug, Clone)]
+pub struct CopyTextOptions {
+    /// Text or CSV format.
+    ///
+    /// Default: `Text`
+    pub format: CopyTextFormat,
+
+    /// Column separator (`DELIMITER`).
+    ///
+    /// Default: tab for text, `,` for CSV
+    pub delimiter: u8,
+
+    /// String representing NULL (`NULL`).
+    ///
+    /// Default: `\N` for text, empty string for CSV. Must not be empty for text;
+    /// see [`validate()`](Self::validate).
+    pub null: String,
+
+    /// Quote character for CSV (`QUOTE`).
+    ///
+    /// Default: `"`
+    pub quote: u8,
+
+    /// Character that escapes the quote character inside a quoted CSV field (`ESCAPE`).
+    ///
+    /// Default: same as `quote`
+    pub escape: u8,
+}
+
+impl CopyTextOptions {
+    /// Default options of `FORMAT text`.
+    pub fn text() -> Self {
+        Self {
+            format: CopyTextFormat::Text,
+            delimiter: b'\t',
+            null: "\\N".into(),
+            quote: b'"',
+            escape: b'"',
+        }
+    }
+
+    /// Default options of `FORMAT csv`.
+    pub fn csv() -> Self {
+        Self {
+            format: CopyTextFormat::Csv,
+            delimiter: b',',
+            null: String::new(),
+            quote: b'"',
+            escape: b'"',
+        }
+    }
+
+    /// Check that every value can be written with these options.
+    ///
+    /// In text format, the NULL string is matched before backslash escapes are
+    /// decoded, so a value spelled like it is written with an escape instead.
+    /// An empty string has no other spelling, so an empty NULL string is rejected.
+    pub fn validate(&self) -> Result<()> {
+        if self.format == CopyTextFormat::Text && self.null.is_empty() {
+            return Err(empty_null_error());
+        }
+        Ok(())
+    }
+}
+
+fn empty_null_error() -> Error {
+    Error::InvalidUsage(
+        "an empty NULL string can't be told apart from an empty value in COPY text format".into(),
+    )
+}
+
+impl Default for CopyTextOptions {
+    fn default() -> Self {
+        Self::text()
+    }
+}
+
+/// Trait for encoding Rust values in text COPY data.
+///
+/// The value is written unescaped; escaping and quoting are done by [`CopyTextEncoder`].
+pub trait ToCopyText {
+    /// Write the text representation of this value.
+    ///
+    /// Returns `false` for NULL, without writing anything.
+    fn write_text(&self, buf: &mut Vec<u8>) -> Result<bool>;
+}
+
+/// Trait for encoding a row in text COPY data.
+///
+/// Implemented for tuples of [`ToCopyText`] values.
+pub trait ToCopyRow {
+    /// Number of fields.
+    fn field_count(&self) -> usize;
+
+    /// Write each field with [`CopyTextEncoder::write_field`].
+    fn write_fields(&self, encoder: &mut CopyTextEncoder, buf: &mut Vec<u8>) -> Result<()>;
+}
+
+/// Encoder for text and CSV COPY data.
+#[derive(Debug, Clone)]
+pub struct CopyTextEncoder {
+    options: CopyTextOptions,
+    /// Unescaped text of the current field
+    scratch: Vec<u8>,
+    /// Number of fields written in the current row
+    field_index: usize,
+}
+
+impl CopyTextEncoder {
+    /// Create an encoder with the given options.
+    pub fn new(options: CopyTextOptions) -> Self {
+        Self {
+            options,
+            scratch: Vec::new(),
+            field_index: 0,
+        }
+    }
+
+    /// Options of this encoder.
+    pub fn options(&self) -> &CopyTextOptions {
+        &self.options
+    }
+
+    /// Write one row, terminated by a newline.
+    ///
+    /// On error, `buf` is left unchanged.
+    pub fn write_row<R: ToCopyRow + ?Sized>(&mut self, row: &R, buf: &mut Vec<u8>) -> Result<()> {
+        let start = buf.len();
+        self.field_index = 0;
+        let result = row.write_fields(self, buf);
+        if result.is_err() {
+            buf.truncate(start);
+            return result;
+        }
+        buf.push(b'\n');
+        Ok(())
+    }
+
+    /// Write one field of the current row, preceded by the delimiter if needed.
+    pub fn write_field<T: ToCopyText + ?Sized>(
+        &mut self,
+        value: &T,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        if self.field_index > 0 {
+            buf.push(self.options.delimiter);
+        }
+        self.field_index += 1;
+
+        self.scratch.clear();
+        if !value.write_text(&mut self.scratch)? {
+            buf.extend_from_slice(self.options.null.as_bytes());
+            return Ok(());
+        }
+        match self.options.format {
+            CopyTextFormat::Text => self.escape_text(buf)?,
+            CopyTextFormat::Csv => self.escape_csv(buf),
+        }
+        Ok(())
+    }
+
+    fn escape_text(&self, buf: &mut Vec<u8>) -> Result<()> {
+        let start = buf.len();
+        self.escape_text_bytes(&self.scratch, buf);
+
+        // The server matches the NULL string before decoding escapes, so a value
+        // spelled like it gets its first byte written as an octal escape
+        if buf.get(start..) == Some(self.options.null.as_bytes()) {
+            let Some((&first, rest)) = self.scratch.split_first() else {
+                return Err(empty_null_error());
+            };
+            buf.truncate(start);
+            buf.extend_from_slice(&[
+                b'\\',
+                b'0' + (first >> 6),
+                b'0' + ((first >> 3) & 0o7),
+                b'0' + (first & 0o7),
+            ]);
+            self.escape_text_bytes(rest, buf);
+        }
+        Ok(())
+    }
+
+    fn escape_text_bytes(&self, value: &[u8], buf: &mut Vec<u8>) {
+        for &byte in value {
+            match byte {
+                b'\\' => buf.extend_from_slice(b"\\\\"),
+                b'\n' => buf.extend_from_slice(b"\\n"),
+                b'\r' => buf.extend_from_slice(b"\\r"),
+                b'\t' => buf.extend_from_slice(b"\\t"),
+                b if b == self.options.delimiter => buf.extend_from_slice(&[b'\\', b]),
+                b => buf.push(b),
+            }
+        }
+    }
+
+    fn escape_csv(&self, buf: &mut Vec<u8>) {
+        let CopyTextOptions {
+            delimiter,
+            quote,
+            escape,
+            ..
+        } = self.options;
+        let value = self.scratch.as_slice();
+
+        // Quote values that would be read back differently: special characters,
+        // the NULL string, and the end-of-data marker
+        let needs_quote = value == self.options.null.as_bytes()
+            || value == b"\\."
+            || value
+                .iter()
+                .any(|&b| b == delimiter || b == quote || b == escape || b == b'\n' || b == b'\r');
+        if !needs_quote {
+            buf.extend_from_slice(value);
+            return;
+        }
+
+        buf.push(quote);
+        for &byte in value {
+            if byte == quote || byte == escape {
+                buf.push(escape);
+            }
+            buf.push(byte);
+        }
+        buf.push(quote);
+    }
+}
+
+/// Decoder for text and CSV COPY data.
+///
+/// Data is pushed in arbitrary chunks with [`push()`](Self::push), and complete
+/// rows are decoded with [`next_row()`](Self::next_row) into any [`FromRow`]
+/// type using [`FromWireValue::from_text`](crate::conversion::FromWireValue::from_text)
+/// for the column type OIDs given to [`new()`](Self::new).
+#[derive(Debug, Clone)]
+pub struct CopyTextDecoder {
+    options: CopyTextOptions,
+    columns: Vec<FieldDescriptionTail>,
+    buf: Vec<u8>,
+    /// Start of the undecoded data in `buf`
+    pos: usize,
+    /// Current row in DataRow payload layout
+    row: Vec<u8>,
+}
+
+impl CopyTextDecoder {
+    /// Create a decoder with the given options for columns of the given type OIDs.
+    pub fn new(options: CopyTextOptions, oids: &[Oid]) -> Self {
+        Self {
+            options,
+            columns: column_descriptions(oids, FormatCode::Text),
+            buf: Vec::new(),
+            pos: 0,
+            row: Vec::new(),
+        }
+    }
+
+    /// Append COPY data.
+    pub fn push(&mut self, data: &[u8]) {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Returns true if there is no buffered data (no partial row).
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    /// Decode the next row.
+    ///
+    /// Returns `None` if more data is needed.
+    pub fn next_row<'a, T: FromRow<'a>>(&'a mut self) -> Result<Option<T>> {
+        loop {
+            let Some(end) = self.find_row_end() else {
+                return Ok(None);
+            };
+            let start = self.pos;
+            self.pos = end + 1;
+
+            let mut line = self.buf.get(start..end).unwrap_or_default();
+            if let Some(stripped) = line.strip_suffix(b"\r") {
+                line = stripped;
+            }
+            // End-of-data marker
+            if line == b"\\." {
+                continue;
+            }
+
+            self.row.clear();
+            self.row.extend_from_slice(&0_u16.to_be_bytes());
+            let count = match self.options.format {
+                CopyTextFormat::Text => parse_text_line(&self.options, line, &mut self.row)?,
+                CopyTextFormat::Csv => parse_csv_line(&self.options, line, &mut self.row)?,
+            };
+            if count != self.columns.len() {
+                return Err(Error::Decode(format!(
+                    "COPY row has {} fields but {} columns were expected",
+                    count,
+                    self.columns.len()
+                )));
+            }
+            let count = u16::try_from(count)
+                .map_err(|e| Error::Decode(format!("too many fields in COPY row: {e}")))?;
+            if let Some(head) = self.row.first_chunk_mut::<2>() {
+                *head = count.to_be_bytes();
+            }
+            break;
+        }
+
+        let this: &'a Self = self;
+        let row = DataRow::parse(&this.row)?;
+        let cols: Vec<FieldDescription<'_>> = this
+            .columns
+            .iter()
+            .map(|tail| FieldDescription { name: "", tail })
+            .collect();
+        T::from_row_text(&cols, row).map(Some)
+    }
+
+    /// Find the newline that ends the row starting at `pos`.
+    fn find_row_end(&self) -> Option<usize> {
+        let data = self.buf.get(self.pos..)?;
+        let mut in_quote = false;
+        let mut i = 0;
+        while let Some(&byte) = data.get(i) {
+            let CopyTextOptions { quote, escape, .. } = self.options;
+            match (self.options.format, in_quote) {
+                (CopyTextFormat::Text, _) => match byte {
+                    b'\\' => i += 1,
+                    b'\n' => return Some(self.pos + i),
+                    _ => {}
+                },
+                (CopyTextFormat::Csv, true) => match byte {
+                    b if b == escape && escape != quote => i += 1,
+                    b if b == quote => in_quote = false,
+                    _ => {}
+                },
+                (CopyTextFormat::Csv, false) => match byte {
+                    b if b == quote => in_quote = true,
+                    b'\n' => return Some(self.pos + i),
+                    _ => {}
+                },
+            }
+            i += 1;
+        }
+        None
+    }
+}
+
+/// Append a field in DataRow layout: length (-1 for NULL) followed by the value.
+fn push_field(row: &mut Vec<u8>, value: Option<&[u8]>) -> Result<()> {
+    match value {
+        None => row.extend_from_slice(&(-1_i32).to_be_bytes()),
+        Some(value) => {
+            let len = i32::try_from(value.len())
+                .map_err(|e| Error::Decode(format!("COPY field is too large: {e}")))?;
+            row.extend_from_slice(&len.to_be_bytes());
+            row.extend_from_slice(value);
+        }
+    }
+    Ok(())
+}
+
+/// Parse a text format line into `row`. Returns the number of fields.
+fn parse_text_line(options: &CopyTextOptions, line: &[u8], row: &mut Vec<u8>) -> Result<usize> {
+    let mut count = 0;
+    let mut value = Vec::new();
+    let mut rest = line;
+
+    loop {
+        // Find the end of the field, skipping escaped characters
+        let mut end = 0;
+        while let Some(&byte) = rest.get(end) {
+            match byte {
+                b'\\' => end += 2,
+                b if b == options.delimiter => break,
+                _ => end += 1,
+            }
+        }
+        let end = end.min(rest.len());
+        let (raw, remaining) = rest.split_at(end);
+
+        count += 1;
+        if raw == options.null.as_bytes() {
+            push_field(row, None)?;
+        } else {
+            value.clear();
+            unescape_text(raw, &mut value);
+            push_field(row, Some(&value))?;
+        }
+
+        match remaining.split_first() {
+            Some((_, after_delimiter)) => rest = after_delimiter,
+            None => return Ok(count),
+        }
+    }
+}
+
+/// Decode backslash escapes of a text format field.
+fn unescape_text(raw: &[u8], out: &mut Vec<u8>) {
+    let mut iter = raw.iter().copied().peekable();
+    while let Some(byte) = iter.next() {
+        if byte != b'\\' {
+            out.push(byte);
+            continue;
+        }
+        let Some(escaped) = iter.next() else {
+            break;
+        };
+        match escaped {
+            b'b' => out.push(0x08),
+            b'f' => out.push(0x0c),
+            b'n' => out.push(b'\n'),
+            b'r' => out.push(b'\r'),
+            b't' => out.push(b'\t'),
+            b'v' => out.push(0x0b),
+            b'0'..=b'7' => {
+                let mut code = u32::from(escaped - b'0');
+                for _ in 0..2 {
+                    match iter.peek() {
+                        Some(&digit @ b'0'..=b'7') => {
+                            code = code * 8 + u32::from(digit - b'0');
+                            iter.next();
+                        }
+                        _ => break,
+                    }
+                }
+                out.push(code as u8);
+            }
+            b'x' if iter.peek().is_some_and(u8::is_ascii_hexdigit) => {
+                let mut code = 0;
+                for _ in 0..2 {
+                    match iter.peek().and_then(|&digit| (digit as char).to_digit(16)) {
+                        Some(digit) => {
+                            code = code * 16 + digit;
+                            iter.next();
+                        }
+                        None => break,
+                    }
+                }
+                out.push(code as u8);
+            }
+            other => out.push(other),
+        }
+    }
+}
+
+/// Parse a CSV format line into `row`. Returns the number of fields.
+fn parse_csv_line(options: &CopyTextOptions, line: &[u8], row: &mut Vec<u8>) -> Result<usize> {
+    let mut count = 0;
+    let mut value = Vec::new();
+    let mut i = 0;
+
+    loop {
+        let start = i;
+        let mut quoted = false;
+        let mut in_quote = false;
+        value.clear();
+
+        while let Some(&byte) = line.get(i) {
+            if in_quote {
+                if byte == options.escape
+                    && line
+                        .get(i + 1)
+                        .is_some_and(|&next| next == options.quote || next == options.escape)
+                    && (options.escape != options.quote || line.get(i + 1) == Some(&options.quote))
+                {
+                    value.extend(line.get(i + 1));
+                    i += 2;
+                    continue;
+                }
+                if byte == options.quote {
+                    in_quote = false;
+                } else {
+                    value.push(byte);
+                }
+            } else {
+                match byte {
+                    b if b == options.quote => {
+                        in_quote = true;
+                        quoted = true;
+                    }
+                    b if b == options.delimiter => break,
+                    b => value.push(b),
+                }
+            }
+            i += 1;
+        }
+        if in_quote {
+            return Err(Error::Decode("unterminated CSV quoted field".into()));
+        }
+
+        count += 1;
+        let raw = line.get(start..i).unwrap_or_default();
+        if !quoted && raw == options.null.as_bytes() {
+            push_field(row, None)?;
+        } else {
+            push_field(row, Some(&value))?;
+        }
+
+        if i >= line.len() {
+            return Ok(count);
+        }
+        // Skip the delimiter
+        i += 1;
+    }
+}
+
+// === ToCopyText implementations ===
+
+impl<T: ToCopyText> ToCopyText for Option<T> {
+    fn write_text(&self, buf: &mut Vec<u8>) -> Result<bool> {
+        match self {
+            Some(v) => v.write_text(buf),
+            None => Ok(false),
+        }
+    }
+}
+
+impl<T: ToCopyText + ?Sized> ToCopyText for &T {
+    fn write_text(&self, buf: &mut Vec<u8>) -> Result<bool> {
+        (*self).write_text(buf)
+    }
+}
+
+impl ToCopyText for bool {
+    fn write_text(&self, buf: &mut Vec<u8>) -> Result<bool> {
+        buf.push(if *self { b't' } else { b'f' });
+        Ok(true)
+    }
+}
+
+impl ToCopyText for str {
+    fn write_text(&self, buf: &mut Vec<u8>) -> Result<bool> {
+        buf.extend_from_slice(self.as_bytes());
+        Ok(true)
+    }
+}
+
+impl ToCopyText for String {
+    fn write_text(&self, buf: &mut Vec<u8>) -> Result<bool> {
+        self.as_str().write_text(buf)
+    }
+}
+
+/// BYTEA in hex format.
+impl ToCopyText for [u8] {
+    fn write_text(&self, buf: &mut Vec<u8>) -> Result<bool> {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        buf.reserve(2 + self.len() * 2);
+        buf.extend_from_slice(b"\\x");
+        for byte in self {
+            buf.extend(HEX.get(usize::from(byte >> 4)));
+            buf.extend(HEX.get(usize::from(byte & 0x0f)));
+        }
+        Ok(true)
+    }
+}
+
+impl ToCopyText for Vec<u8> {
+    fn write_text(&self, buf: &mut Vec<u8>) -> Result<bool> {
+        self.as_slice().write_text(buf)
+    }
+}
+
+/// Types whose `Display` output is accepted by the PostgreSQL input function.
+macro_rules! impl_to_copy_text_display {
+    ($($T:ty),+ $(,)?) => {
+        $(
+            impl ToCopyText for $T {
+                fn write_text(&self, buf: &mut Vec<u8>) -> Result<bool> {
+                    write!(buf, "{}", self)?;
+                    Ok(true)
+                }
+            }
+        )+
+    };
+}
+
+impl_to_copy_text_display!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64);
+
+#[cfg(feature = "with-uuid")]
+impl_to_copy_text_display!(uuid::Uuid);
+
+#[cfg(feature = "with-rust-decimal")]
+impl_to_copy_text_display!(rust_decimal::Decimal);
+
+#[cfg(feature = "with-chrono")]
+impl_to_copy_text_display!(
+    chrono::NaiveDate,
+    chrono::NaiveTime,
+    chrono::NaiveDateTime,
+    chrono::DateTime<chrono::Utc>,
+);
+
+#[cfg(feature = "with-time")]
+impl_to_copy_text_display!(
+    time::Date,
+    time::Time,
+    time::PrimitiveDateTime,
+    time::OffsetDateTime,
+);
+
+// === ToCopyRow implementations ===
+
+impl<R: ToCopyRow + ?Sized> ToCopyRow for &R {
+    fn field_count(&self) -> usize {
+        (*self).field_count()
+    }
+
+    fn write_fields(&self, encoder: &mut CopyTextEncoder, buf: &mut Vec<u8>) -> Result<()> {
+        (*self).write_fields(encoder, buf)
+    }
+}
+
+macro_rules! impl_to_copy_row {
+    ($count:expr, $($idx:tt: $T:ident),+) => {
+        impl<$($T: ToCopyText),+> ToCopyRow for ($($T,)+) {
+            fn field_count(&self) -> usize {
+                $count
+            }
+
+            fn write_fields(&self, encoder: &mut CopyTextEncoder, buf: &mut Vec<u8>) -> Result<()> {
+                $(
+                    encoder.write_field(&self.$idx, buf)?;
+                )+
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_to_copy_row!(1, 0: T0);
+impl_to_copy_row!(2, 0: T0, 1: T1);
+impl_to_copy_row!(3, 0: T0, 1: T1, 2: T2);
+impl_to_copy_row!(4, 0: T0, 1: T1, 2: T2, 3: T3);
+impl_to_copy_row!(5, 0: T0, 1: T1, 2: T2, 3: T3, 4: T4);
+impl_to_copy_row!(6, 0: T0, 1: T1, 2: T2, 3: T3, 4: T4, 5: T5);
+impl_to_copy_row!(7, 0: T0, 1: T1, 2: T2, 3: T3, 4: T4, 5: T5, 6: T6);
+impl_to_copy_row!(8, 0: T0, 1: T1, 2: T2, 3: T3, 4: T4, 5: T5, 6: T6, 7: T7);
+impl_to_copy_row!(9, 0: T0, 1: T1, 2: T2, 3: T3, 4: T4, 5: T5, 6: T6, 7: T7, 8: T8);
+impl_to_copy_row!(10, 0: T0, 1: T1, 2: T2, 3: T3, 4: T4, 5: T5, 6: T6, 7: T7, 8: T8, 9: T9);
+impl_to_copy_row!(11, 0: T0, 1: T1, 2: T2, 3: T3, 4: T4, 5: T5, 6: T6, 7: T7, 8: T8, 9: T9, 10: T10);
+impl_to_copy_row!(12, 0: T0, 1: T1, 2: T2, 3: T3, 4: T4, 5: T5, 6: T6, 7: T7, 8: T8, 9: T9, 10: T10, 11: T11);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::types::oid;
+
+    fn encode<R: ToCopyRow>(options: CopyTextOptions, rows: &[R]) -> String {
+        let mut encoder = CopyTextEncoder::new(options);
+        let mut buf = Vec::new();
+        for row in rows {
+            encoder.write_row(row, &mut buf).unwrap();
+        }
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn decode(options: CopyTextOptions, data: &str) -> Vec<(i32, Option<String>)> {
+        let mut decoder = CopyTextDecoder::new(options, &[oid::INT4, oid::TEXT]);
+        let mut rows = Vec::new();
+        // Feed one byte at a time to exercise partial rows
+        for byte in data.as_bytes() {
+            decoder.push(std::slice::from_ref(byte));
+            while let Some(row) = decoder.next_row().unwrap() {
+                rows.push(row);
+            }
+        }
+        assert!(decoder.is_empty());
+        rows
+    }
+
+    fn sample_rows() -> Vec<(i32, Option<String>)> {
+        vec![
+            (1, Some("plain".to_string())),
+            (2, None),
+            (3, Some(String::new())),
+            (4, Some("tab\there\nnewline\rcr\\backslash".to_string())),
+            (5, Some("comma, \"quote\"".to_string())),
+            (6, Some("\\N".to_string())),
+            (7, Some("\\.".to_string())),
+        ]
+    }
+
+    #[test]
+    fn test_encode_text() {
+        let data = encode(CopyTextOptions::text(), &sample_rows());
+        assert_eq!(
+            data,
+            "1\tplain\n\
+             2\t\\N\n\
+             3\t\n\
+             4\ttab\\there\\nnewline\\rcr\\\\backslash\n\
+             5\tcomma, \"quote\"\n\
+             6\t\\\\N\n\
+             7\t\\\\.\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_csv() {
+        let data = encode(CopyTextOptions::csv(), &sample_rows());
+        assert_eq!(
+            data,
+            "1,plain\n\
+             2,\n\
+             3,\"\"\n\
+             4,\"tab\there\nnewline\rcr\\backslash\"\n\
+             5,\"comma, \"\"quote\"\"\"\n\
+             6,\\N\n\
+             7,\"\\.\"\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_custom_options() {
+        let options = CopyTextOptions {
+            delimiter: b'|',
+            null: "NULL".into(),
+            ..CopyTextOptions::text()
+        };
+        let data = encode(options, &[(1, Some("a|b")), (2, None)]);
+        assert_eq!(data, "1|a\\|b\n2|NULL\n");
+
+        let options = CopyTextOptions {
+            delimiter: b';',
+            escape: b'\\',
+            ..CopyTextOptions::csv()
+        };
+        let data = encode(options, &[(1, Some("a;\"b\\"))]);
+        assert_eq!(data, "1;\"a;\\\"b\\\\\"\n");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for options in [CopyTextOptions::text(), CopyTextOptions::csv()] {
+            let data = encode(options.clone(), &sample_rows());
+            assert_eq!(decode(options, &data), sample_rows());
+        }
+
+        let options = CopyTextOptions {
+            delimiter: b';',
+            escape: b'\\',
+            null: "NULL".into(),
+            ..CopyTextOptions::csv()
+        };
+        let mut rows = sample_rows();
+        rows.push((8, Some("NULL".to_string())));
+        let data = encode(options.clone(), &rows);
+        assert_eq!(decode(options, &data), rows);
+    }
+
+    #[test]
+    fn test_text_value_equal_to_null() {
+        let options = CopyTextOptions {
+            null: "NULL".into(),
+            ..CopyTextOptions::text()
+        };
+        let rows = vec![
+            (1, Some("NULL".to_string())),
+            (2, None),
+            (3, Some("NULLS".to_string())),
+        ];
+        let data = encode(options.clone(), &rows);
+        assert_eq!(data, "1\t\\116ULL\n2\tNULL\n3\tNULLS\n");
+        assert_eq!(decode(options, &data), rows);
+
+        // A NULL string that is itself an escaped spelling
+        let options = CopyTextOptions {
+            null: "a\\tb".into(),
+            ..CopyTextOptions::text()
+        };
+        let rows = vec![(1, Some("a\tb".to_string())), (2, None)];
+        let data = encode(options.clone(), &rows);
+        assert_eq!(data, "1\t\\141\\tb\n2\ta\\tb\n");
+        assert_eq!(decode(options, &data), rows);
+    }
+
+    #[test]
+    fn test_text_empty_null() {
+        let options = CopyTextOptions {
+            null: String::new(),
+            ..CopyTextOptions::text()
+        };
+        assert!(options.validate().is_err());
+        assert!(CopyTextOptions::text().validate().is_ok());
+        assert!(CopyTextOptions::csv().validate().is_ok());
+
+        let mut encoder = CopyTextEncoder::new(options);
+        let mut buf = Vec::new();
+        encoder.write_row(&(1, Some("a")), &mut buf).unwrap();
+        assert!(matches!(
+            encoder.write_row(&(2, Some("")), &mut buf),
+            Err(Error::InvalidUsage(_))
+        ));
+        assert_eq!(buf, b"1\ta\n");
+    }
+
+    #[test]
+    fn test_decode_text_escapes() {
+        let rows = decode(CopyTextOptions::text(), "1\t\\101\\x42\\b\\q\n");
+        assert_eq!(rows, vec![(1, Some("AB\u{8}q".to_string()))]);
+    }
+
+    #[test]
+    fn test_decode_field_count_mismatch() {
+        let mut decoder = CopyTextDecoder::new(CopyTextOptions::text(), &[oid::INT4]);
+        decoder.push(b"1\t2\n");
+        assert!(decoder.next_row::<(i32,)>().is_err());
+    }
+
+    #[test]
+    fn test_encode_bytea() {
+        let data = encode(CopyTextOptions::text(), &[(vec![0x00_u8, 0xab, 0xff],)]);
+        assert_eq!(data, "\\\\x00abff\n");
+    }
+}
@@ -3,6 +3,8 @@
 use std::collections::HashMap;
 use thiserror::Error;
 
+use crate::protocol::frontend::auth::OAuthDiscovery;
+
 /// Result type for zero-postgres operations.
 pub type Result<T> = core::result::Result<T, Error>;
 
@@ -193,10 +195,35 @@ pub enum Error {
     #[error("TLS error: {0}")]
     Tls(#[from] native_tls::Error),
 
+    /// TLS error from rustls
+    #[cfg(any(feature = "sync-rustls", feature = "tokio-rustls"))]
+    #[error("TLS error: {0}")]
+    Rustls(#[from] rustls::Error),
+
     /// Connection is broken and cannot be reused
     #[error("Connection is broken")]
     ConnectionBroken,
 
+    /// OAuth bearer token missing or rejected, with the server's discovery information
+    #[error("OAuth bearer authentication failed: {0}")]
+    OAuthBearer(OAuthDiscovery),
+
+    /// Server does not satisfy `target_session_attrs`
+    #[error("Server does not match target_session_attrs: {0}")]
+    SessionAttrsMismatch(String),
+
+    /// No pooled connection became available before the `get_timeout` deadline
+    #[error("Pool exhausted: timed out waiting for a connection")]
+    PoolExhausted,
+
+    /// The pool was closed with `Pool::close`
+    #[error("Pool closed")]
+    PoolClosed,
+
+    /// Query did not complete within the query timeout and was cancelled
+    #[error("Query timed out")]
+    Timeout,
+
     /// Invalid usage (e.g., nested transactions)
     #[error("Invalid usage: {0}")]
     InvalidUsage(String),
@@ -37,11 +37,26 @@ mod buffer_pool;
 mod buffer_set;
 mod error;
 mod opts;
+mod password;
+#[cfg(any(feature = "sync", feature = "tokio"))]
+mod pgpass;
 mod pipeline;
+#[cfg(any(feature = "sync", feature = "tokio"))]
+mod pool_stats;
+mod scram_cache;
+mod service;
 mod statement;
+#[cfg(any(
+    feature = "sync-tls",
+    feature = "tokio-tls",
+    feature = "sync-rustls",
+    feature = "tokio-rustls"
+))]
+mod tls;
 
 // pub
 pub mod conversion;
+pub mod copy;
 pub mod handler;
 pub mod protocol;
 pub mod state;
@@ -56,8 +71,16 @@ pub use buffer_pool::BufferPool;
 pub use buffer_set::BufferSet;
 pub use error::{Error, Result, ServerError};
 pub use handler::AsyncMessageHandler;
-pub use opts::{Opts, SslMode};
+pub use opts::{
+    AuthMethod, ChannelBinding, LoadBalanceHosts, Opts, PoolResetFn, PoolResetPolicy,
+    ProtocolVersion, RequireAuth, SslMode, SslNegotiation, TargetSessionAttrs,
+};
+pub use password::{AsyncPasswordProvider, OAuthTokenProvider, PasswordFuture, PasswordProvider};
 pub use pipeline::Ticket;
+#[cfg(any(feature = "sync", feature = "tokio"))]
+pub use pool_stats::{PoolStats, WaitTimeHistogram};
+pub use protocol::frontend::auth::OAuthDiscovery;
+pub use scram_cache::ScramKeyCache;
 pub use state::action::AsyncMessage;
 pub use state::extended::PreparedStatement;
 pub use statement::IntoStatement;
@@ -1,22 +1,276 @@
 //! Connection options.
 
+#[cfg(any(feature = "sync", feature = "tokio"))]
+use std::borrow::Cow;
 use std::sync::Arc;
+use std::time::Duration;
 
 use url::Url;
 
 use crate::buffer_pool::{BufferPool, GLOBAL_BUFFER_POOL};
 use crate::error::Error;
+use crate::password::{AsyncPasswordProvider, OAuthTokenProvider, PasswordProvider};
+use crate::protocol::frontend::startup::{PROTOCOL_VERSION_3_0, PROTOCOL_VERSION_3_2};
+use crate::protocol::types::TransactionStatus;
+use crate::scram_cache::{GLOBAL_SCRAM_KEY_CACHE, ScramKeyCache};
+use crate::service;
 
 /// SSL connection mode.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum SslMode {
     /// Don't use SSL
     Disable,
+    /// Try an unencrypted connection first, fall back to SSL if the server rejects it
+    Allow,
     /// Try SSL, fall back to unencrypted if not supported
     #[default]
     Prefer,
     /// Require SSL connection
     Require,
+    /// Require SSL and verify the server certificate against trusted root certificates
+    VerifyCa,
+    /// Like `VerifyCa`, and also verify that the certificate matches the host name
+    VerifyFull,
+}
+
+impl SslMode {
+    /// Returns true if the connection must not fall back to unencrypted.
+    pub fn requires_tls(self) -> bool {
+        matches!(
+            self,
+            SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull
+        )
+    }
+}
+
+/// How TLS is negotiated with the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SslNegotiation {
+    /// Send an SSLRequest and start TLS if the server accepts it
+    #[default]
+    Postgres,
+    /// Start the TLS handshake immediately with ALPN `postgresql` (PostgreSQL 17+)
+    ///
+    /// Saves a round trip and works with TLS-terminating proxies. Requires an
+    /// `SslMode` that requires TLS.
+    Direct,
+}
+
+/// Channel binding mode for SCRAM authentication.
+///
+/// Channel binding ties the authentication to the TLS connection
+/// (`SCRAM-SHA-256-PLUS` with `tls-server-end-point`), proving that the server
+/// holding the password verifier is the one that presented the certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelBinding {
+    /// Never use channel binding
+    Disable,
+    /// Use channel binding if the connection is TLS and the server supports it
+    #[default]
+    Prefer,
+    /// Fail unless the server authenticates with channel binding
+    Require,
+}
+
+/// Authentication method requested by the server, for [`RequireAuth`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuthMethod {
+    /// No authentication (`none`)
+    None,
+    /// Cleartext password (`password`)
+    Password,
+    /// MD5-hashed password (`md5`)
+    Md5,
+    /// SCRAM-SHA-256, with or without channel binding (`scram-sha-256`)
+    ScramSha256,
+    /// OAuth bearer token (`oauth`)
+    OAuth,
+}
+
+impl AuthMethod {
+    /// Get the libpq name of the method.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuthMethod::None => "none",
+            AuthMethod::Password => "password",
+            AuthMethod::Md5 => "md5",
+            AuthMethod::ScramSha256 => "scram-sha-256",
+            AuthMethod::OAuth => "oauth",
+        }
+    }
+}
+
+/// Authentication methods the client accepts from the server.
+///
+/// Parsed from a libpq `require_auth` list such as `scram-sha-256,none`, or a list
+/// of negated methods such as `!password,!md5`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum RequireAuth {
+    /// Any authentication method
+    #[default]
+    Any,
+    /// Only the listed methods
+    Only(Vec<AuthMethod>),
+    /// Any method except the listed ones
+    Except(Vec<AuthMethod>),
+}
+
+impl RequireAuth {
+    /// Returns true if the server may authenticate the client with `method`.
+    pub fn allows(&self, method: AuthMethod) -> bool {
+        match self {
+            RequireAuth::Any => true,
+            RequireAuth::Only(methods) => methods.contains(&method),
+            RequireAuth::Except(methods) => !methods.contains(&method),
+        }
+    }
+}
+
+impl std::fmt::Display for RequireAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (methods, prefix) = match self {
+            RequireAuth::Any => return Ok(()),
+            RequireAuth::Only(methods) => (methods, ""),
+            RequireAuth::Except(methods) => (methods, "!"),
+        };
+        for (i, method) in methods.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}{}", prefix, method.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// Frontend/backend protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProtocolVersion {
+    /// Protocol 3.0, supported by all PostgreSQL versions
+    V3_0,
+    /// Protocol 3.2 (PostgreSQL 18+), with variable-length cancel keys
+    V3_2,
+}
+
+impl ProtocolVersion {
+    /// Get the minor version number.
+    pub fn minor(self) -> u32 {
+        match self {
+            ProtocolVersion::V3_0 => 0,
+            ProtocolVersion::V3_2 => 2,
+        }
+    }
+
+    /// Get the version from a minor version number.
+    pub fn from_minor(minor: u32) -> Option<Self> {
+        match minor {
+            0 => Some(ProtocolVersion::V3_0),
+            2 => Some(ProtocolVersion::V3_2),
+            _ => None,
+        }
+    }
+
+    /// Get the version code sent in the StartupMessage.
+    pub fn code(self) -> i32 {
+        match self {
+            ProtocolVersion::V3_0 => PROTOCOL_VERSION_3_0,
+            ProtocolVersion::V3_2 => PROTOCOL_VERSION_3_2,
+        }
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "3.{}", self.minor())
+    }
+}
+
+/// Order in which multiple hosts are tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoadBalanceHosts {
+    /// Try hosts in the order they are listed
+    #[default]
+    Disable,
+    /// Try hosts in random order
+    Random,
+}
+
+/// Required properties of the server session, checked after connecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TargetSessionAttrs {
+    /// Any server is acceptable
+    #[default]
+    Any,
+    /// The session must accept read-write transactions by default
+    ReadWrite,
+    /// The session must not accept read-write transactions by default
+    ReadOnly,
+    /// The server must not be in hot standby mode
+    Primary,
+    /// The server must be in hot standby mode
+    Standby,
+    /// Prefer a server in hot standby mode, fall back to any server
+    PreferStandby,
+}
+
+/// SQL run on the transaction status of a connection returned to the pool.
+pub type PoolResetFn = dyn Fn(TransactionStatus) -> Option<String> + Send + Sync;
+
+/// How a connection is reset when it is returned to the pool.
+///
+/// A connection returned inside a transaction is rolled back first, except with
+/// `None` and `Custom`. A connection still in a transaction after the reset is
+/// closed instead of being reused.
+///
+/// A connection that was not used since it was checked out (never borrowed as
+/// `&mut Conn`, which every query needs) is not reset, saving the round trip.
+///
+/// After a reset statement runs, the pool's `after_connect` hook runs again to
+/// restore the session state it sets up.
+#[derive(Clone, Default)]
+pub enum PoolResetPolicy {
+    /// Don't reset; session state and prepared statements are kept
+    None,
+    /// `DISCARD ALL`: reset all session state and close prepared statements
+    #[default]
+    DiscardAll,
+    /// `RESET ALL`: reset run-time parameters, keeping prepared statements
+    ResetAll,
+    /// `ROLLBACK` only if the connection is in a transaction
+    RollbackIfInTx,
+    /// Run the SQL returned for the transaction status, if any
+    Custom(Arc<PoolResetFn>),
+}
+
+impl PoolResetPolicy {
+    /// Returns true if an open transaction is rolled back before the reset.
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    pub(crate) fn rolls_back(&self, status: TransactionStatus) -> bool {
+        status.in_transaction() && !matches!(self, Self::None | Self::Custom(_))
+    }
+
+    /// Get the reset statement for a connection returned with the transaction status.
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    pub(crate) fn statement(&self, status: TransactionStatus) -> Option<Cow<'static, str>> {
+        match self {
+            Self::None | Self::RollbackIfInTx => None,
+            Self::DiscardAll => Some(Cow::Borrowed("DISCARD ALL")),
+            Self::ResetAll => Some(Cow::Borrowed("RESET ALL")),
+            Self::Custom(reset) => reset(status).map(Cow::Owned),
+        }
+    }
+}
+
+impl std::fmt::Debug for PoolResetPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => f.write_str("None"),
+            Self::DiscardAll => f.write_str("DiscardAll"),
+            Self::ResetAll => f.write_str("ResetAll"),
+            Self::RollbackIfInTx => f.write_str("RollbackIfInTx"),
+            Self::Custom(_) => f.write_str("Custom"),
+        }
+    }
 }
 
 /// Connection options for PostgreSQL.
@@ -37,6 +291,23 @@ pub struct Opts {
     /// Default: `None`
     pub socket: Option<String>,
 
+    /// Hosts to try in order, as `(host, port)` pairs. When empty, `host`/`port` is used.
+    ///
+    /// A host starting with `/` is a Unix socket directory.
+    ///
+    /// Default: `[]`
+    pub hosts: Vec<(String, u16)>,
+
+    /// Order in which `hosts` are tried.
+    ///
+    /// Default: `LoadBalanceHosts::Disable`
+    pub load_balance_hosts: LoadBalanceHosts,
+
+    /// Required properties of the server session. Hosts that don't match are skipped.
+    ///
+    /// Default: `TargetSessionAttrs::Any`
+    pub target_session_attrs: TargetSessionAttrs,
+
     /// Username for authentication.
     ///
     /// Default: `""`
@@ -52,6 +323,31 @@ pub struct Opts {
     /// Default: `None`
     pub password: Option<String>,
 
+    /// Provider called for the password each time a new connection authenticates,
+    /// for short-lived tokens and rotating secrets. Takes precedence over `password`.
+    ///
+    /// Default: `None`
+    pub password_provider: Option<Arc<dyn PasswordProvider>>,
+
+    /// Asynchronous password provider for the tokio client. Takes precedence over
+    /// `password_provider` and `password`. The sync client can't use it.
+    ///
+    /// Default: `None`
+    pub async_password_provider: Option<Arc<dyn AsyncPasswordProvider>>,
+
+    /// Provider of the bearer token for OAuth authentication (PostgreSQL 18+).
+    /// Required when the server requests `OAUTHBEARER`.
+    ///
+    /// Default: `None`
+    pub oauth_token_provider: Option<Arc<dyn OAuthTokenProvider>>,
+
+    /// Password file to look up the password in when `password` is `None`.
+    ///
+    /// When `None`, `PGPASSFILE` or `~/.pgpass` is used.
+    ///
+    /// Default: `None`
+    pub passfile: Option<String>,
+
     /// Application name to report to the server.
     ///
     /// Default: `None`
@@ -62,6 +358,66 @@ pub struct Opts {
     /// Default: `SslMode::Prefer`
     pub ssl_mode: SslMode,
 
+    /// How TLS is negotiated when `ssl_mode` requests it.
+    ///
+    /// Default: `SslNegotiation::Postgres`
+    pub ssl_negotiation: SslNegotiation,
+
+    /// Path to PEM root certificates used to verify the server certificate.
+    ///
+    /// `"system"` uses the system's trusted roots. When set, `Require` verifies the
+    /// certificate chain like `VerifyCa`. When `None`, verify modes use the system roots.
+    ///
+    /// Default: `None`
+    pub ssl_root_cert: Option<String>,
+
+    /// Path to the PEM client certificate, for servers that require mutual TLS.
+    ///
+    /// Default: `None`
+    pub ssl_cert: Option<String>,
+
+    /// Path to the PEM (PKCS#8) private key of `ssl_cert`.
+    ///
+    /// Default: `None`
+    pub ssl_key: Option<String>,
+
+    /// Path to a PEM certificate revocation list checked during verification.
+    ///
+    /// Default: `None`
+    pub ssl_crl: Option<String>,
+
+    /// Authentication methods accepted from the server.
+    ///
+    /// Authentication requests for other methods fail the connection before the
+    /// password is sent, so a spoofed server cannot downgrade the authentication.
+    ///
+    /// Default: `RequireAuth::Any`
+    pub require_auth: RequireAuth,
+
+    /// Channel binding mode for SCRAM authentication.
+    ///
+    /// Default: `ChannelBinding::Prefer`
+    pub channel_binding: ChannelBinding,
+
+    /// rustls client configuration, used instead of one built from `ssl_mode` and the
+    /// certificate options. `ssl_mode` still decides whether TLS is requested.
+    ///
+    /// Default: `None`
+    #[cfg(any(feature = "sync-rustls", feature = "tokio-rustls"))]
+    pub rustls_config: Option<Arc<rustls::ClientConfig>>,
+
+    /// Minimum protocol version to accept when the server negotiates a lower version.
+    ///
+    /// Default: `ProtocolVersion::V3_0`
+    pub min_protocol_version: ProtocolVersion,
+
+    /// Protocol version requested in the StartupMessage.
+    ///
+    /// Servers older than PostgreSQL 18 negotiate down to 3.0.
+    ///
+    /// Default: `ProtocolVersion::V3_2`
+    pub max_protocol_version: ProtocolVersion,
+
     /// Additional connection parameters.
     ///
     /// Default: `[]`
@@ -72,6 +428,55 @@ pub struct Opts {
     /// Default: `true`
     pub prefer_unix_socket: bool,
 
+    /// Timeout for establishing a connection: the TCP connection, per resolved
+    /// address, and then the SSL negotiation and startup.
+    ///
+    /// Default: `None`
+    pub connect_timeout: Option<Duration>,
+
+    /// Enable TCP keepalive probes.
+    ///
+    /// Default: `true`
+    pub keepalives: bool,
+
+    /// Idle time before the first keepalive probe (None = system default).
+    ///
+    /// Default: `None`
+    pub keepalives_idle: Option<Duration>,
+
+    /// Interval between keepalive probes (None = system default).
+    ///
+    /// Default: `None`
+    pub keepalives_interval: Option<Duration>,
+
+    /// Number of unanswered keepalive probes before the connection is dropped (None = system default).
+    ///
+    /// Default: `None`
+    pub keepalives_count: Option<u32>,
+
+    /// Maximum time transmitted data may remain unacknowledged before the connection
+    /// is dropped (`TCP_USER_TIMEOUT`, Linux only).
+    ///
+    /// Default: `None`
+    pub tcp_user_timeout: Option<Duration>,
+
+    /// Socket read timeout for the sync client. A read that times out breaks the connection.
+    ///
+    /// Default: `None`
+    pub read_timeout: Option<Duration>,
+
+    /// Socket write timeout for the sync client. A write that times out breaks the connection.
+    ///
+    /// Default: `None`
+    pub write_timeout: Option<Duration>,
+
+    /// Timeout for queries on connections created with these options.
+    ///
+    /// When a query runs longer, it is cancelled on the server and `Error::Timeout` is returned.
+    ///
+    /// Default: `None`
+    pub query_timeout: Option<Duration>,
+
     /// Maximum number of idle connections in the pool.
     ///
     /// Default: `100`
@@ -82,10 +487,38 @@ pub struct Opts {
     /// Default: `None`
     pub pool_max_concurrency: Option<usize>,
 
+    /// Maximum lifetime of a pooled connection. Older connections are closed
+    /// instead of being reused.
+    ///
+    /// Default: `None`
+    pub pool_max_lifetime: Option<Duration>,
+
+    /// Time after which an idle pooled connection is closed.
+    ///
+    /// Default: `None`
+    pub pool_idle_timeout: Option<Duration>,
+
+    /// Number of idle connections the pool keeps open, replacing connections
+    /// closed by `pool_max_lifetime` and `pool_idle_timeout`.
+    ///
+    /// Default: `0`
+    pub pool_min_idle: usize,
+
+    /// How connections are reset when returned to the pool.
+    ///
+    /// Default: `PoolResetPolicy::DiscardAll`
+    pub pool_reset_policy: PoolResetPolicy,
+
     /// Buffer pool for reusing buffers across connections.
     ///
     /// Default: `GLOBAL_BUFFER_POOL`
     pub buffer_pool: Arc<BufferPool>,
+
+    /// Cache of SCRAM-SHA-256 keys, which skips the PBKDF2 derivation when
+    /// reconnecting with the same password.
+    ///
+    /// Default: `GLOBAL_SCRAM_KEY_CACHE`
+    pub scram_key_cache: Arc<ScramKeyCache>,
 }
 
 impl Default for Opts {
@@ -94,20 +527,488 @@ impl Default for Opts {
             host: String::new(),
             port: 5432,
             socket: None,
+            hosts: Vec::new(),
+            load_balance_hosts: LoadBalanceHosts::Disable,
+            target_session_attrs: TargetSessionAttrs::Any,
             user: String::new(),
             database: None,
             password: None,
+            password_provider: None,
+            async_password_provider: None,
+            oauth_token_provider: None,
+            passfile: None,
             application_name: None,
             ssl_mode: SslMode::Prefer,
+            ssl_negotiation: SslNegotiation::Postgres,
+            ssl_root_cert: None,
+            ssl_cert: None,
+            ssl_key: None,
+            ssl_crl: None,
+            require_auth: RequireAuth::Any,
+            channel_binding: ChannelBinding::Prefer,
+            #[cfg(any(feature = "sync-rustls", feature = "tokio-rustls"))]
+            rustls_config: None,
+            min_protocol_version: ProtocolVersion::V3_0,
+            max_protocol_version: ProtocolVersion::V3_2,
             params: Vec::new(),
             prefer_unix_socket: true,
+            connect_timeout: None,
+            keepalives: true,
+            keepalives_idle: None,
+            keepalives_interval: None,
+            keepalives_count: None,
+            tcp_user_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            query_timeout: None,
             pool_max_idle_conn: 100,
             pool_max_concurrency: None,
+            pool_max_lifetime: None,
+            pool_idle_timeout: None,
+            pool_min_idle: 0,
+            pool_reset_policy: PoolResetPolicy::DiscardAll,
             buffer_pool: Arc::clone(&GLOBAL_BUFFER_POOL),
+            scram_key_cache: Arc::clone(&GLOBAL_SCRAM_KEY_CACHE),
         }
     }
 }
 
+impl Opts {
+    /// Options for each host to try, with `host`/`port`/`socket` set to that host.
+    ///
+    /// Hosts are shuffled if `load_balance_hosts` is `Random`.
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    pub(crate) fn host_candidates(&self) -> Vec<Opts> {
+        if self.hosts.is_empty() || self.socket.is_some() {
+            return vec![self.clone()];
+        }
+
+        let mut candidates: Vec<Opts> = self
+            .hosts
+            .iter()
+            .map(|(host, port)| {
+                let mut opts = self.clone();
+                opts.hosts = Vec::new();
+                opts.port = *port;
+                if host.starts_with('/') {
+                    opts.socket = Some(format!("{}/.s.PGSQL.{}", host, port));
+                    opts.host = "localhost".into();
+                } else {
+                    opts.host = host.clone();
+                }
+                opts
+            })
+            .collect();
+
+        if self.load_balance_hosts == LoadBalanceHosts::Random {
+            use rand::seq::SliceRandom;
+            candidates.shuffle(&mut rand::rng());
+        }
+        candidates
+    }
+
+    /// Set `host`/`port` and `hosts` from libpq-style comma-separated lists.
+    ///
+    /// `ports` must contain either one port for all hosts or one port per host.
+    pub(crate) fn set_hosts(&mut self, hosts: &str, ports: &str) -> Result<(), Error> {
+        let hosts: Vec<&str> = hosts.split(',').map(str::trim).collect();
+        let ports = ports
+            .split(',')
+            .map(|port| match port.trim() {
+                "" => Ok(5432),
+                port => port
+                    .parse::<u16>()
+                    .map_err(|e| Error::InvalidUsage(format!("Invalid port: {}: {}", port, e))),
+            })
+            .collect::<Result<Vec<u16>, Error>>()?;
+
+        if ports.len() != 1 && ports.len() != hosts.len() {
+            return Err(Error::InvalidUsage(format!(
+                "could not match {} port numbers to {} hosts",
+                ports.len(),
+                hosts.len()
+            )));
+        }
+
+        let pairs: Vec<(String, u16)> = hosts
+            .iter()
+            .enumerate()
+            .map(|(i, host)| {
+                let host = if host.is_empty() { "localhost" } else { host };
+                let port = ports.get(i).copied().unwrap_or(ports[0]);
+                (host.to_string(), port)
+            })
+            .collect();
+
+        self.host = pairs[0].0.clone();
+        self.port = pairs[0].1;
+        self.hosts = if pairs.len() > 1 { pairs } else { Vec::new() };
+        self.socket = (self.hosts.is_empty() && self.host.starts_with('/'))
+            .then(|| format!("{}/.s.PGSQL.{}", self.host, self.port));
+        Ok(())
+    }
+
+    /// Apply nodelay, keepalive and `TCP_USER_TIMEOUT` settings to a connected TCP socket.
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    pub(crate) fn configure_tcp(&self, socket: socket2::SockRef<'_>) -> std::io::Result<()> {
+        socket.set_tcp_nodelay(true)?;
+
+        if self.keepalives {
+            let mut keepalive = socket2::TcpKeepalive::new();
+            if let Some(idle) = self.keepalives_idle {
+                keepalive = keepalive.with_time(idle);
+            }
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "freebsd",
+                target_os = "netbsd"
+            ))]
+            {
+                if let Some(interval) = self.keepalives_interval {
+                    keepalive = keepalive.with_interval(interval);
+                }
+                if let Some(count) = self.keepalives_count {
+                    keepalive = keepalive.with_retries(count);
+                }
+            }
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if self.tcp_user_timeout.is_some() {
+            socket.set_tcp_user_timeout(self.tcp_user_timeout)?;
+        }
+
+        Ok(())
+    }
+
+    /// Build options from libpq environment variables.
+    ///
+    /// Supported variables: `PGHOST`, `PGPORT`, `PGUSER`, `PGPASSWORD`, `PGDATABASE`,
+    /// `PGSSLMODE`, `PGSSLNEGOTIATION`, `PGSSLROOTCERT`, `PGSSLCERT`, `PGSSLKEY`, `PGSSLCRL`, `PGREQUIREAUTH`, `PGCHANNELBINDING`, `PGAPPNAME`, `PGOPTIONS`, `PGCONNECT_TIMEOUT`, `PGTARGETSESSIONATTRS`,
+    /// `PGMINPROTOCOLVERSION`, `PGMAXPROTOCOLVERSION`, `PGLOADBALANCEHOSTS` and `PGPASSFILE`. Unset variables keep their defaults.
+    ///
+    /// `PGSERVICE` names a service in the service file (`PGSERVICEFILE`), whose
+    /// settings take precedence over the other variables.
+    pub fn from_env() -> Result<Self, Error> {
+        let mut opts = Self::with_default_host();
+        opts.apply_env(|name| std::env::var(name).ok())?;
+        Ok(opts)
+    }
+
+    /// Parse a URL or conninfo string, using libpq environment variables for
+    /// settings the string does not specify.
+    ///
+    /// This matches how psql and other libpq tools combine a connection string with
+    /// the environment.
+    pub fn from_env_with(s: &str) -> Result<Self, Error> {
+        let mut opts = Self::from_env()?;
+        opts.apply_str(s)?;
+        Ok(opts)
+    }
+
+    /// Interval of the pool reaper, or `None` if the pool options need no reaper.
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    pub(crate) fn pool_reaper_interval(&self) -> Option<Duration> {
+        const MIN_INTERVAL: Duration = Duration::from_millis(10);
+        const MAX_INTERVAL: Duration = Duration::from_secs(1);
+
+        let shortest = [self.pool_max_lifetime, self.pool_idle_timeout]
+            .into_iter()
+            .flatten()
+            .min();
+        match shortest {
+            Some(limit) => Some((limit / 2).clamp(MIN_INTERVAL, MAX_INTERVAL)),
+            None => (self.pool_min_idle > 0).then_some(MAX_INTERVAL),
+        }
+    }
+
+    /// Returns true if a password provider is set.
+    pub(crate) fn has_password_provider(&self) -> bool {
+        self.password_provider.is_some() || self.async_password_provider.is_some()
+    }
+
+    /// Default options connecting to `localhost`.
+    fn with_default_host() -> Self {
+        Opts {
+            host: "localhost".to_string(),
+            ..Opts::default()
+        }
+    }
+
+    /// Apply libpq environment variables looked up with `var`.
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), Error> {
+        const ENV_VARS: &[(&str, &str)] = &[
+            ("PGHOST", "host"),
+            ("PGPORT", "port"),
+            ("PGUSER", "user"),
+            ("PGPASSWORD", "password"),
+            ("PGDATABASE", "dbname"),
+            ("PGSSLMODE", "sslmode"),
+            ("PGSSLNEGOTIATION", "sslnegotiation"),
+            ("PGSSLROOTCERT", "sslrootcert"),
+            ("PGSSLCERT", "sslcert"),
+            ("PGSSLKEY", "sslkey"),
+            ("PGSSLCRL", "sslcrl"),
+            ("PGREQUIREAUTH", "require_auth"),
+            ("PGCHANNELBINDING", "channel_binding"),
+            ("PGAPPNAME", "application_name"),
+            ("PGOPTIONS", "options"),
+            ("PGCONNECT_TIMEOUT", "connect_timeout"),
+            ("PGTARGETSESSIONATTRS", "target_session_attrs"),
+            ("PGMINPROTOCOLVERSION", "min_protocol_version"),
+            ("PGMAXPROTOCOLVERSION", "max_protocol_version"),
+            ("PGLOADBALANCEHOSTS", "load_balance_hosts"),
+            ("PGPASSFILE", "passfile"),
+        ];
+
+        let params: Vec<(&str, String)> = ENV_VARS
+            .iter()
+            .filter_map(|(name, key)| var(name).map(|value| (*key, value)))
+            .collect();
+        self.apply_params(params)?;
+
+        if let Some(name) = var("PGSERVICE") {
+            self.apply_params(service::lookup(&name, None, &var)?)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the service named by the `service` parameter, if any.
+    ///
+    /// Service settings are applied before, and overridden by, the other parameters.
+    fn apply_service<K, V>(&mut self, params: &[(K, V)]) -> Result<(), Error>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let find = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key.as_ref() == name)
+                .map(|(_, value)| value.as_ref())
+        };
+        if let Some(name) = find("service") {
+            let service_params =
+                service::lookup(name, find("servicefile"), &|var| std::env::var(var).ok())?;
+            self.apply_params(service_params)?;
+        }
+        Ok(())
+    }
+
+    /// Apply a URL or a conninfo string on top of the current options.
+    ///
+    /// Like libpq, only a string starting with a URL scheme is parsed as a URL.
+    fn apply_str(&mut self, s: &str) -> Result<(), Error> {
+        let is_url = ["postgres://", "postgresql://", "pg://"]
+            .iter()
+            .any(|scheme| s.starts_with(scheme));
+        if is_url {
+            let rewritten = rewrite_multi_host_url(s);
+            let url = Url::parse(rewritten.as_deref().unwrap_or(s))
+                .map_err(|e| Error::InvalidUsage(format!("Invalid URL: {}", e)))?;
+            self.apply_url(&url)
+        } else {
+            let params = parse_conninfo(s)?;
+            self.apply_service(&params)?;
+            self.apply_params(params)
+        }
+    }
+
+    /// Apply a connection URL on top of the current options.
+    fn apply_url(&mut self, url: &Url) -> Result<(), Error> {
+        if !["postgres", "postgresql", "pg"].contains(&url.scheme()) {
+            return Err(Error::InvalidUsage(format!(
+                "Invalid scheme: expected 'postgres://', 'postgresql://', or 'pg://', got '{}://'",
+                url.scheme()
+            )));
+        }
+
+        let params: Vec<_> = url.query_pairs().collect();
+        self.apply_service(&params)?;
+
+        let host = url.host_str().filter(|host| !host.is_empty());
+        if host.is_some() || url.port().is_some() {
+            let hosts = host.map_or_else(|| self.host_list(), str::to_string);
+            let ports = url.port().unwrap_or(self.port).to_string();
+            self.set_hosts(&hosts, &ports)?;
+        }
+        if !url.username().is_empty() {
+            self.user = url.username().to_string();
+        }
+        if let Some(password) = url.password() {
+            self.password = Some(password.to_string());
+        }
+        if let Some(database) = url.path().strip_prefix('/').filter(|s| !s.is_empty()) {
+            self.database = Some(database.to_string());
+        }
+
+        self.apply_params(params)
+    }
+
+    /// Apply connection parameters, given as keyword/value pairs.
+    ///
+    /// `host` and `port` lists are combined after all parameters are applied.
+    fn apply_params<K, V>(&mut self, params: impl IntoIterator<Item = (K, V)>) -> Result<(), Error>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut host_list = None;
+        let mut port_list = None;
+
+        for (key, value) in params {
+            match key.as_ref() {
+                "host" => host_list = Some(value.as_ref().to_string()),
+                "port" => port_list = Some(value.as_ref().to_string()),
+                key => self.set_param(key, value.as_ref())?,
+            }
+        }
+
+        if host_list.is_some() || port_list.is_some() {
+            let hosts = host_list.unwrap_or_else(|| self.host_list());
+            let ports = port_list.unwrap_or_else(|| self.port.to_string());
+            self.set_hosts(&hosts, &ports)?;
+        }
+        Ok(())
+    }
+
+    /// Current hosts as a comma-separated list.
+    fn host_list(&self) -> String {
+        if self.hosts.is_empty() {
+            return self.host.clone();
+        }
+        let hosts: Vec<&str> = self.hosts.iter().map(|(host, _)| host.as_str()).collect();
+        hosts.join(",")
+    }
+
+    /// Apply a single connection parameter other than `host` and `port`.
+    ///
+    /// Unknown parameters are sent to the server as startup parameters.
+    fn set_param(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        match key {
+            "user" => {
+                self.user = value.to_string();
+            }
+            "password" => {
+                self.password = Some(value.to_string());
+            }
+            "dbname" => {
+                self.database = (!value.is_empty()).then(|| value.to_string());
+            }
+            "load_balance_hosts" => {
+                self.load_balance_hosts = value.parse()?;
+            }
+            "target_session_attrs" => {
+                self.target_session_attrs = value.parse()?;
+            }
+            "sslmode" => {
+                self.ssl_mode = value.parse()?;
+            }
+            "sslnegotiation" => {
+                self.ssl_negotiation = value.parse()?;
+            }
+            "sslrootcert" => {
+                self.ssl_root_cert = Some(value.to_string());
+            }
+            "sslcert" => {
+                self.ssl_cert = Some(value.to_string());
+            }
+            "sslkey" => {
+                self.ssl_key = Some(value.to_string());
+            }
+            "sslcrl" => {
+                self.ssl_crl = Some(value.to_string());
+            }
+            "require_auth" => {
+                self.require_auth = value.parse()?;
+            }
+            "channel_binding" => {
+                self.channel_binding = value.parse()?;
+            }
+            "min_protocol_version" => {
+                self.min_protocol_version = value.parse()?;
+            }
+            "max_protocol_version" => {
+                self.max_protocol_version = value.parse()?;
+            }
+            "application_name" => {
+                self.application_name = Some(value.to_string());
+            }
+            "prefer_unix_socket" => {
+                self.prefer_unix_socket = parse_bool(key, value)?;
+            }
+            "connect_timeout" => {
+                self.connect_timeout = parse_seconds(key, value)?;
+            }
+            "keepalives" => {
+                self.keepalives = parse_bool(key, value)?;
+            }
+            "keepalives_idle" => {
+                self.keepalives_idle = parse_seconds(key, value)?;
+            }
+            "keepalives_interval" => {
+                self.keepalives_interval = parse_seconds(key, value)?;
+            }
+            "keepalives_count" => {
+                self.keepalives_count = Some(value.parse().map_err(|e| {
+                    Error::InvalidUsage(format!("Invalid keepalives_count: {}: {}", value, e))
+                })?);
+            }
+            "tcp_user_timeout" => {
+                let millis: u64 = value.parse().map_err(|e| {
+                    Error::InvalidUsage(format!("Invalid tcp_user_timeout: {}: {}", value, e))
+                })?;
+                self.tcp_user_timeout = (millis > 0).then(|| Duration::from_millis(millis));
+            }
+            "read_timeout" => {
+                self.read_timeout = parse_seconds(key, value)?;
+            }
+            "write_timeout" => {
+                self.write_timeout = parse_seconds(key, value)?;
+            }
+            "pool_max_idle_conn" => {
+                self.pool_max_idle_conn = value.parse().map_err(|_| {
+                    Error::InvalidUsage(format!("Invalid pool_max_idle_conn: {}", value))
+                })?;
+            }
+            "passfile" => {
+                self.passfile = Some(value.to_string());
+            }
+            "service" | "servicefile" => {
+                // Applied by `apply_service`
+            }
+            "pool_max_concurrency" => {
+                self.pool_max_concurrency = Some(value.parse().map_err(|_| {
+                    Error::InvalidUsage(format!("Invalid pool_max_concurrency: {}", value))
+                })?);
+            }
+            "pool_max_lifetime" => {
+                self.pool_max_lifetime = parse_seconds(key, value)?;
+            }
+            "pool_idle_timeout" => {
+                self.pool_idle_timeout = parse_seconds(key, value)?;
+            }
+            "pool_min_idle" => {
+                self.pool_min_idle = value.parse().map_err(|e| {
+                    Error::InvalidUsage(format!("Invalid pool_min_idle: {}: {}", value, e))
+                })?;
+            }
+            "pool_reset_policy" => {
+                self.pool_reset_policy = value.parse()?;
+            }
+            _ => match self.params.iter_mut().find(|(k, _)| k == key) {
+                Some((_, v)) => *v = value.to_string(),
+                None => self.params.push((key.to_string(), value.to_string())),
+            },
+        }
+        Ok(())
+    }
+}
+
 impl TryFrom<&Url> for Opts {
     type Error = Error;
 
@@ -115,90 +1016,794 @@ impl TryFrom<&Url> for Opts {
     ///
     /// Format: `postgres://[user[:password]@]host[:port][/database][?param1=value1&param2=value2&..]`
     ///
+    /// Multiple hosts (`host1:port1,host2:port2`) are accepted when parsing from a string.
+    ///
     /// Supported query parameters:
-    /// - `sslmode`: disable, prefer, require
+    /// - `sslmode`: disable, allow, prefer, require, verify-ca, verify-full
+    /// - `sslrootcert`, `sslcert`, `sslkey`, `sslcrl`: TLS certificate, key and CRL paths
     /// - `application_name`: application name
     /// - `prefer_unix_socket`: true/True/1/yes/on or false/False/0/no/off
+    /// - `connect_timeout`: connect timeout in seconds (0 = none)
+    /// - `keepalives`: enable TCP keepalive (1 or 0)
+    /// - `keepalives_idle`, `keepalives_interval`: keepalive timing in seconds
+    /// - `keepalives_count`: number of keepalive probes
+    /// - `tcp_user_timeout`: `TCP_USER_TIMEOUT` in milliseconds (0 = system default)
+    /// - `read_timeout`, `write_timeout`: sync socket timeouts in seconds (0 = none)
+    /// - `host`: comma-separated hosts, overriding the host in the URL
+    /// - `port`: comma-separated ports, one for all hosts or one per host
+    /// - `user`, `password`, `dbname`: override the URL components
+    /// - `passfile`: password file, used when no password is given
+    /// - `service`, `servicefile`: service in the service file providing defaults
+    /// - `load_balance_hosts`: disable, random
+    /// - `target_session_attrs`: any, read-write, read-only, primary, standby, prefer-standby
     /// - `pool_max_idle_conn`: maximum idle connections (positive integer)
     /// - `pool_max_concurrency`: maximum concurrent connections (positive integer)
+    /// - `pool_max_lifetime`, `pool_idle_timeout`: pooled connection limits in seconds (0 = none)
+    /// - `pool_min_idle`: minimum idle connections (non-negative integer)
+    /// - `pool_reset_policy`: none, discard-all, reset-all, rollback-if-in-tx
+    ///
+    /// Other parameters, such as `options`, are sent to the server as startup parameters.
     fn try_from(url: &Url) -> Result<Self, Self::Error> {
-        if !["postgres", "postgresql", "pg"].contains(&url.scheme()) {
-            return Err(Error::InvalidUsage(format!(
-                "Invalid scheme: expected 'postgres://', 'postgresql://', or 'pg://', got '{}://'",
-                url.scheme()
-            )));
+        let mut opts = Self::with_default_host();
+        opts.apply_url(url)?;
+        Ok(opts)
+    }
+}
+
+impl std::str::FromStr for SslMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(SslMode::Disable),
+            "allow" => Ok(SslMode::Allow),
+            "prefer" => Ok(SslMode::Prefer),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            _ => Err(Error::InvalidUsage(format!(
+                "Invalid sslmode: expected one of ['disable', 'allow', 'prefer', 'require', \
+                 'verify-ca', 'verify-full'], got {}",
+                s
+            ))),
         }
+    }
+}
 
-        let mut opts = Opts {
-            host: url.host_str().unwrap_or("localhost").to_string(),
-            port: url.port().unwrap_or(5432),
-            user: url.username().to_string(),
-            password: url.password().map(|s| s.to_string()),
-            database: url.path().strip_prefix('/').and_then(|s| {
-                if s.is_empty() {
-                    None
-                } else {
-                    Some(s.to_string())
-                }
-            }),
-            ..Opts::default()
-        };
+impl std::str::FromStr for SslNegotiation {
+    type Err = Error;
 
-        for (key, value) in url.query_pairs() {
-            match key.as_ref() {
-                "sslmode" => {
-                    opts.ssl_mode = match value.as_ref() {
-                        "disable" => SslMode::Disable,
-                        "prefer" => SslMode::Prefer,
-                        "require" => SslMode::Require,
-                        _ => {
-                            return Err(Error::InvalidUsage(format!(
-                                "Invalid sslmode: expected one of ['disable', 'prefer', 'require'], got {}",
-                                value
-                            )));
-                        }
-                    };
-                }
-                "application_name" => {
-                    opts.application_name = Some(value.to_string());
-                }
-                "prefer_unix_socket" => {
-                    opts.prefer_unix_socket = match value.as_ref() {
-                        "true" | "True" | "1" | "yes" | "on" => true,
-                        "false" | "False" | "0" | "no" | "off" => false,
-                        _ => {
-                            return Err(Error::InvalidUsage(format!(
-                                "Invalid prefer_unix_socket: {}",
-                                value
-                            )));
-                        }
-                    };
-                }
-                "pool_max_idle_conn" => {
-                    opts.pool_max_idle_conn = value.parse().map_err(|_| {
-                        Error::InvalidUsage(format!("Invalid pool_max_idle_conn: {}", value))
-                    })?;
-                }
-                "pool_max_concurrency" => {
-                    opts.pool_max_concurrency = Some(value.parse().map_err(|_| {
-                        Error::InvalidUsage(format!("Invalid pool_max_concurrency: {}", value))
-                    })?);
-                }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "postgres" => Ok(SslNegotiation::Postgres),
+            "direct" => Ok(SslNegotiation::Direct),
+            _ => Err(Error::InvalidUsage(format!(
+                "Invalid sslnegotiation: expected one of ['postgres', 'direct'], got {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for AuthMethod {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(AuthMethod::None),
+            "password" => Ok(AuthMethod::Password),
+            "md5" => Ok(AuthMethod::Md5),
+            "scram-sha-256" => Ok(AuthMethod::ScramSha256),
+            "oauth" => Ok(AuthMethod::OAuth),
+            _ => Err(Error::InvalidUsage(format!(
+                "Invalid require_auth method: expected one of ['none', 'password', 'md5', \
+                 'scram-sha-256', 'oauth'], got {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for RequireAuth {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(RequireAuth::Any);
+        }
+
+        let negated = s.starts_with('!');
+        let mut methods = Vec::new();
+        for item in s.split(',') {
+            let name = match item.strip_prefix('!') {
+                Some(name) if negated => name,
+                None if !negated => item,
                 _ => {
-                    opts.params.push((key.to_string(), value.to_string()));
+                    return Err(Error::InvalidUsage(format!(
+                        "Invalid require_auth: negative and positive methods cannot be mixed: {}",
+                        s
+                    )));
                 }
+            };
+            let method: AuthMethod = name.parse()?;
+            if methods.contains(&method) {
+                return Err(Error::InvalidUsage(format!(
+                    "Invalid require_auth: method \"{}\" is listed more than once",
+                    name
+                )));
             }
+            methods.push(method);
         }
 
-        Ok(opts)
+        Ok(if negated {
+            RequireAuth::Except(methods)
+        } else {
+            RequireAuth::Only(methods)
+        })
+    }
+}
+
+impl std::str::FromStr for ChannelBinding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(ChannelBinding::Disable),
+            "prefer" => Ok(ChannelBinding::Prefer),
+            "require" => Ok(ChannelBinding::Require),
+            _ => Err(Error::InvalidUsage(format!(
+                "Invalid channel_binding: expected one of ['disable', 'prefer', 'require'], got {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for ProtocolVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "3.0" => Ok(ProtocolVersion::V3_0),
+            "3.2" | "latest" => Ok(ProtocolVersion::V3_2),
+            _ => Err(Error::InvalidUsage(format!(
+                "Invalid protocol version: expected one of ['3.0', '3.2', 'latest'], got {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for LoadBalanceHosts {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(LoadBalanceHosts::Disable),
+            "random" => Ok(LoadBalanceHosts::Random),
+            _ => Err(Error::InvalidUsage(format!(
+                "Invalid load_balance_hosts: expected one of ['disable', 'random'], got {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for TargetSessionAttrs {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(TargetSessionAttrs::Any),
+            "read-write" => Ok(TargetSessionAttrs::ReadWrite),
+            "read-only" => Ok(TargetSessionAttrs::ReadOnly),
+            "primary" => Ok(TargetSessionAttrs::Primary),
+            "standby" => Ok(TargetSessionAttrs::Standby),
+            "prefer-standby" => Ok(TargetSessionAttrs::PreferStandby),
+            _ => Err(Error::InvalidUsage(format!(
+                "Invalid target_session_attrs: expected one of ['any', 'read-write', 'read-only', \
+                 'primary', 'standby', 'prefer-standby'], got {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for PoolResetPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(PoolResetPolicy::None),
+            "discard-all" => Ok(PoolResetPolicy::DiscardAll),
+            "reset-all" => Ok(PoolResetPolicy::ResetAll),
+            "rollback-if-in-tx" => Ok(PoolResetPolicy::RollbackIfInTx),
+            _ => Err(Error::InvalidUsage(format!(
+                "Invalid pool_reset_policy: expected one of ['none', 'discard-all', 'reset-all', \
+                 'rollback-if-in-tx'], got {}",
+                s
+            ))),
+        }
     }
 }
 
+/// Parse a boolean URL parameter.
+fn parse_bool(key: &str, value: &str) -> Result<bool, Error> {
+    match value {
+        "true" | "True" | "1" | "yes" | "on" => Ok(true),
+        "false" | "False" | "0" | "no" | "off" => Ok(false),
+        _ => Err(Error::InvalidUsage(format!("Invalid {}: {}", key, value))),
+    }
+}
+
+/// Parse a duration URL parameter given in whole seconds, where 0 means no timeout.
+fn parse_seconds(key: &str, value: &str) -> Result<Option<Duration>, Error> {
+    let secs: u64 = value
+        .parse()
+        .map_err(|e| Error::InvalidUsage(format!("Invalid {}: {}: {}", key, value, e)))?;
+    Ok((secs > 0).then(|| Duration::from_secs(secs)))
+}
+
 impl TryFrom<&str> for Opts {
     type Error = Error;
 
+    /// Parse a connection URL (`postgres://...`) or a libpq conninfo string
+    /// (`host=localhost port=5432 dbname=app`).
+    ///
+    /// Conninfo strings accept the same keywords as URL query parameters, plus
+    /// `host`, `port`, `user`, `password` and `dbname`. Values containing spaces
+    /// are quoted with `'`, and `\` escapes the next character.
     fn try_from(s: &str) -> Result<Self, Self::Error> {
-        let url = Url::parse(s).map_err(|e| Error::InvalidUsage(format!("Invalid URL: {}", e)))?;
-        Self::try_from(&url)
+        let mut opts = Self::with_default_host();
+        opts.apply_str(s)?;
+        Ok(opts)
+    }
+}
+
+/// Parse a libpq conninfo string into keyword/value pairs.
+fn parse_conninfo(s: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut params = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && !c.is_whitespace()) {
+            key.push(c);
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.next() != Some('=') {
+            return Err(Error::InvalidUsage(format!(
+                "missing \"=\" after \"{}\" in connection info string",
+                key
+            )));
+        }
+        if key.is_empty() {
+            return Err(Error::InvalidUsage(
+                "missing keyword before \"=\" in connection info string".into(),
+            ));
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'\'').is_some() {
+            loop {
+                match chars.next() {
+                    Some('\'') => break,
+                    Some('\\') => value.extend(chars.next()),
+                    Some(c) => value.push(c),
+                    None => {
+                        return Err(Error::InvalidUsage(
+                            "unterminated quoted string in connection info string".into(),
+                        ));
+                    }
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                if c == '\\' {
+                    value.extend(chars.next());
+                } else {
+                    value.push(c);
+                }
+            }
+        }
+
+        params.push((key, value));
+    }
+
+    Ok(params)
+}
+
+/// Move a libpq-style multi-host authority (`host1:port1,host2:port2`) into
+/// `host` and `port` query parameters, which [`Url`] can parse.
+///
+/// Explicit `host`/`port` query parameters come later in the query and take precedence.
+fn rewrite_multi_host_url(s: &str) -> Option<String> {
+    let (scheme, rest) = s.split_once("://")?;
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, tail) = rest.split_at(end);
+    let (userinfo, hostspec) = match authority.rfind('@') {
+        Some(i) => authority.split_at(i + 1),
+        None => ("", authority),
+    };
+    if !hostspec.contains(',') {
+        return None;
+    }
+
+    let mut hosts = Vec::new();
+    let mut ports = Vec::new();
+    for entry in hostspec.split(',') {
+        let (host, port) = match entry.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (host, port),
+            _ => (entry, ""),
+        };
+        hosts.push(host.trim_start_matches('[').trim_end_matches(']'));
+        ports.push(port);
+    }
+
+    let (path, query) = match tail.split_once('?') {
+        Some((path, query)) => (path, format!("&{}", query)),
+        None => (tail, String::new()),
+    };
+    Some(format!(
+        "{}://{}localhost{}?host={}&port={}{}",
+        scheme,
+        userinfo,
+        path,
+        hosts.join(","),
+        ports.join(","),
+        query
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_socket_params() {
+        let opts = Opts::try_from(
+            "postgres://user@db:5433/app?connect_timeout=10&keepalives=0&keepalives_idle=30\
+             &keepalives_interval=5&keepalives_count=3&tcp_user_timeout=1500\
+             &read_timeout=60&write_timeout=0",
+        )
+        .unwrap();
+
+        assert_eq!(opts.connect_timeout, Some(Duration::from_secs(10)));
+        assert!(!opts.keepalives);
+        assert_eq!(opts.keepalives_idle, Some(Duration::from_secs(30)));
+        assert_eq!(opts.keepalives_interval, Some(Duration::from_secs(5)));
+        assert_eq!(opts.keepalives_count, Some(3));
+        assert_eq!(opts.tcp_user_timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(opts.read_timeout, Some(Duration::from_secs(60)));
+        assert_eq!(opts.write_timeout, None);
+        assert!(opts.params.is_empty());
+    }
+
+    #[test]
+    fn test_url_socket_defaults() {
+        let opts = Opts::try_from("postgres://localhost").unwrap();
+        assert_eq!(opts.connect_timeout, None);
+        assert!(opts.keepalives);
+        assert_eq!(opts.keepalives_idle, None);
+        assert_eq!(opts.tcp_user_timeout, None);
+        assert_eq!(opts.read_timeout, None);
+    }
+
+    #[test]
+    fn test_url_pool_params() {
+        let opts = Opts::try_from(
+            "postgres://localhost?pool_max_lifetime=1800&pool_idle_timeout=0&pool_min_idle=2",
+        )
+        .unwrap();
+        assert_eq!(opts.pool_max_lifetime, Some(Duration::from_secs(1800)));
+        assert_eq!(opts.pool_idle_timeout, None);
+        assert_eq!(opts.pool_min_idle, 2);
+        assert!(opts.params.is_empty());
+
+        assert!(Opts::try_from("postgres://localhost?pool_min_idle=-1").is_err());
+    }
+
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    #[test]
+    fn test_pool_reaper_interval() {
+        let opts = Opts::try_from(
+            "postgres://localhost?pool_max_lifetime=1800&pool_idle_timeout=0&pool_min_idle=2",
+        )
+        .unwrap();
+        assert_eq!(opts.pool_reaper_interval(), Some(Duration::from_secs(1)));
+
+        let opts = Opts {
+            pool_idle_timeout: Some(Duration::from_millis(200)),
+            ..Opts::default()
+        };
+        assert_eq!(
+            opts.pool_reaper_interval(),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(Opts::default().pool_reaper_interval(), None);
+    }
+
+    #[test]
+    fn test_pool_reset_policy() {
+        let opts = Opts::try_from("postgres://localhost?pool_reset_policy=reset-all").unwrap();
+        assert!(matches!(opts.pool_reset_policy, PoolResetPolicy::ResetAll));
+        assert!(matches!(
+            Opts::default().pool_reset_policy,
+            PoolResetPolicy::DiscardAll
+        ));
+        assert!(Opts::try_from("postgres://localhost?pool_reset_policy=discard").is_err());
+    }
+
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    #[test]
+    fn test_pool_reset_statement() {
+        let policy = PoolResetPolicy::DiscardAll;
+        assert!(!policy.rolls_back(TransactionStatus::Idle));
+        assert!(policy.rolls_back(TransactionStatus::Failed));
+        assert_eq!(
+            policy.statement(TransactionStatus::Idle).as_deref(),
+            Some("DISCARD ALL")
+        );
+
+        let policy = PoolResetPolicy::RollbackIfInTx;
+        assert!(policy.rolls_back(TransactionStatus::InTransaction));
+        assert_eq!(policy.statement(TransactionStatus::InTransaction), None);
+        assert!(!PoolResetPolicy::None.rolls_back(TransactionStatus::InTransaction));
+
+        let policy = PoolResetPolicy::Custom(Arc::new(|status: TransactionStatus| {
+            status
+                .in_transaction()
+                .then(|| "ROLLBACK; SET search_path TO public".to_string())
+        }));
+        assert!(!policy.rolls_back(TransactionStatus::InTransaction));
+        assert_eq!(policy.statement(TransactionStatus::Idle), None);
+        assert_eq!(
+            policy.statement(TransactionStatus::Failed).as_deref(),
+            Some("ROLLBACK; SET search_path TO public")
+        );
+        assert_eq!(format!("{:?}", policy), "Custom");
+    }
+
+    #[test]
+    fn test_url_multiple_hosts() {
+        let opts =
+            Opts::try_from("postgres://user@db1:5433,db2,[::1]:5434/app?sslmode=disable").unwrap();
+        assert_eq!(opts.host, "db1");
+        assert_eq!(opts.port, 5433);
+        assert_eq!(
+            opts.hosts,
+            vec![
+                ("db1".to_string(), 5433),
+                ("db2".to_string(), 5432),
+                ("::1".to_string(), 5434)
+            ]
+        );
+        assert_eq!(opts.user, "user");
+        assert_eq!(opts.database.as_deref(), Some("app"));
+        assert_eq!(opts.ssl_mode, SslMode::Disable);
+    }
+
+    #[test]
+    fn test_url_host_port_params() {
+        let opts = Opts::try_from("postgres://localhost/app?host=a,b&port=5433").unwrap();
+        assert_eq!(
+            opts.hosts,
+            vec![("a".to_string(), 5433), ("b".to_string(), 5433)]
+        );
+
+        let opts = Opts::try_from("postgres://localhost?host=/tmp&port=5433").unwrap();
+        assert!(opts.hosts.is_empty());
+        assert_eq!(opts.socket.as_deref(), Some("/tmp/.s.PGSQL.5433"));
+
+        assert!(Opts::try_from("postgres://localhost?host=a,b,c&port=1,2").is_err());
+    }
+
+    #[cfg(any(feature = "sync", feature = "tokio"))]
+    #[test]
+    fn test_host_candidates() {
+        let opts = Opts::try_from("postgres://localhost?host=a,/tmp&port=1,2").unwrap();
+        let candidates = opts.host_candidates();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!((candidates[0].host.as_str(), candidates[0].port), ("a", 1));
+        assert!(candidates[0].socket.is_none());
+        assert_eq!(candidates[1].socket.as_deref(), Some("/tmp/.s.PGSQL.2"));
+        assert!(candidates.iter().all(|c| c.hosts.is_empty()));
+
+        let opts = Opts::try_from("postgres://localhost").unwrap();
+        assert_eq!(opts.host_candidates().len(), 1);
+    }
+
+    #[test]
+    fn test_url_session_attrs() {
+        let opts = Opts::try_from(
+            "postgres://a,b/app?load_balance_hosts=random&target_session_attrs=prefer-standby",
+        )
+        .unwrap();
+        assert_eq!(opts.load_balance_hosts, LoadBalanceHosts::Random);
+        assert_eq!(opts.target_session_attrs, TargetSessionAttrs::PreferStandby);
+        #[cfg(any(feature = "sync", feature = "tokio"))]
+        assert_eq!(opts.host_candidates().len(), 2);
+
+        let opts = Opts::try_from("postgres://localhost").unwrap();
+        assert_eq!(opts.load_balance_hosts, LoadBalanceHosts::Disable);
+        assert_eq!(opts.target_session_attrs, TargetSessionAttrs::Any);
+
+        assert!(Opts::try_from("postgres://localhost?target_session_attrs=replica").is_err());
+        assert!(Opts::try_from("postgres://localhost?load_balance_hosts=yes").is_err());
+    }
+
+    #[test]
+    fn test_conninfo() {
+        let opts = Opts::try_from(
+            "host=db port=5433 user=alice password='a \\'secret\\' \\\\' dbname = app \
+             sslmode=disable application_name=my\\ app options='-c search_path=app'",
+        )
+        .unwrap();
+        assert_eq!(opts.host, "db");
+        assert_eq!(opts.port, 5433);
+        assert_eq!(opts.user, "alice");
+        assert_eq!(opts.password.as_deref(), Some("a 'secret' \\"));
+        assert_eq!(opts.database.as_deref(), Some("app"));
+        assert_eq!(opts.ssl_mode, SslMode::Disable);
+        assert_eq!(opts.application_name.as_deref(), Some("my app"));
+        assert_eq!(
+            opts.params,
+            vec![("options".to_string(), "-c search_path=app".to_string())]
+        );
+
+        let opts = Opts::try_from("host=a,b port=1,2 target_session_attrs=read-write").unwrap();
+        assert_eq!(opts.hosts, vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+        assert_eq!(opts.target_session_attrs, TargetSessionAttrs::ReadWrite);
+
+        let opts = Opts::try_from("").unwrap();
+        assert_eq!(opts.host, "localhost");
+        assert_eq!(opts.port, 5432);
+
+        // Values containing "://" don't make it a URL
+        let opts =
+            Opts::try_from("host=db password='a://b' sslrootcert=file:///etc/ca.pem").unwrap();
+        assert_eq!(opts.host, "db");
+        assert_eq!(opts.password.as_deref(), Some("a://b"));
+        assert_eq!(opts.ssl_root_cert.as_deref(), Some("file:///etc/ca.pem"));
+    }
+
+    #[test]
+    fn test_conninfo_invalid() {
+        assert!(Opts::try_from("host").is_err());
+        assert!(Opts::try_from("host localhost").is_err());
+        assert!(Opts::try_from("=localhost").is_err());
+        assert!(Opts::try_from("password='unterminated").is_err());
+        assert!(Opts::try_from("port=abc").is_err());
+    }
+
+    #[test]
+    fn test_env() {
+        let env = |name: &str| {
+            match name {
+                "PGHOST" => Some("/var/run/postgresql"),
+                "PGPORT" => Some("5433"),
+                "PGUSER" => Some("alice"),
+                "PGPASSWORD" => Some("secret"),
+                "PGDATABASE" => Some("app"),
+                "PGSSLMODE" => Some("require"),
+                "PGAPPNAME" => Some("worker"),
+                "PGOPTIONS" => Some("-c statement_timeout=5s"),
+                _ => None,
+            }
+            .map(str::to_string)
+        };
+
+        let mut opts = Opts::with_default_host();
+        opts.apply_env(env).unwrap();
+        assert_eq!(opts.host, "/var/run/postgresql");
+        assert_eq!(opts.port, 5433);
+        assert_eq!(
+            opts.socket.as_deref(),
+            Some("/var/run/postgresql/.s.PGSQL.5433")
+        );
+        assert_eq!(opts.user, "alice");
+        assert_eq!(opts.password.as_deref(), Some("secret"));
+        assert_eq!(opts.database.as_deref(), Some("app"));
+        assert_eq!(opts.ssl_mode, SslMode::Require);
+        assert_eq!(opts.application_name.as_deref(), Some("worker"));
+        assert_eq!(
+            opts.params,
+            vec![("options".to_string(), "-c statement_timeout=5s".to_string())]
+        );
+
+        // Explicit settings override the environment, the rest falls back to it
+        opts.apply_str("host=db dbname=other options=-cwork_mem=64MB")
+            .unwrap();
+        assert_eq!((opts.host.as_str(), opts.port), ("db", 5433));
+        assert_eq!(opts.socket, None);
+        assert_eq!(opts.user, "alice");
+        assert_eq!(opts.database.as_deref(), Some("other"));
+        assert_eq!(
+            opts.params,
+            vec![("options".to_string(), "-cwork_mem=64MB".to_string())]
+        );
+
+        let mut opts = Opts::with_default_host();
+        opts.apply_env(env).unwrap();
+        opts.apply_str("postgres://bob@db/other").unwrap();
+        assert_eq!((opts.host.as_str(), opts.port), ("db", 5433));
+        assert_eq!(opts.user, "bob");
+        assert_eq!(opts.password.as_deref(), Some("secret"));
+        assert_eq!(opts.database.as_deref(), Some("other"));
+    }
+
+    #[test]
+    fn test_service() {
+        let path = std::env::temp_dir().join(format!(
+            "zero-postgres-pg_service-{}.conf",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "[prod]\nhost=db1,db2\nport=5433\ndbname=app\nsslmode=require\n",
+        )
+        .unwrap();
+        let servicefile = path.to_string_lossy();
+
+        let opts = Opts::try_from(
+            format!("service=prod servicefile={} dbname=other", servicefile).as_str(),
+        )
+        .unwrap();
+        assert_eq!(
+            opts.hosts,
+            vec![("db1".to_string(), 5433), ("db2".to_string(), 5433)]
+        );
+        assert_eq!(opts.ssl_mode, SslMode::Require);
+        assert_eq!(opts.database.as_deref(), Some("other"));
+
+        let opts = Opts::try_from(
+            format!("postgres://db3/?service=prod&servicefile={}", servicefile).as_str(),
+        )
+        .unwrap();
+        assert_eq!((opts.host.as_str(), opts.port), ("db3", 5433));
+        assert!(opts.hosts.is_empty());
+        assert_eq!(opts.database.as_deref(), Some("app"));
+
+        // Service settings take precedence over other environment variables
+        let mut opts = Opts::with_default_host();
+        let servicefile = servicefile.into_owned();
+        opts.apply_env(|name| match name {
+            "PGSERVICE" => Some("prod".to_string()),
+            "PGSERVICEFILE" => Some(servicefile.clone()),
+            "PGDATABASE" => Some("envdb".to_string()),
+            "PGUSER" => Some("alice".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(opts.database.as_deref(), Some("app"));
+        assert_eq!(opts.user, "alice");
+
+        assert!(
+            Opts::try_from(format!("service=dev servicefile={}", servicefile).as_str()).is_err()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_url_ssl_params() {
+        let opts = Opts::try_from(
+            "postgres://localhost?sslmode=verify-full&sslrootcert=/certs/root.crt\
+             &sslcert=/certs/client.crt&sslkey=/certs/client.key&sslcrl=/certs/root.crl",
+        )
+        .unwrap();
+        assert_eq!(opts.ssl_mode, SslMode::VerifyFull);
+        assert_eq!(opts.ssl_root_cert.as_deref(), Some("/certs/root.crt"));
+        assert_eq!(opts.ssl_cert.as_deref(), Some("/certs/client.crt"));
+        assert_eq!(opts.ssl_key.as_deref(), Some("/certs/client.key"));
+        assert_eq!(opts.ssl_crl.as_deref(), Some("/certs/root.crl"));
+        assert!(opts.params.is_empty());
+
+        for (value, mode) in [
+            ("disable", SslMode::Disable),
+            ("allow", SslMode::Allow),
+            ("prefer", SslMode::Prefer),
+            ("require", SslMode::Require),
+            ("verify-ca", SslMode::VerifyCa),
+        ] {
+            assert_eq!(value.parse::<SslMode>().unwrap(), mode);
+        }
+        assert!(Opts::try_from("postgres://localhost?sslmode=verify").is_err());
+
+        assert!(!SslMode::Allow.requires_tls());
+        assert!(!SslMode::Prefer.requires_tls());
+        assert!(SslMode::VerifyCa.requires_tls());
+
+        assert_eq!(Opts::default().ssl_negotiation, SslNegotiation::Postgres);
+        let opts =
+            Opts::try_from("postgres://localhost?sslmode=require&sslnegotiation=direct").unwrap();
+        assert_eq!(opts.ssl_negotiation, SslNegotiation::Direct);
+        assert!(opts.params.is_empty());
+        assert!(Opts::try_from("postgres://localhost?sslnegotiation=tls").is_err());
+    }
+
+    #[test]
+    fn test_channel_binding() {
+        assert_eq!(Opts::default().channel_binding, ChannelBinding::Prefer);
+
+        let opts = Opts::try_from("postgres://localhost?channel_binding=require").unwrap();
+        assert_eq!(opts.channel_binding, ChannelBinding::Require);
+        assert!(opts.params.is_empty());
+
+        let opts = Opts::try_from("host=localhost channel_binding=disable").unwrap();
+        assert_eq!(opts.channel_binding, ChannelBinding::Disable);
+
+        assert!(Opts::try_from("postgres://localhost?channel_binding=always").is_err());
+    }
+
+    #[test]
+    fn test_require_auth() {
+        assert_eq!(Opts::default().require_auth, RequireAuth::Any);
+
+        let opts = Opts::try_from("postgres://localhost?require_auth=scram-sha-256,none").unwrap();
+        assert_eq!(
+            opts.require_auth,
+            RequireAuth::Only(vec![AuthMethod::ScramSha256, AuthMethod::None])
+        );
+        assert!(opts.params.is_empty());
+        assert!(opts.require_auth.allows(AuthMethod::None));
+        assert!(!opts.require_auth.allows(AuthMethod::Password));
+        assert_eq!(opts.require_auth.to_string(), "scram-sha-256,none");
+
+        let opts = Opts::try_from("host=localhost require_auth=!password,!md5").unwrap();
+        assert_eq!(
+            opts.require_auth,
+            RequireAuth::Except(vec![AuthMethod::Password, AuthMethod::Md5])
+        );
+        assert!(opts.require_auth.allows(AuthMethod::ScramSha256));
+        assert!(!opts.require_auth.allows(AuthMethod::Md5));
+        assert_eq!(opts.require_auth.to_string(), "!password,!md5");
+
+        assert_eq!(
+            "oauth".parse::<RequireAuth>().unwrap(),
+            RequireAuth::Only(vec![AuthMethod::OAuth])
+        );
+        assert!("password,!md5".parse::<RequireAuth>().is_err());
+        assert!("!password,md5".parse::<RequireAuth>().is_err());
+        assert!("md5,md5".parse::<RequireAuth>().is_err());
+        assert!("kerberos".parse::<RequireAuth>().is_err());
+        assert!("md5,".parse::<RequireAuth>().is_err());
+    }
+
+    #[test]
+    fn test_protocol_version() {
+        let opts = Opts::default();
+        assert_eq!(opts.min_protocol_version, ProtocolVersion::V3_0);
+        assert_eq!(opts.max_protocol_version, ProtocolVersion::V3_2);
+
+        let opts = Opts::try_from(
+            "postgres://localhost?min_protocol_version=latest&max_protocol_version=3.2",
+        )
+        .unwrap();
+        assert_eq!(opts.min_protocol_version, ProtocolVersion::V3_2);
+        assert_eq!(opts.max_protocol_version, ProtocolVersion::V3_2);
+        assert!(opts.params.is_empty());
+
+        let opts = Opts::try_from("host=localhost max_protocol_version=3.0").unwrap();
+        assert_eq!(opts.max_protocol_version, ProtocolVersion::V3_0);
+        assert!(Opts::try_from("host=localhost max_protocol_version=3.1").is_err());
+
+        assert!(ProtocolVersion::V3_0 < ProtocolVersion::V3_2);
+        assert_eq!(ProtocolVersion::V3_2.to_string(), "3.2");
+        assert_eq!(ProtocolVersion::from_minor(0), Some(ProtocolVersion::V3_0));
+        assert_eq!(ProtocolVersion::from_minor(1), None);
+        assert_eq!(ProtocolVersion::V3_0.code(), 0x0003_0000);
+    }
+
+    #[test]
+    fn test_url_invalid_socket_params() {
+        assert!(Opts::try_from("postgres://localhost?connect_timeout=abc").is_err());
+        assert!(Opts::try_from("postgres://localhost?keepalives=maybe").is_err());
+        assert!(Opts::try_from("postgres://localhost?keepalives_count=-1").is_err());
     }
 }
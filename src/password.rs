@@ -0,0 +1,122 @@
+//! Password and OAuth token providers for dynamic credentials.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::error::Result;
+use crate::opts::Opts;
+
+/// Provider of the password used to authenticate new connections.
+///
+/// The provider is called each time a new connection needs a password, so it can
+/// return short-lived tokens (e.g. cloud IAM database authentication) or rotated
+/// secrets. Set it as [`Opts::password_provider`]; it takes precedence over
+/// [`Opts::password`].
+///
+/// # Example
+///
+/// ```ignore
+/// use std::sync::Arc;
+/// use zero_postgres::{Opts, sync::Pool};
+///
+/// let mut opts = Opts::try_from("postgres://app@db.example.com/app")?;
+/// opts.password_provider = Some(Arc::new(|opts: &Opts| fetch_token(&opts.host, &opts.user)));
+/// let pool = Arc::new(Pool::new(opts));
+/// ```
+pub trait PasswordProvider: Send + Sync {
+    /// Get the password for a connection with the given options.
+    fn password(&self, opts: &Opts) -> Result<String>;
+}
+
+impl<F: Fn(&Opts) -> Result<String> + Send + Sync> PasswordProvider for F {
+    fn password(&self, opts: &Opts) -> Result<String> {
+        self(opts)
+    }
+}
+
+impl std::fmt::Debug for dyn PasswordProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PasswordProvider")
+    }
+}
+
+/// Future returned by [`AsyncPasswordProvider::password`].
+pub type PasswordFuture<'a> = Pin<Box<dyn Future<Output = Result<String>> + Send + 'a>>;
+
+/// Asynchronous provider of the password used to authenticate new connections.
+///
+/// Like [`PasswordProvider`], for providers that fetch the password with async I/O.
+/// Set it as [`Opts::async_password_provider`]; it is used by the tokio client and
+/// takes precedence over [`Opts::password_provider`] and [`Opts::password`].
+///
+/// # Example
+///
+/// ```ignore
+/// use std::sync::Arc;
+/// use zero_postgres::{Opts, tokio::Pool};
+///
+/// let mut opts = Opts::try_from("postgres://app@db.example.com/app")?;
+/// opts.async_password_provider = Some(Arc::new(|opts: &Opts| {
+///     let user = opts.user.clone();
+///     async move { vault_client().database_password(&user).await }
+/// }));
+/// let pool = Arc::new(Pool::new(opts));
+/// ```
+pub trait AsyncPasswordProvider: Send + Sync {
+    /// Get the password for a connection with the given options.
+    fn password<'a>(&'a self, opts: &'a Opts) -> PasswordFuture<'a>;
+}
+
+impl<F, Fut> AsyncPasswordProvider for F
+where
+    F: Fn(&Opts) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<String>> + Send + 'static,
+{
+    fn password<'a>(&'a self, opts: &'a Opts) -> PasswordFuture<'a> {
+        Box::pin(self(opts))
+    }
+}
+
+impl std::fmt::Debug for dyn AsyncPasswordProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AsyncPasswordProvider")
+    }
+}
+
+/// Provider of the bearer token for OAuth authentication (`OAUTHBEARER`, PostgreSQL 18+).
+///
+/// The provider is called when the server requests OAuth authentication, typically
+/// to return a cached SSO access token. It is called on the connecting thread or
+/// task, so it should not block for long. Set it as [`Opts::oauth_token_provider`].
+///
+/// Returning `None` asks the server for its discovery information: the connection
+/// fails with [`Error::OAuthBearer`](crate::Error::OAuthBearer) containing the issuer
+/// and scope to get a token for. The same error is returned when the server rejects
+/// a token.
+///
+/// # Example
+///
+/// ```ignore
+/// use std::sync::Arc;
+/// use zero_postgres::{Opts, sync::Conn};
+///
+/// let mut opts = Opts::try_from("postgres://app@db.example.com/app")?;
+/// opts.oauth_token_provider = Some(Arc::new(|_: &Opts| Ok(Some(sso_client().access_token()?))));
+/// let conn = Conn::new(opts)?;
+/// ```
+pub trait OAuthTokenProvider: Send + Sync {
+    /// Get the bearer token for a connection with the given options.
+    fn token(&self, opts: &Opts) -> Result<Option<String>>;
+}
+
+impl<F: Fn(&Opts) -> Result<Option<String>> + Send + Sync> OAuthTokenProvider for F {
+    fn token(&self, opts: &Opts) -> Result<Option<String>> {
+        self(opts)
+    }
+}
+
+impl std::fmt::Debug for dyn OAuthTokenProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OAuthTokenProvider")
+    }
+}
@@ -0,0 +1,181 @@
+//! Password file (`.pgpass`) lookup.
+//!
+//! Each line has the form `hostname:port:database:username:password`. `*` matches
+//! any value, `\` escapes `:` and `\`, and lines starting with `#` are comments.
+
+use std::path::{Path, PathBuf};
+
+use crate::opts::Opts;
+
+/// Find the password for the connection in the password file.
+///
+/// The file is `opts.passfile`, then `PGPASSFILE`, then `~/.pgpass`
+/// (`%APPDATA%\postgresql\pgpass.conf` on Windows). Returns `None` if there is no
+/// file, it can't be read, or no line matches.
+pub(crate) fn lookup(opts: &Opts) -> Option<String> {
+    let path = passfile_path(opts)?;
+    if !check_permissions(&path) {
+        return None;
+    }
+    let content = std::fs::read_to_string(&path).ok()?;
+
+    // Unix sockets match "localhost"
+    let host = if opts.socket.is_some() || opts.host.is_empty() || opts.host.starts_with('/') {
+        "localhost"
+    } else {
+        opts.host.as_str()
+    };
+    let port = opts.port.to_string();
+    let database = opts.database.as_deref().unwrap_or(&opts.user);
+
+    find_password(&content, [host, &port, database, &opts.user])
+}
+
+fn passfile_path(opts: &Opts) -> Option<PathBuf> {
+    if let Some(path) = &opts.passfile {
+        return Some(PathBuf::from(path));
+    }
+    if let Some(path) = std::env::var_os("PGPASSFILE") {
+        return Some(PathBuf::from(path));
+    }
+    if cfg!(windows) {
+        let appdata = std::env::var_os("APPDATA")?;
+        Some(Path::new(&appdata).join("postgresql").join("pgpass.conf"))
+    } else {
+        Some(std::env::home_dir()?.join(".pgpass"))
+    }
+}
+
+/// Check that the file is a regular file that is not accessible by group or others.
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return false;
+    };
+    if !metadata.is_file() {
+        log::warn!("password file \"{}\" is not a plain file", path.display());
+        return false;
+    }
+    if metadata.permissions().mode() & 0o077 != 0 {
+        log::warn!(
+            "password file \"{}\" has group or world access; permissions should be u=rw (0600) or less",
+            path.display()
+        );
+        return false;
+    }
+    true
+}
+
+#[cfg(not(unix))]
+fn check_permissions(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Find the password of the first line matching `[host, port, database, user]`.
+fn find_password(content: &str, fields: [&str; 4]) -> Option<String> {
+    content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(split_line)
+        .find(|(patterns, _)| {
+            patterns
+                .iter()
+                .zip(fields)
+                .all(|(pattern, field)| pattern == "*" || pattern == field)
+        })
+        .map(|(_, password)| password)
+}
+
+/// Split a line into its four match fields and the password.
+fn split_line(line: &str) -> Option<([String; 4], String)> {
+    let mut fields = Vec::with_capacity(5);
+    let mut field = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => field.extend(chars.next()),
+            ':' if fields.len() < 4 => fields.push(std::mem::take(&mut field)),
+            other => field.push(other),
+        }
+    }
+    let password = field;
+    let patterns: [String; 4] = fields.try_into().ok()?;
+    Some((patterns, password))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PGPASS: &str = "\
+# comment
+db.example.com:5432:app:alice:secret1
+*:5433:*:alice:secret2
+localhost:*:*:*:local\\:pass\\\\word
+*:*:*:bob:pa:ss
+";
+
+    #[test]
+    fn test_find_password() {
+        let find = |fields| find_password(PGPASS, fields);
+        assert_eq!(
+            find(["db.example.com", "5432", "app", "alice"]).as_deref(),
+            Some("secret1")
+        );
+        assert_eq!(
+            find(["db.example.com", "5433", "other", "alice"]).as_deref(),
+            Some("secret2")
+        );
+        assert_eq!(
+            find(["localhost", "5432", "app", "carol"]).as_deref(),
+            Some("local:pass\\word")
+        );
+        assert_eq!(
+            find(["db.example.com", "5432", "app", "bob"]).as_deref(),
+            Some("pa:ss")
+        );
+        assert_eq!(find(["db.example.com", "5432", "app", "carol"]), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lookup() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path =
+            std::env::temp_dir().join(format!("zero-postgres-pgpass-{}", std::process::id()));
+        std::fs::write(&path, PGPASS).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        let mut opts = Opts {
+            host: "db.example.com".into(),
+            user: "alice".into(),
+            database: Some("app".into()),
+            passfile: Some(path.to_string_lossy().into_owned()),
+            ..Opts::default()
+        };
+        assert_eq!(lookup(&opts).as_deref(), Some("secret1"));
+
+        // Unix sockets match "localhost"
+        opts.host = "/var/run/postgresql".into();
+        assert_eq!(lookup(&opts).as_deref(), Some("local:pass\\word"));
+
+        // Files readable by others are ignored
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(lookup(&opts), None);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lookup(&opts), None);
+    }
+
+    #[test]
+    fn test_split_line() {
+        assert!(split_line("host:5432:db:user").is_none());
+        assert!(split_line("").is_none());
+        let (patterns, password) = split_line("h\\:x:1:d:u:").unwrap();
+        assert_eq!(patterns, ["h:x", "1", "d", "u"]);
+        assert_eq!(password, "");
+    }
+}
@@ -0,0 +1,181 @@
+//! Connection pool statistics.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Snapshot of the state of a connection pool, returned by `Pool::stats()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Idle connections in the pool
+    pub idle: usize,
+    /// Connections checked out of the pool
+    pub in_use: usize,
+    /// Callers waiting for a connection because `pool_max_concurrency` connections are in use
+    pub waiting: usize,
+    /// Connections opened by the pool
+    pub total_created: u64,
+    /// Connections closed because they were broken, failed a ping or failed to reset
+    pub total_closed_on_error: u64,
+    /// Time callers of `get` waited for a connection slot
+    pub wait_time: WaitTimeHistogram,
+}
+
+/// Histogram of the time callers waited for a connection slot.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WaitTimeHistogram {
+    counts: [u64; WaitTimeHistogram::BUCKETS.len() + 1],
+}
+
+impl WaitTimeHistogram {
+    /// Upper bounds of the buckets. Longer waits are counted in a last, unbounded bucket.
+    pub const BUCKETS: [Duration; 8] = [
+        Duration::from_millis(1),
+        Duration::from_millis(5),
+        Duration::from_millis(10),
+        Duration::from_millis(50),
+        Duration::from_millis(100),
+        Duration::from_millis(500),
+        Duration::from_secs(1),
+        Duration::from_secs(5),
+    ];
+
+    /// Number of waits in each bucket, with the bucket's upper bound (`None` for the last bucket).
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        Self::BUCKETS
+            .iter()
+            .copied()
+            .map(Some)
+            .chain([None])
+            .zip(self.counts.iter().copied())
+    }
+
+    /// Total number of waits.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Index of the bucket counting a wait.
+    fn bucket(wait: Duration) -> usize {
+        Self::BUCKETS
+            .iter()
+            .position(|bound| wait <= *bound)
+            .unwrap_or(Self::BUCKETS.len())
+    }
+}
+
+/// Counters shared by a pool and its connections.
+#[derive(Default)]
+pub(crate) struct PoolCounters {
+    in_use: AtomicUsize,
+    waiting: AtomicUsize,
+    created: AtomicU64,
+    closed_on_error: AtomicU64,
+    wait_time: [AtomicU64; WaitTimeHistogram::BUCKETS.len() + 1],
+}
+
+impl PoolCounters {
+    // `in_use` is `SeqCst`, like the closed flag of the pools, so a checkout
+    // that started before `close` set the flag is always seen by `close`
+    pub(crate) fn checked_out(&self) {
+        self.in_use.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn checked_in(&self) {
+        self.in_use.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Number of connections checked out of the pool.
+    pub(crate) fn in_use(&self) -> usize {
+        self.in_use.load(Ordering::SeqCst)
+    }
+
+    /// Count a caller waiting for a connection slot until the guard is dropped,
+    /// whether the wait succeeds, fails or is cancelled.
+    pub(crate) fn start_waiting(&self) -> Waiting<'_> {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        Waiting {
+            counters: self,
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn record_created(&self) {
+        self.created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_closed_on_error(&self) {
+        self.closed_on_error.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_wait(&self, wait: Duration) {
+        if let Some(count) = self.wait_time.get(WaitTimeHistogram::bucket(wait)) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn snapshot(&self, idle: usize) -> PoolStats {
+        PoolStats {
+            idle,
+            in_use: self.in_use.load(Ordering::Relaxed),
+            waiting: self.waiting.load(Ordering::Relaxed),
+            total_created: self.created.load(Ordering::Relaxed),
+            total_closed_on_error: self.closed_on_error.load(Ordering::Relaxed),
+            wait_time: WaitTimeHistogram {
+                counts: std::array::from_fn(|i| {
+                    self.wait_time
+                        .get(i)
+                        .map_or(0, |count| count.load(Ordering::Relaxed))
+                }),
+            },
+        }
+    }
+}
+
+/// Guard returned by [`PoolCounters::start_waiting`].
+pub(crate) struct Waiting<'a> {
+    counters: &'a PoolCounters,
+    start: Instant,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.counters.waiting.fetch_sub(1, Ordering::Relaxed);
+        self.counters.record_wait(self.start.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_time_histogram() {
+        let counters = PoolCounters::default();
+        for millis in [0, 1, 2, 70, 10_000] {
+            counters.record_wait(Duration::from_millis(millis));
+        }
+        counters.record_created();
+        counters.checked_out();
+        counters.checked_out();
+        let waiting = counters.start_waiting();
+
+        let stats = counters.snapshot(3);
+        assert_eq!(stats.idle, 3);
+        assert_eq!(stats.in_use, 2);
+        assert_eq!(stats.waiting, 1);
+        assert_eq!(stats.total_created, 1);
+        assert_eq!(stats.wait_time.count(), 5);
+        let buckets: Vec<_> = stats.wait_time.buckets().collect();
+        assert_eq!(buckets.len(), 9);
+        assert_eq!(buckets[0], (Some(Duration::from_millis(1)), 2));
+        assert_eq!(buckets[1], (Some(Duration::from_millis(5)), 1));
+        assert_eq!(buckets[4], (Some(Duration::from_millis(100)), 1));
+        assert_eq!(buckets[8], (None, 1));
+
+        // The wait ends when the guard is dropped
+        drop(waiting);
+        let stats = counters.snapshot(3);
+        assert_eq!(stats.waiting, 0);
+        assert_eq!(stats.wait_time.count(), 6);
+    }
+}
@@ -87,7 +87,8 @@ impl<'a> AuthenticationMessage<'a> {
 
 /// BackendKeyData message - contains process ID and secret key for cancellation.
 ///
-/// In protocol 3.2, the secret key is variable-length (4-256 bytes).
+/// The secret key is 4 bytes in protocol 3.0, and variable-length (4-256 bytes)
+/// in protocol 3.2.
 #[derive(Debug, Clone)]
 pub struct BackendKeyData {
     /// Process ID of the backend
@@ -192,8 +193,8 @@ impl<'a> NotificationResponse<'a> {
 /// NegotiateProtocolVersion message - server doesn't support requested protocol features.
 #[derive(Debug, Clone)]
 pub struct NegotiateProtocolVersion<'a> {
-    /// Newest minor protocol version supported
-    pub newest_minor_version: u32,
+    /// Newest protocol version supported, as `major << 16 | minor` (196608 for 3.0)
+    pub newest_version: u32,
     /// Unrecognized protocol options
     pub unrecognized_options: Vec<&'a str>,
 }
@@ -201,7 +202,7 @@ pub struct NegotiateProtocolVersion<'a> {
 impl<'a> NegotiateProtocolVersion<'a> {
     /// Parse a NegotiateProtocolVersion message from payload bytes.
     pub fn parse(payload: &'a [u8]) -> Result<Self> {
-        let (newest_minor_version, rest) = read_u32(payload)?;
+        let (newest_version, rest) = read_u32(payload)?;
         let (num_options, mut rest) = read_u32(rest)?;
 
         let mut unrecognized_options = Vec::with_capacity(num_options as usize);
@@ -212,7 +213,7 @@ impl<'a> NegotiateProtocolVersion<'a> {
         }
 
         Ok(Self {
-            newest_minor_version,
+            newest_version,
             unrecognized_options,
         })
     }
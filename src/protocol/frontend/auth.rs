@@ -1,6 +1,7 @@
 //! Authentication messages.
 
 use crate::protocol::codec::MessageBuilder;
+use crate::scram_cache::ScramKeyCache;
 
 /// Write a PasswordMessage (cleartext or MD5 hashed password).
 pub fn write_password(buf: &mut Vec<u8>, password: &str) {
@@ -56,63 +57,352 @@ pub fn write_sasl_response(buf: &mut Vec<u8>, response: &[u8]) {
     msg.finish();
 }
 
+/// SASL mechanism name of SCRAM-SHA-256.
+pub const SCRAM_SHA_256: &str = "SCRAM-SHA-256";
+
+/// SASL mechanism name of SCRAM-SHA-256 with channel binding.
+pub const SCRAM_SHA_256_PLUS: &str = "SCRAM-SHA-256-PLUS";
+
+/// SASL mechanism name of OAuth 2.0 bearer token authentication (RFC 7628).
+pub const OAUTHBEARER: &str = "OAUTHBEARER";
+
+/// OAUTHBEARER response acknowledging the server's error response, to which the
+/// server replies with the authentication failure.
+pub const OAUTHBEARER_ERROR_ACK: &[u8] = b"\x01";
+
+/// Build the OAUTHBEARER client initial response.
+///
+/// Without a token, the response asks the server for its discovery information.
+pub fn oauth_bearer_initial_response(token: Option<&str>) -> Vec<u8> {
+    match token {
+        Some(token) => format!("n,,\x01auth=Bearer {}\x01\x01", token).into_bytes(),
+        None => b"n,,\x01\x01".to_vec(),
+    }
+}
+
+/// OAUTHBEARER error response from the server (RFC 7628 section 3.2.2).
+///
+/// Sent when the bearer token is missing or rejected, with the information needed
+/// to get a token from the issuer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OAuthDiscovery {
+    /// Error status, e.g. `invalid_token`
+    pub status: String,
+    /// Scope required by the server, space-separated
+    pub scope: Option<String>,
+    /// URL of the issuer's OpenID Connect discovery document
+    pub openid_configuration: Option<String>,
+}
+
+impl OAuthDiscovery {
+    /// Parse the JSON error response.
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let json = simdutf8::compat::from_utf8(data)
+            .map_err(|e| format!("Invalid OAUTHBEARER error response: {}", e))?;
+        let members = JsonParser::new(json)
+            .parse_object()
+            .ok_or_else(|| format!("Invalid OAUTHBEARER error response: {}", json))?;
+
+        let mut status = None;
+        let mut scope = None;
+        let mut openid_configuration = None;
+        for (key, value) in members {
+            match key.as_str() {
+                "status" => status = value,
+                "scope" => scope = value,
+                "openid-configuration" => openid_configuration = value,
+                _ => {}
+            }
+        }
+        Ok(Self {
+            status: status.ok_or("OAUTHBEARER error response without status")?,
+            scope,
+            openid_configuration,
+        })
+    }
+}
+
+impl std::fmt::Display for OAuthDiscovery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.status)?;
+        if let Some(url) = &self.openid_configuration {
+            write!(f, ", openid-configuration: {}", url)?;
+        }
+        if let Some(scope) = &self.scope {
+            write!(f, ", scope: {}", scope)?;
+        }
+        Ok(())
+    }
+}
+
+/// Maximum nesting depth of skipped JSON values.
+const JSON_MAX_DEPTH: usize = 16;
+
+/// Minimal JSON parser for the OAUTHBEARER error response.
+struct JsonParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    /// Nesting depth of the current value
+    depth: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            chars: input.chars().peekable(),
+            depth: 0,
+        }
+    }
+
+    /// Parse a top-level object, returning its members with their string values.
+    ///
+    /// Members with other values are returned with `None`.
+    fn parse_object(mut self) -> Option<Vec<(String, Option<String>)>> {
+        let members = self.object()?;
+        self.skip_whitespace();
+        self.chars.peek().is_none().then_some(members)
+    }
+
+    fn object(&mut self) -> Option<Vec<(String, Option<String>)>> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        if self.peek() == Some('}') {
+            self.chars.next();
+            return Some(members);
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            let value = if self.peek() == Some('"') {
+                Some(self.string()?)
+            } else {
+                self.skip_value()?;
+                None
+            };
+            members.push((key, value));
+            match self.next()? {
+                ',' => {}
+                '}' => return Some(members),
+                _ => return None,
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let mut out = String::new();
+        if self.chars.next()? != '"' {
+            return None;
+        }
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(out),
+                '\\' => match self.chars.next()? {
+                    'b' => out.push('\u{8}'),
+                    'f' => out.push('\u{c}'),
+                    'n' => out.push('\n'),
+                    'r' => out.push('\r'),
+                    't' => out.push('\t'),
+                    'u' => out.push(self.unicode_escape()?),
+                    c @ ('"' | '\\' | '/') => out.push(c),
+                    _ => return None,
+                },
+                c if c < ' ' => return None,
+                c => out.push(c),
+            }
+        }
+    }
+
+    /// Parse the hex digits of a `\u` escape, including a following low surrogate.
+    fn unicode_escape(&mut self) -> Option<char> {
+        let high = self.hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high);
+        }
+        if self.chars.next()? != '\\' || self.chars.next()? != 'u' {
+            return None;
+        }
+        let low = self.hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return None;
+        }
+        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        (0..4).try_fold(0, |acc, _| {
+            Some(acc * 16 + self.chars.next()?.to_digit(16)?)
+        })
+    }
+
+    /// Skip a value other than a string.
+    fn skip_value(&mut self) -> Option<()> {
+        self.depth += 1;
+        if self.depth > JSON_MAX_DEPTH {
+            return None;
+        }
+        let result = self.skip_nested_value();
+        self.depth -= 1;
+        result
+    }
+
+    fn skip_nested_value(&mut self) -> Option<()> {
+        match self.peek()? {
+            '{' => self.object().map(|_| ()),
+            '[' => {
+                self.chars.next();
+                if self.peek() == Some(']') {
+                    self.chars.next();
+                    return Some(());
+                }
+                loop {
+                    if self.peek() == Some('"') {
+                        self.string()?;
+                    } else {
+                        self.skip_value()?;
+                    }
+                    match self.next()? {
+                        ',' => {}
+                        ']' => return Some(()),
+                        _ => return None,
+                    }
+                }
+            }
+            _ => {
+                // Number, true, false or null
+                let mut empty = true;
+                while self
+                    .chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+                    .is_some()
+                {
+                    empty = false;
+                }
+                (!empty).then_some(())
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    /// Peek at the next non-whitespace character.
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().copied()
+    }
+
+    /// Take the next non-whitespace character.
+    fn next(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.next()
+    }
+
+    fn expect(&mut self, expected: char) -> Option<()> {
+        (self.next()? == expected).then_some(())
+    }
+}
+
+/// SCRAM-SHA-256 keys derived from the password, salt and iteration count.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ScramKeys {
+    /// ClientKey = HMAC(SaltedPassword, "Client Key")
+    pub client_key: [u8; 32],
+    /// ServerKey = HMAC(SaltedPassword, "Server Key")
+    pub server_key: [u8; 32],
+}
+
+impl ScramKeys {
+    /// Derive the keys, running PBKDF2 with `iterations` rounds.
+    pub fn derive(password: &str, salt: &[u8], iterations: u32) -> Result<Self, String> {
+        use pbkdf2::pbkdf2_hmac;
+        use sha2::Sha256;
+
+        // SaltedPassword = Hi(Normalize(password), salt, iterations)
+        let mut salted_password = [0u8; 32];
+        pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut salted_password);
+
+        Ok(Self {
+            client_key: hmac_sha256(&salted_password, b"Client Key")?,
+            server_key: hmac_sha256(&salted_password, b"Server Key")?,
+        })
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<[u8; 32], String> {
+    use hmac::{Hmac, Mac};
+
+    let mut mac = <Hmac<sha2::Sha256> as Mac>::new_from_slice(key)
+        .map_err(|e| format!("HMAC error: {}", e))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().into())
+}
+
 /// SCRAM-SHA-256 client implementation.
 pub struct ScramClient {
     /// Client nonce
     nonce: String,
-    /// Channel binding flag
-    channel_binding: String,
+    /// GS2 header ("n,,", "y,," or "p=tls-server-end-point,,")
+    gs2_header: &'static str,
+    /// Channel binding data (tls-server-end-point hash), empty without channel binding
+    channel_binding_data: Vec<u8>,
     /// Password
     password: String,
     /// Server-first-message (stored for later)
     server_first: Option<String>,
     /// Auth message for signature verification
     auth_message: Option<String>,
-    /// Salted password for server signature verification
-    salted_password: Option<Vec<u8>>,
+    /// Keys derived from the password, for server signature verification
+    keys: Option<ScramKeys>,
 }
 
 impl ScramClient {
-    /// Create a new SCRAM client.
+    /// Create a new SCRAM client without channel binding.
     pub fn new(password: &str) -> Self {
-        use rand::Rng;
-
-        // Generate 24-byte random nonce, base64 encoded
-        let mut nonce_bytes = [0u8; 24];
-        rand::rng().fill(&mut nonce_bytes);
-        let nonce = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, nonce_bytes);
+        Self::with_gs2_header(password, "n,,", Vec::new())
+    }
 
-        Self {
-            nonce,
-            channel_binding: "n,,".to_string(), // No channel binding
-            password: password.to_string(),
-            server_first: None,
-            auth_message: None,
-            salted_password: None,
-        }
+    /// Create a new SCRAM client that supports channel binding, for a server
+    /// that does not offer SCRAM-SHA-256-PLUS.
+    ///
+    /// The server rejects the authentication if it does support channel binding,
+    /// which detects a downgrade of the mechanism.
+    pub fn new_channel_binding_supported(password: &str) -> Self {
+        Self::with_gs2_header(password, "y,,", Vec::new())
     }
 
-    /// Create a new SCRAM client with channel binding.
+    /// Create a new SCRAM-SHA-256-PLUS client with `tls-server-end-point`
+    /// channel binding.
+    ///
+    /// `channel_binding_data` is the hash of the server certificate.
     pub fn new_with_channel_binding(password: &str, channel_binding_data: &[u8]) -> Self {
+        Self::with_gs2_header(
+            password,
+            "p=tls-server-end-point,,",
+            channel_binding_data.to_vec(),
+        )
+    }
+
+    fn with_gs2_header(
+        password: &str,
+        gs2_header: &'static str,
+        channel_binding_data: Vec<u8>,
+    ) -> Self {
         use rand::Rng;
 
+        // Generate 24-byte random nonce, base64 encoded
         let mut nonce_bytes = [0u8; 24];
         rand::rng().fill(&mut nonce_bytes);
         let nonce = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, nonce_bytes);
 
-        // p=tls-server-end-point,,
-        let cb_data = base64::Engine::encode(
-            &base64::engine::general_purpose::STANDARD,
-            channel_binding_data,
-        );
-
         Self {
             nonce,
-            channel_binding: format!("p=tls-server-end-point,,{}", cb_data),
+            gs2_header,
+            channel_binding_data,
             password: password.to_string(),
             server_first: None,
             auth_message: None,
-            salted_password: None,
+            keys: None,
         }
     }
 
@@ -120,7 +410,7 @@ impl ScramClient {
     pub fn client_first_message(&self) -> String {
         // n,,n=,r=<nonce>
         // Note: username is empty because PostgreSQL ignores it in SCRAM
-        format!("{}n=,r={}", self.channel_binding, self.nonce)
+        format!("{}n=,r={}", self.gs2_header, self.nonce)
     }
 
     /// Get the bare client-first-message (without channel binding prefix).
@@ -130,9 +420,28 @@ impl ScramClient {
 
     /// Process server-first-message and generate client-final-message.
     pub fn process_server_first(&mut self, server_first: &str) -> Result<String, String> {
+        self.process_server_first_with(server_first, ScramKeys::derive)
+    }
+
+    /// Process server-first-message and generate client-final-message, getting the
+    /// keys from `cache` to skip PBKDF2 for a password, salt and iteration count
+    /// seen before.
+    pub fn process_server_first_cached(
+        &mut self,
+        server_first: &str,
+        cache: &ScramKeyCache,
+    ) -> Result<String, String> {
+        self.process_server_first_with(server_first, |password, salt, iterations| {
+            cache.get_or_derive(password, salt, iterations)
+        })
+    }
+
+    fn process_server_first_with(
+        &mut self,
+        server_first: &str,
+        derive_keys: impl FnOnce(&str, &[u8], u32) -> Result<ScramKeys, String>,
+    ) -> Result<String, String> {
         use base64::Engine;
-        use hmac::{Hmac, Mac};
-        use pbkdf2::pbkdf2_hmac;
         use sha2::{Digest, Sha256};
 
         self.server_first = Some(server_first.to_string());
@@ -166,31 +475,17 @@ impl ScramClient {
             .decode(salt_b64)
             .map_err(|e| format!("Invalid salt: {}", e))?;
 
-        // Compute SaltedPassword = Hi(Normalize(password), salt, iterations)
-        let mut salted_password = vec![0u8; 32];
-        pbkdf2_hmac::<Sha256>(
-            self.password.as_bytes(),
-            &salt,
-            iterations,
-            &mut salted_password,
-        );
-
-        self.salted_password = Some(salted_password.clone());
-
-        // ClientKey = HMAC(SaltedPassword, "Client Key")
-        let client_key = {
-            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&salted_password)
-                .map_err(|e| format!("HMAC error: {}", e))?;
-            mac.update(b"Client Key");
-            mac.finalize().into_bytes()
-        };
+        let keys = derive_keys(&self.password, &salt, iterations)?;
+        self.keys = Some(keys);
+        let client_key = keys.client_key;
 
         // StoredKey = H(ClientKey)
         let stored_key = Sha256::digest(client_key);
 
-        // channel-binding = base64(channel-binding-flag)
-        let channel_binding_b64 =
-            base64::engine::general_purpose::STANDARD.encode(self.channel_binding.as_bytes());
+        // channel-binding = base64(gs2-header + channel-binding-data)
+        let mut channel_binding = self.gs2_header.as_bytes().to_vec();
+        channel_binding.extend_from_slice(&self.channel_binding_data);
+        let channel_binding_b64 = base64::engine::general_purpose::STANDARD.encode(channel_binding);
 
         // client-final-message-without-proof = c=<channel-binding>,r=<nonce>
         let client_final_without_proof = format!("c={},r={}", channel_binding_b64, combined_nonce);
@@ -205,12 +500,7 @@ impl ScramClient {
         self.auth_message = Some(auth_message.clone());
 
         // ClientSignature = HMAC(StoredKey, AuthMessage)
-        let client_signature = {
-            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&stored_key)
-                .map_err(|e| format!("HMAC error: {}", e))?;
-            mac.update(auth_message.as_bytes());
-            mac.finalize().into_bytes()
-        };
+        let client_signature = hmac_sha256(&stored_key, auth_message.as_bytes())?;
 
         // ClientProof = ClientKey XOR ClientSignature
         let mut client_proof = [0u8; 32];
@@ -227,7 +517,6 @@ impl ScramClient {
     /// Verify server-final-message.
     pub fn verify_server_final(&self, server_final: &str) -> Result<(), String> {
         use base64::Engine;
-        use hmac::{Hmac, Mac};
 
         // Parse server-final-message: v=<server-signature>
         let server_signature_b64 = server_final
@@ -239,27 +528,11 @@ impl ScramClient {
             .map_err(|e| format!("Invalid server signature: {}", e))?;
 
         // Compute expected ServerSignature
-        let salted_password = self
-            .salted_password
-            .as_ref()
-            .ok_or("Missing salted password")?;
+        let keys = self.keys.as_ref().ok_or("Missing SCRAM keys")?;
         let auth_message = self.auth_message.as_ref().ok_or("Missing auth message")?;
 
-        // ServerKey = HMAC(SaltedPassword, "Server Key")
-        let server_key = {
-            let mut mac = <Hmac<sha2::Sha256> as Mac>::new_from_slice(salted_password)
-                .map_err(|e| format!("HMAC error: {}", e))?;
-            mac.update(b"Server Key");
-            mac.finalize().into_bytes()
-        };
-
         // ServerSignature = HMAC(ServerKey, AuthMessage)
-        let expected_signature = {
-            let mut mac = <Hmac<sha2::Sha256> as Mac>::new_from_slice(&server_key)
-                .map_err(|e| format!("HMAC error: {}", e))?;
-            mac.update(auth_message.as_bytes());
-            mac.finalize().into_bytes()
-        };
+        let expected_signature = hmac_sha256(&keys.server_key, auth_message.as_bytes())?;
 
         if server_signature.as_slice() != expected_signature.as_slice() {
             return Err("Server signature verification failed".to_string());
@@ -273,6 +546,61 @@ impl ScramClient {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_oauth_bearer_initial_response() {
+        assert_eq!(
+            oauth_bearer_initial_response(Some("abc.def")),
+            b"n,,\x01auth=Bearer abc.def\x01\x01"
+        );
+        assert_eq!(oauth_bearer_initial_response(None), b"n,,\x01\x01");
+    }
+
+    #[test]
+    fn test_oauth_discovery() {
+        let discovery = OAuthDiscovery::parse(
+            br#"{ "status": "invalid_token", "scope": "openid db",
+                 "openid-configuration": "https:\/\/sso.example.com\/.well-known\/openid-configuration" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            discovery,
+            OAuthDiscovery {
+                status: "invalid_token".into(),
+                scope: Some("openid db".into()),
+                openid_configuration: Some(
+                    "https://sso.example.com/.well-known/openid-configuration".into()
+                ),
+            }
+        );
+
+        // Unknown members are ignored
+        let discovery = OAuthDiscovery::parse(
+            br#"{"status":"invalid_token","x":[1,{"y":null},true],"z":-1.5e3,"n":"\u00e9\ud83d\ude00"}"#,
+        )
+        .unwrap();
+        assert_eq!(discovery.status, "invalid_token");
+        assert_eq!(discovery.scope, None);
+
+        for invalid in [
+            &b""[..],
+            b"{}",
+            b"{\"status\":\"x\"",
+            b"{\"status\":\"x\"} x",
+            b"{\"status\":\"x\",}",
+            b"{\"status\":x}",
+            b"[\"status\"]",
+            b"{\"status\":\"\\ud83d\"}",
+        ] {
+            assert!(OAuthDiscovery::parse(invalid).is_err(), "{:?}", invalid);
+        }
+        let nested = format!(
+            "{{\"status\":\"x\",\"a\":{}1{}}}",
+            "[".repeat(100),
+            "]".repeat(100)
+        );
+        assert!(OAuthDiscovery::parse(nested.as_bytes()).is_err());
+    }
+
     #[test]
     fn test_md5_password() {
         // Test vector from PostgreSQL
@@ -290,4 +618,73 @@ mod tests {
         // Check that password is null-terminated in the message
         assert!(buf.ends_with(&[0]));
     }
+
+    fn client_final(scram: &mut ScramClient) -> String {
+        let server_first = format!("r={}server,s=c2FsdA==,i=4096", scram.nonce);
+        scram.process_server_first(&server_first).unwrap()
+    }
+
+    #[test]
+    fn test_scram_without_channel_binding() {
+        let mut scram = ScramClient::new("secret");
+        assert!(scram.client_first_message().starts_with("n,,n=,r="));
+        // base64("n,,")
+        assert!(client_final(&mut scram).starts_with("c=biws,r="));
+
+        let mut scram = ScramClient::new_channel_binding_supported("secret");
+        assert!(scram.client_first_message().starts_with("y,,n=,r="));
+        // base64("y,,")
+        assert!(client_final(&mut scram).starts_with("c=eSws,r="));
+    }
+
+    #[test]
+    fn test_scram_with_channel_binding() {
+        use base64::Engine;
+
+        let mut scram = ScramClient::new_with_channel_binding("secret", &[1, 2, 3]);
+        assert!(
+            scram
+                .client_first_message()
+                .starts_with("p=tls-server-end-point,,n=,r=")
+        );
+
+        let client_final = client_final(&mut scram);
+        let c = client_final
+            .strip_prefix("c=")
+            .and_then(|rest| rest.split(',').next())
+            .unwrap();
+        let decoded = base64::engine::general_purpose::STANDARD.decode(c).unwrap();
+        assert_eq!(decoded, b"p=tls-server-end-point,,\x01\x02\x03");
+    }
+
+    #[test]
+    fn test_scram_cached_keys() {
+        let cache = ScramKeyCache::new(4);
+        let mut scram = ScramClient::new("secret");
+        let mut cached = ScramClient::new("secret");
+        cached.nonce = scram.nonce.clone();
+
+        let server_first = format!("r={}server,s=c2FsdA==,i=4096", scram.nonce);
+        let expected = scram.process_server_first(&server_first).unwrap();
+        let client_final = cached
+            .process_server_first_cached(&server_first, &cache)
+            .unwrap();
+        assert_eq!(client_final, expected);
+        assert_eq!(cache.len(), 1);
+
+        // The cached ServerKey verifies the server signature
+        let keys = cache.get_or_derive("secret", b"salt", 4096).unwrap();
+        let signature = hmac_sha256(
+            &keys.server_key,
+            cached.auth_message.as_ref().unwrap().as_bytes(),
+        )
+        .unwrap();
+        let server_final = format!(
+            "v={}",
+            base64::Engine::encode(&base64::engine::general_purpose::STANDARD, signature)
+        );
+        assert!(cached.verify_server_final(&server_final).is_ok());
+        assert!(scram.verify_server_final(&server_final).is_ok());
+        assert!(cached.verify_server_final("v=AAAA").is_err());
+    }
 }
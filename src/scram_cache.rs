@@ -0,0 +1,182 @@
+//! Cache of SCRAM-SHA-256 keys shared across connections.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, LazyLock, Mutex, PoisonError};
+
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+
+use crate::protocol::frontend::auth::ScramKeys;
+
+const CACHE_CAPACITY: usize = 64;
+
+/// Global SCRAM key cache shared by connections.
+pub static GLOBAL_SCRAM_KEY_CACHE: LazyLock<Arc<ScramKeyCache>> =
+    LazyLock::new(|| Arc::new(ScramKeyCache::default()));
+
+/// Keys of one cache entry, derived by the first connection that needs them.
+type Slot = Arc<Mutex<Option<ScramKeys>>>;
+
+/// Cache of SCRAM-SHA-256 ClientKey/ServerKey, keyed by password, salt and
+/// iteration count.
+///
+/// Deriving the keys runs PBKDF2 with the server's iteration count, which dominates
+/// the CPU cost of opening a connection. The server sends the same salt and
+/// iteration count until the password is changed, so later connections reuse the
+/// keys. Concurrent connections needing the same keys wait for a single derivation.
+///
+/// Entries are keyed by an HMAC with a random secret of the cache, so the cache
+/// does not hold passwords and its keys can't be used to guess them offline. The
+/// oldest entry is evicted when the cache is full; a cache with capacity 0
+/// disables caching.
+pub struct ScramKeyCache {
+    entries: Mutex<VecDeque<([u8; 32], Slot)>>,
+    capacity: usize,
+    /// HMAC keyed with the random secret
+    mac: Hmac<Sha256>,
+}
+
+impl ScramKeyCache {
+    /// Create a new cache holding up to `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        let mut secret = [0u8; 64];
+        rand::rng().fill(&mut secret);
+        Self {
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            mac: <Hmac<Sha256> as KeyInit>::new(&secret.into()),
+        }
+    }
+
+    /// Get the keys for the password, salt and iteration count, deriving them on a miss.
+    pub fn get_or_derive(
+        &self,
+        password: &str,
+        salt: &[u8],
+        iterations: u32,
+    ) -> Result<ScramKeys, String> {
+        let slot = self.slot(self.cache_key(password, salt, iterations));
+        let mut cached = slot.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(keys) = *cached {
+            return Ok(keys);
+        }
+        let keys = ScramKeys::derive(password, salt, iterations)?;
+        *cached = Some(keys);
+        Ok(keys)
+    }
+
+    /// Remove all entries.
+    pub fn clear(&self) {
+        self.lock_entries().clear();
+    }
+
+    /// Number of cached entries.
+    pub fn len(&self) -> usize {
+        self.lock_entries().len()
+    }
+
+    /// Returns true if the cache has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Find or insert the slot of a cache key.
+    fn slot(&self, key: [u8; 32]) -> Slot {
+        let mut entries = self.lock_entries();
+        if let Some((_, slot)) = entries.iter().find(|(k, _)| *k == key) {
+            return Arc::clone(slot);
+        }
+        let slot = Slot::default();
+        if self.capacity == 0 {
+            return slot;
+        }
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back((key, Arc::clone(&slot)));
+        slot
+    }
+
+    /// Hash the password, salt and iteration count into a cache key.
+    fn cache_key(&self, password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+        let mut mac = self.mac.clone();
+        for field in [password.as_bytes(), salt] {
+            mac.update(&(field.len() as u64).to_be_bytes());
+            mac.update(field);
+        }
+        mac.update(&iterations.to_be_bytes());
+        mac.finalize().into_bytes().into()
+    }
+
+    fn lock_entries(&self) -> std::sync::MutexGuard<'_, VecDeque<([u8; 32], Slot)>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for ScramKeyCache {
+    fn default() -> Self {
+        Self::new(CACHE_CAPACITY)
+    }
+}
+
+impl std::fmt::Debug for ScramKeyCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScramKeyCache")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_or_derive() {
+        let cache = ScramKeyCache::new(2);
+        let keys = cache.get_or_derive("secret", b"salt", 4096).unwrap();
+        assert!(keys == ScramKeys::derive("secret", b"salt", 4096).unwrap());
+        assert!(keys == cache.get_or_derive("secret", b"salt", 4096).unwrap());
+        assert_eq!(cache.len(), 1);
+
+        // Each of password, salt and iteration count is part of the key
+        let other = cache.get_or_derive("secret", b"salt", 4097).unwrap();
+        assert!(keys != other);
+        assert_eq!(cache.len(), 2);
+        assert_ne!(
+            cache.cache_key("secret", b"salt", 4096),
+            cache.cache_key("secret", b"tlas", 4096)
+        );
+        assert_ne!(
+            cache.cache_key("ab", b"c", 1),
+            cache.cache_key("a", b"bc", 1)
+        );
+
+        // The oldest entry is evicted
+        cache.get_or_derive("other", b"salt", 4096).unwrap();
+        assert_eq!(cache.len(), 2);
+        let entries = cache.lock_entries();
+        assert!(
+            !entries
+                .iter()
+                .any(|(key, _)| *key == cache.cache_key("secret", b"salt", 4096))
+        );
+        drop(entries);
+
+        cache.clear();
+        assert!(cache.is_empty());
+
+        // Keys depend on the secret of the cache
+        assert_ne!(
+            cache.cache_key("secret", b"salt", 4096),
+            ScramKeyCache::new(2).cache_key("secret", b"salt", 4096)
+        );
+
+        let cache = ScramKeyCache::new(0);
+        cache.get_or_derive("secret", b"salt", 4096).unwrap();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+//! Connection service file (`pg_service.conf`) lookup.
+//!
+//! The file is INI-style: each `[name]` section lists `keyword=value` connection
+//! parameters, and lines starting with `#` are comments.
+
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+
+/// Find the parameters of a service.
+///
+/// Searches `servicefile` (or `PGSERVICEFILE`, or `~/.pg_service.conf`), then
+/// `pg_service.conf` in `PGSYSCONFDIR`. Environment variables are read with `var`.
+pub(crate) fn lookup(
+    name: &str,
+    servicefile: Option<&str>,
+    var: &dyn Fn(&str) -> Option<String>,
+) -> Result<Vec<(String, String)>> {
+    let user_file = match servicefile
+        .map(str::to_string)
+        .or_else(|| var("PGSERVICEFILE"))
+    {
+        Some(path) => Some(PathBuf::from(path)),
+        None => var("HOME")
+            .map(PathBuf::from)
+            .or_else(std::env::home_dir)
+            .map(|home| home.join(".pg_service.conf")),
+    };
+    let system_file = var("PGSYSCONFDIR").map(|dir| Path::new(&dir).join("pg_service.conf"));
+
+    for path in user_file.iter().chain(system_file.iter()) {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(Error::InvalidUsage(format!(
+                    "could not read service file \"{}\": {}",
+                    path.display(),
+                    e
+                )));
+            }
+        };
+        if let Some(params) = find_service(&content, name, path)? {
+            return Ok(params);
+        }
+    }
+
+    Err(Error::InvalidUsage(format!(
+        "definition of service \"{}\" not found",
+        name
+    )))
+}
+
+/// Find the parameters of a service in the content of a service file.
+fn find_service(content: &str, name: &str, path: &Path) -> Result<Option<Vec<(String, String)>>> {
+    let mut params = None;
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(section) = line.strip_prefix('[') {
+            if params.is_some() {
+                break;
+            }
+            if section.strip_suffix(']') == Some(name) {
+                params = Some(Vec::new());
+            }
+            continue;
+        }
+
+        let Some(params) = params.as_mut() else {
+            continue;
+        };
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(Error::InvalidUsage(format!(
+                "syntax error in service file \"{}\", line {}",
+                path.display(),
+                i + 1
+            )));
+        };
+        let key = key.trim();
+        if key == "service" {
+            return Err(Error::InvalidUsage(format!(
+                "nested service specifications not supported in service file \"{}\", line {}",
+                path.display(),
+                i + 1
+            )));
+        }
+        params.push((key.to_string(), value.trim().to_string()));
+    }
+
+    Ok(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVICES: &str = "\
+# comment
+[staging]
+host=staging.example.com
+dbname = app
+
+[prod]
+host=db1,db2
+port=5433
+target_session_attrs=read-write
+";
+
+    #[test]
+    fn test_find_service() {
+        let path = Path::new("pg_service.conf");
+        let params = find_service(SERVICES, "prod", path).unwrap().unwrap();
+        assert_eq!(
+            params,
+            vec![
+                ("host".to_string(), "db1,db2".to_string()),
+                ("port".to_string(), "5433".to_string()),
+                ("target_session_attrs".to_string(), "read-write".to_string()),
+            ]
+        );
+
+        let params = find_service(SERVICES, "staging", path).unwrap().unwrap();
+        assert_eq!(params.len(), 2);
+        assert_eq!(params[1], ("dbname".to_string(), "app".to_string()));
+
+        assert!(find_service(SERVICES, "dev", path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_find_service_invalid() {
+        let path = Path::new("pg_service.conf");
+        assert!(find_service("[a]\nhost\n", "a", path).is_err());
+        assert!(find_service("[a]\nservice=b\n", "a", path).is_err());
+        // Errors in other sections are not reported
+        assert!(find_service("[a]\nhost\n[b]\nhost=x\n", "b", path).is_ok());
+    }
+}
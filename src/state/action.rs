@@ -38,6 +38,18 @@ pub enum Action {
     /// After successful handshake, call `step()` again.
     TlsHandshake,
 
+    /// Provide the password for authentication.
+    ///
+    /// Requested when a password provider is set. The caller should get the password
+    /// from the provider, call `set_password()`, then call `step()` again.
+    RequestPassword,
+
+    /// Provide the OAuth bearer token for authentication.
+    ///
+    /// The caller should get the token from the OAuth token provider, call
+    /// `set_oauth_token()`, then call `step()` again.
+    RequestOAuthToken,
+
     /// An asynchronous message was received.
     ///
     /// The caller should handle the message, read the next message,
@@ -0,0 +1,243 @@
+//! Query cancellation state machine.
+
+use crate::buffer_set::BufferSet;
+use crate::error::{Error, Result};
+use crate::opts::{SslMode, SslNegotiation};
+use crate::protocol::backend::BackendKeyData;
+use crate::protocol::frontend::startup::{write_cancel_request, write_ssl_request};
+use crate::protocol::types::TransactionStatus;
+
+use super::StateMachine;
+use super::action::Action;
+
+/// Cancel request state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Initial,
+    WaitingSslResponse,
+    WaitingTlsHandshake,
+    Sent,
+    Finished,
+}
+
+/// State machine for sending a CancelRequest on a new connection.
+///
+/// Negotiates TLS according to the SSL mode and negotiation like [`ConnectionStateMachine`](super::ConnectionStateMachine),
+/// then writes the CancelRequest. The server does not respond; it closes the
+/// connection after processing the request.
+pub struct CancelStateMachine {
+    state: State,
+    ssl_mode: SslMode,
+    ssl_negotiation: SslNegotiation,
+    backend_key: BackendKeyData,
+    /// SSL response byte, set by driver after ReadByte
+    ssl_response: u8,
+}
+
+impl CancelStateMachine {
+    /// Create a state machine that cancels the query of the given backend.
+    pub fn new(
+        ssl_mode: SslMode,
+        ssl_negotiation: SslNegotiation,
+        backend_key: BackendKeyData,
+    ) -> Self {
+        Self {
+            state: State::Initial,
+            ssl_mode,
+            ssl_negotiation,
+            backend_key,
+            ssl_response: 0,
+        }
+    }
+
+    /// Set the SSL response byte (called by driver after ReadByte).
+    pub fn set_ssl_response(&mut self, response: u8) {
+        self.ssl_response = response;
+    }
+
+    fn handle_initial(&mut self, buffer_set: &mut BufferSet) -> Result<Action> {
+        buffer_set.write_buffer.clear();
+
+        let client_supports_tls = cfg!(any(
+            feature = "sync-tls",
+            feature = "tokio-tls",
+            feature = "sync-rustls",
+            feature = "tokio-rustls"
+        ));
+
+        let send_ssl_request = match self.ssl_mode {
+            SslMode::Disable | SslMode::Allow => false,
+            SslMode::Prefer => client_supports_tls,
+            mode if mode.requires_tls() && !client_supports_tls => {
+                return Err(Error::Unsupported(
+                    "SSL required but TLS feature not enabled".into(),
+                ));
+            }
+            SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => true,
+        };
+
+        if self.ssl_negotiation == SslNegotiation::Direct {
+            if !self.ssl_mode.requires_tls() {
+                return Err(Error::InvalidUsage(
+                    "sslnegotiation=direct requires sslmode require, verify-ca or verify-full"
+                        .into(),
+                ));
+            }
+            // Start TLS without SSLRequest
+            self.state = State::WaitingTlsHandshake;
+            return Ok(Action::TlsHandshake);
+        }
+
+        if send_ssl_request {
+            write_ssl_request(&mut buffer_set.write_buffer);
+            self.state = State::WaitingSslResponse;
+            Ok(Action::WriteAndReadByte)
+        } else {
+            Ok(self.write_cancel_request(buffer_set))
+        }
+    }
+
+    fn handle_ssl_response(&mut self, buffer_set: &mut BufferSet) -> Result<Action> {
+        match self.ssl_response {
+            b'S' => {
+                self.state = State::WaitingTlsHandshake;
+                Ok(Action::TlsHandshake)
+            }
+            b'N' => {
+                if self.ssl_mode.requires_tls() {
+                    return Err(Error::Auth(
+                        "SSL required but not supported by server".into(),
+                    ));
+                }
+                Ok(self.write_cancel_request(buffer_set))
+            }
+            _ => Err(Error::Protocol(format!(
+                "Unexpected SSL response: {}",
+                self.ssl_response
+            ))),
+        }
+    }
+
+    fn write_cancel_request(&mut self, buffer_set: &mut BufferSet) -> Action {
+        buffer_set.write_buffer.clear();
+        write_cancel_request(
+            &mut buffer_set.write_buffer,
+            self.backend_key.process_id(),
+            self.backend_key.secret_key(),
+        );
+        self.state = State::Sent;
+        Action::Write
+    }
+}
+
+impl StateMachine for CancelStateMachine {
+    fn step(&mut self, buffer_set: &mut BufferSet) -> Result<Action> {
+        match self.state {
+            State::Initial => self.handle_initial(buffer_set),
+            State::WaitingSslResponse => self.handle_ssl_response(buffer_set),
+            State::WaitingTlsHandshake => Ok(self.write_cancel_request(buffer_set)),
+            State::Sent | State::Finished => {
+                self.state = State::Finished;
+                Ok(Action::Finished)
+            }
+        }
+    }
+
+    fn transaction_status(&self) -> TransactionStatus {
+        TransactionStatus::Idle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::frontend::startup::{CANCEL_REQUEST_CODE, SSL_REQUEST_CODE};
+
+    fn backend_key() -> BackendKeyData {
+        let mut payload = 1234_u32.to_be_bytes().to_vec();
+        payload.extend_from_slice(&[7; 32]);
+        BackendKeyData::parse(&payload).unwrap()
+    }
+
+    #[test]
+    fn test_cancel_without_ssl() {
+        let mut buffer_set = BufferSet::new();
+        let mut sm =
+            CancelStateMachine::new(SslMode::Disable, SslNegotiation::Postgres, backend_key());
+
+        assert!(matches!(sm.step(&mut buffer_set).unwrap(), Action::Write));
+        let buf = &buffer_set.write_buffer;
+        assert_eq!(buf.len(), 12 + 32);
+        assert_eq!(&buf[0..4], &44_i32.to_be_bytes());
+        assert_eq!(&buf[4..8], &CANCEL_REQUEST_CODE.to_be_bytes());
+        assert_eq!(&buf[8..12], &1234_u32.to_be_bytes());
+        assert_eq!(&buf[12..], &[7; 32]);
+
+        assert!(matches!(
+            sm.step(&mut buffer_set).unwrap(),
+            Action::Finished
+        ));
+    }
+
+    #[test]
+    fn test_cancel_ssl_rejected() {
+        let mut buffer_set = BufferSet::new();
+        let mut sm =
+            CancelStateMachine::new(SslMode::Prefer, SslNegotiation::Postgres, backend_key());
+
+        let action = sm.step(&mut buffer_set).unwrap();
+        if cfg!(any(
+            feature = "sync-tls",
+            feature = "tokio-tls",
+            feature = "sync-rustls",
+            feature = "tokio-rustls"
+        )) {
+            assert!(matches!(action, Action::WriteAndReadByte));
+            assert_eq!(
+                &buffer_set.write_buffer[4..8],
+                &SSL_REQUEST_CODE.to_be_bytes()
+            );
+            sm.set_ssl_response(b'N');
+            assert!(matches!(sm.step(&mut buffer_set).unwrap(), Action::Write));
+        } else {
+            assert!(matches!(action, Action::Write));
+        }
+        assert_eq!(
+            &buffer_set.write_buffer[4..8],
+            &CANCEL_REQUEST_CODE.to_be_bytes()
+        );
+        assert!(matches!(
+            sm.step(&mut buffer_set).unwrap(),
+            Action::Finished
+        ));
+    }
+
+    #[test]
+    fn test_cancel_direct_ssl() {
+        let mut buffer_set = BufferSet::new();
+        let mut sm =
+            CancelStateMachine::new(SslMode::Require, SslNegotiation::Direct, backend_key());
+        if cfg!(any(
+            feature = "sync-tls",
+            feature = "tokio-tls",
+            feature = "sync-rustls",
+            feature = "tokio-rustls"
+        )) {
+            assert!(matches!(
+                sm.step(&mut buffer_set).unwrap(),
+                Action::TlsHandshake
+            ));
+            assert!(matches!(sm.step(&mut buffer_set).unwrap(), Action::Write));
+            assert_eq!(
+                &buffer_set.write_buffer[4..8],
+                &CANCEL_REQUEST_CODE.to_be_bytes()
+            );
+        } else {
+            assert!(sm.step(&mut buffer_set).is_err());
+        }
+
+        let mut sm =
+            CancelStateMachine::new(SslMode::Prefer, SslNegotiation::Direct, backend_key());
+        assert!(sm.step(&mut buffer_set).is_err());
+    }
+}
@@ -1,15 +1,18 @@
 //! Connection startup and authentication state machine.
 
 use crate::error::{Error, Result};
-use crate::opts::{Opts, SslMode};
+use crate::opts::{AuthMethod, ChannelBinding, Opts, ProtocolVersion, SslMode, SslNegotiation};
 use crate::protocol::backend::{
     AuthenticationMessage, BackendKeyData, ErrorResponse, NegotiateProtocolVersion,
     ParameterStatus, RawMessage, ReadyForQuery, msg_type,
 };
-use crate::protocol::frontend::auth::{ScramClient, md5_password};
+use crate::protocol::frontend::auth::{
+    OAUTHBEARER, OAUTHBEARER_ERROR_ACK, OAuthDiscovery, SCRAM_SHA_256, SCRAM_SHA_256_PLUS,
+    ScramClient, md5_password, oauth_bearer_initial_response,
+};
 use crate::protocol::frontend::{
-    startup::write_ssl_request, write_password, write_sasl_initial_response, write_sasl_response,
-    write_startup,
+    startup::{write_ssl_request, write_startup_with_version},
+    write_password, write_sasl_initial_response, write_sasl_response,
 };
 use crate::protocol::types::TransactionStatus;
 
@@ -40,22 +43,52 @@ pub struct ConnectionStateMachine {
     backend_key: Option<BackendKeyData>,
     server_params: Vec<(String, String)>,
     transaction_status: TransactionStatus,
+    /// Requested protocol version, lowered by NegotiateProtocolVersion
+    protocol_version: ProtocolVersion,
     scram_client: Option<ScramClient>,
+    /// Password for authentication, from the options or the password provider
+    password: Option<String>,
+    /// Whether the password was requested from the driver
+    password_requested: bool,
+    /// OAuth bearer token from the OAuth token provider
+    oauth_token: Option<String>,
+    /// Whether the OAuth token was requested from the driver
+    oauth_token_requested: bool,
+    /// Whether OAUTHBEARER authentication is in progress
+    oauth_bearer: bool,
+    /// Error response of the server to the OAuth token, returned when authentication fails
+    oauth_discovery: Option<OAuthDiscovery>,
     /// SSL response byte, set by driver after ReadByte
     ssl_response: u8,
+    /// `tls-server-end-point` channel binding data, set by driver after TlsHandshake
+    channel_binding_data: Option<Vec<u8>>,
 }
 
 impl ConnectionStateMachine {
     /// Create a new connection state machine.
     pub fn new(options: Opts) -> Self {
+        // The password provider takes precedence over the static password
+        let password = if options.has_password_provider() {
+            None
+        } else {
+            options.password.clone()
+        };
         Self {
             state: State::Initial,
+            protocol_version: options.max_protocol_version,
             options,
             backend_key: None,
             server_params: Vec::new(),
             transaction_status: TransactionStatus::Idle,
             scram_client: None,
+            password,
+            password_requested: false,
+            oauth_token: None,
+            oauth_token_requested: false,
+            oauth_bearer: false,
+            oauth_discovery: None,
             ssl_response: 0,
+            channel_binding_data: None,
         }
     }
 
@@ -64,6 +97,11 @@ impl ConnectionStateMachine {
         self.backend_key.as_ref()
     }
 
+    /// Get the negotiated protocol version.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
     /// Take server parameters.
     pub fn take_server_params(&mut self) -> Vec<(String, String)> {
         std::mem::take(&mut self.server_params)
@@ -74,22 +112,107 @@ impl ConnectionStateMachine {
         self.ssl_response = response;
     }
 
+    /// Set the password from the password provider (called by driver after RequestPassword).
+    pub fn set_password(&mut self, password: String) {
+        self.password = Some(password);
+    }
+
+    /// Returns true if the password should be requested from the password provider.
+    ///
+    /// The password is requested at most once, when authentication first needs it.
+    fn should_request_password(&mut self) -> bool {
+        if self.password.is_some()
+            || self.password_requested
+            || !self.options.has_password_provider()
+        {
+            return false;
+        }
+        self.password_requested = true;
+        true
+    }
+
+    /// Set the token from the OAuth token provider (called by driver after RequestOAuthToken).
+    ///
+    /// Without a token, the server is asked for its discovery information.
+    pub fn set_oauth_token(&mut self, token: Option<String>) {
+        self.oauth_token = token;
+    }
+
+    /// Set the `tls-server-end-point` channel binding data of the TLS connection
+    /// (called by driver after TlsHandshake).
+    pub fn set_channel_binding_data(&mut self, data: Option<Vec<u8>>) {
+        self.channel_binding_data = data;
+    }
+
+    /// Fail if `require_auth` does not allow the authentication method requested by the server.
+    fn check_auth_method(&self, method: AuthMethod) -> Result<()> {
+        if self.options.require_auth.allows(method) {
+            return Ok(());
+        }
+        let request = match method {
+            AuthMethod::None => "server did not complete authentication",
+            AuthMethod::Password => "server requested a cleartext password",
+            AuthMethod::Md5 => "server requested a hashed password",
+            AuthMethod::ScramSha256 => "server requested SCRAM-SHA-256 authentication",
+            AuthMethod::OAuth => "server requested OAuth authentication",
+        };
+        Err(Error::Auth(format!(
+            "authentication method requirement \"{}\" failed: {}",
+            self.options.require_auth, request
+        )))
+    }
+
+    /// Fail if channel binding is required, for authentication without SCRAM-SHA-256-PLUS.
+    fn check_channel_binding_not_required(&self) -> Result<()> {
+        if self.options.channel_binding == ChannelBinding::Require {
+            return Err(Error::Auth(
+                "channel binding required, but server authenticated client without channel binding"
+                    .into(),
+            ));
+        }
+        Ok(())
+    }
+
     fn handle_initial(&mut self, buffer_set: &mut BufferSet) -> Result<Action> {
         buffer_set.write_buffer.clear();
 
-        let client_supports_tls = cfg!(any(feature = "sync-tls", feature = "tokio-tls"));
+        if self.options.min_protocol_version > self.options.max_protocol_version {
+            return Err(Error::InvalidUsage(format!(
+                "min_protocol_version {} is greater than max_protocol_version {}",
+                self.options.min_protocol_version, self.options.max_protocol_version
+            )));
+        }
+
+        let client_supports_tls = cfg!(any(
+            feature = "sync-tls",
+            feature = "tokio-tls",
+            feature = "sync-rustls",
+            feature = "tokio-rustls"
+        ));
 
         let send_ssl_request = match self.options.ssl_mode {
-            SslMode::Disable => false,
+            SslMode::Disable | SslMode::Allow => false,
             SslMode::Prefer => client_supports_tls,
-            SslMode::Require if !client_supports_tls => {
+            mode if mode.requires_tls() && !client_supports_tls => {
                 return Err(Error::Unsupported(
                     "SSL required but TLS feature not enabled".into(),
                 ));
             }
-            SslMode::Require => true,
+            SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => true,
         };
 
+        if self.options.ssl_negotiation == SslNegotiation::Direct {
+            if !self.options.ssl_mode.requires_tls() {
+                return Err(Error::InvalidUsage(
+                    "sslnegotiation=direct requires sslmode require, verify-ca or verify-full"
+                        .into(),
+                ));
+            }
+            // Start TLS without SSLRequest
+            self.state = State::WaitingTlsHandshake;
+            return Ok(Action::TlsHandshake);
+        }
+
         if send_ssl_request {
             write_ssl_request(&mut buffer_set.write_buffer);
             self.state = State::WaitingSslResponse;
@@ -108,7 +231,7 @@ impl ConnectionStateMachine {
                 Ok(Action::TlsHandshake)
             }
             b'N' => {
-                if self.options.ssl_mode == SslMode::Require {
+                if self.options.ssl_mode.requires_tls() {
                     return Err(Error::Auth(
                         "SSL required but not supported by server".into(),
                     ));
@@ -149,7 +272,7 @@ impl ConnectionStateMachine {
             params.push((name, value));
         }
 
-        write_startup(write_buffer, &params);
+        write_startup_with_version(write_buffer, self.protocol_version.code(), &params);
     }
 
     fn handle_auth_message(&mut self, buffer_set: &mut BufferSet) -> Result<Action> {
@@ -157,13 +280,9 @@ impl ConnectionStateMachine {
 
         // Handle NegotiateProtocolVersion - server doesn't support our protocol version
         if type_byte == msg_type::NEGOTIATE_PROTOCOL_VERSION {
-            let negotiate = NegotiateProtocolVersion::parse(&buffer_set.read_buffer)?;
-            // Server sends the newest minor version it supports (0 for 3.0, 1 for 3.1, etc.)
-            return Err(Error::Protocol(format!(
-                "Server does not support protocol 3.2 (requires PostgreSQL 17+). \
-                 Server supports protocol 3.{}. Unrecognized options: {:?}",
-                negotiate.newest_minor_version, negotiate.unrecognized_options
-            )));
+            self.handle_negotiate_protocol_version(buffer_set)?;
+            // Authentication continues with the negotiated version
+            return Ok(Action::ReadMessage);
         }
 
         if type_byte != msg_type::AUTHENTICATION {
@@ -177,12 +296,18 @@ impl ConnectionStateMachine {
 
         match auth {
             AuthenticationMessage::Ok => {
+                self.check_auth_method(AuthMethod::None)?;
+                self.check_channel_binding_not_required()?;
                 self.state = State::WaitingReady;
                 Ok(Action::ReadMessage)
             }
             AuthenticationMessage::CleartextPassword => {
+                self.check_auth_method(AuthMethod::Password)?;
+                self.check_channel_binding_not_required()?;
+                if self.should_request_password() {
+                    return Ok(Action::RequestPassword);
+                }
                 let password = self
-                    .options
                     .password
                     .as_ref()
                     .ok_or_else(|| Error::Auth("Password required but not provided".into()))?;
@@ -193,8 +318,12 @@ impl ConnectionStateMachine {
                 Ok(Action::Write)
             }
             AuthenticationMessage::Md5Password { salt } => {
+                self.check_auth_method(AuthMethod::Md5)?;
+                self.check_channel_binding_not_required()?;
+                if self.should_request_password() {
+                    return Ok(Action::RequestPassword);
+                }
                 let password = self
-                    .options
                     .password
                     .as_ref()
                     .ok_or_else(|| Error::Auth("Password required but not provided".into()))?;
@@ -206,27 +335,63 @@ impl ConnectionStateMachine {
                 Ok(Action::Write)
             }
             AuthenticationMessage::Sasl { mechanisms } => {
-                // Check if SCRAM-SHA-256 is supported
-                if !mechanisms.contains(&"SCRAM-SHA-256") {
-                    return Err(Error::Auth(format!(
-                        "No supported SASL mechanism. Server offers: {:?}",
-                        mechanisms
-                    )));
+                // Prefer OAuth when the client has a token provider
+                if mechanisms.contains(&OAUTHBEARER)
+                    && (self.options.oauth_token_provider.is_some()
+                        || !mechanisms.contains(&SCRAM_SHA_256))
+                {
+                    return self.start_oauth_bearer(buffer_set);
                 }
+                self.check_auth_method(AuthMethod::ScramSha256)?;
 
+                if self.should_request_password() {
+                    return Ok(Action::RequestPassword);
+                }
                 let password = self
-                    .options
                     .password
                     .as_ref()
                     .ok_or_else(|| Error::Auth("Password required but not provided".into()))?;
 
-                let scram = ScramClient::new(password);
+                // Channel binding requires TLS and SCRAM-SHA-256-PLUS on the server
+                let server_supports_plus = mechanisms.contains(&SCRAM_SHA_256_PLUS);
+                let (mechanism, scram) =
+                    match (self.options.channel_binding, &self.channel_binding_data) {
+                        (ChannelBinding::Prefer | ChannelBinding::Require, Some(data))
+                            if server_supports_plus =>
+                        {
+                            (
+                                SCRAM_SHA_256_PLUS,
+                                ScramClient::new_with_channel_binding(password, data),
+                            )
+                        }
+                        (ChannelBinding::Require, _) => {
+                            return Err(Error::Auth(format!(
+                                "channel binding required, but not available \
+                                 (TLS: {}, server offers: {:?})",
+                                self.channel_binding_data.is_some(),
+                                mechanisms
+                            )));
+                        }
+                        _ if !mechanisms.contains(&SCRAM_SHA_256) => {
+                            return Err(Error::Auth(format!(
+                                "No supported SASL mechanism. Server offers: {:?}",
+                                mechanisms
+                            )));
+                        }
+                        // Tell the server that the client supports channel binding, so
+                        // that a stripped SCRAM-SHA-256-PLUS is detected
+                        (ChannelBinding::Prefer, Some(_)) => (
+                            SCRAM_SHA_256,
+                            ScramClient::new_channel_binding_supported(password),
+                        ),
+                        _ => (SCRAM_SHA_256, ScramClient::new(password)),
+                    };
                 let client_first = scram.client_first_message();
 
                 buffer_set.write_buffer.clear();
                 write_sasl_initial_response(
                     &mut buffer_set.write_buffer,
-                    "SCRAM-SHA-256",
+                    mechanism,
                     client_first.as_bytes(),
                 );
 
@@ -241,6 +406,64 @@ impl ConnectionStateMachine {
         }
     }
 
+    /// Send the OAUTHBEARER initial response with the token from the OAuth token provider.
+    fn start_oauth_bearer(&mut self, buffer_set: &mut BufferSet) -> Result<Action> {
+        self.check_auth_method(AuthMethod::OAuth)?;
+        self.check_channel_binding_not_required()?;
+        if self.options.oauth_token_provider.is_none() {
+            return Err(Error::Auth(
+                "server requested OAuth authentication, but oauth_token_provider is not set".into(),
+            ));
+        }
+        if !self.oauth_token_requested {
+            self.oauth_token_requested = true;
+            return Ok(Action::RequestOAuthToken);
+        }
+
+        buffer_set.write_buffer.clear();
+        write_sasl_initial_response(
+            &mut buffer_set.write_buffer,
+            OAUTHBEARER,
+            &oauth_bearer_initial_response(self.oauth_token.as_deref()),
+        );
+        self.oauth_bearer = true;
+        self.state = State::SaslInProgressRead;
+        Ok(Action::Write)
+    }
+
+    fn handle_negotiate_protocol_version(&mut self, buffer_set: &BufferSet) -> Result<()> {
+        let negotiate = NegotiateProtocolVersion::parse(&buffer_set.read_buffer)?;
+        if !negotiate.unrecognized_options.is_empty() {
+            return Err(Error::Protocol(format!(
+                "Server reported unsupported protocol options that were not requested: {:?}",
+                negotiate.unrecognized_options
+            )));
+        }
+
+        // Server sends the newest version it supports (major << 16 | minor)
+        let (major, minor) = (
+            negotiate.newest_version >> 16,
+            negotiate.newest_version & 0xffff,
+        );
+        let version = ProtocolVersion::from_minor(minor)
+            .filter(|version| major == 3 && *version < self.protocol_version)
+            .ok_or_else(|| {
+                Error::Protocol(format!(
+                    "Server requested invalid protocol version {}.{} (requested {})",
+                    major, minor, self.protocol_version
+                ))
+            })?;
+        if version < self.options.min_protocol_version {
+            return Err(Error::Unsupported(format!(
+                "Server only supports protocol {}, but min_protocol_version is {}",
+                version, self.options.min_protocol_version
+            )));
+        }
+
+        self.protocol_version = version;
+        Ok(())
+    }
+
     fn handle_sasl_message(&mut self, buffer_set: &mut BufferSet) -> Result<Action> {
         let type_byte = buffer_set.type_byte;
         if type_byte != msg_type::AUTHENTICATION {
@@ -253,6 +476,21 @@ impl ConnectionStateMachine {
         let auth = AuthenticationMessage::parse(&buffer_set.read_buffer)?;
 
         match auth {
+            // The server rejected the OAuth token; acknowledge to get the failure
+            AuthenticationMessage::SaslContinue { data } if self.oauth_bearer => {
+                let discovery = OAuthDiscovery::parse(data).map_err(Error::Auth)?;
+                self.oauth_discovery = Some(discovery);
+
+                buffer_set.write_buffer.clear();
+                write_sasl_response(&mut buffer_set.write_buffer, OAUTHBEARER_ERROR_ACK);
+                self.state = State::SaslInProgressRead;
+                Ok(Action::Write)
+            }
+            // OAUTHBEARER succeeds without SASLFinal
+            AuthenticationMessage::Ok if self.oauth_bearer && self.oauth_discovery.is_none() => {
+                self.state = State::WaitingReady;
+                Ok(Action::ReadMessage)
+            }
             AuthenticationMessage::SaslContinue { data } => {
                 let scram = self
                     .scram_client
@@ -263,7 +501,7 @@ impl ConnectionStateMachine {
                     .map_err(|e| Error::Auth(format!("Invalid server-first-message: {}", e)))?;
 
                 let client_final = scram
-                    .process_server_first(server_first)
+                    .process_server_first_cached(server_first, &self.options.scram_key_cache)
                     .map_err(Error::Auth)?;
 
                 buffer_set.write_buffer.clear();
@@ -321,6 +559,13 @@ impl ConnectionStateMachine {
         match type_byte {
             msg_type::BACKEND_KEY_DATA => {
                 let key = BackendKeyData::parse(payload)?;
+                // Cancel keys are variable-length only since protocol 3.2
+                if self.protocol_version == ProtocolVersion::V3_0 && key.secret_key().len() != 4 {
+                    return Err(Error::Protocol(format!(
+                        "BackendKeyData: invalid secret key length {} for protocol 3.0",
+                        key.secret_key().len()
+                    )));
+                }
                 self.backend_key = Some(key);
                 Ok(Action::ReadMessage)
             }
@@ -414,6 +659,10 @@ impl StateMachine for ConnectionStateMachine {
 
         // Handle error response
         if type_byte == msg_type::ERROR_RESPONSE {
+            // The server ends failed OAuth authentication with an error
+            if let Some(discovery) = self.oauth_discovery.take() {
+                return Err(Error::OAuthBearer(discovery));
+            }
             let error = ErrorResponse::parse(&buffer_set.read_buffer)?;
             return Err(error.into_error());
         }
@@ -434,3 +683,408 @@ impl StateMachine for ConnectionStateMachine {
         self.transaction_status
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::protocol::frontend::startup::{PROTOCOL_VERSION_3_0, PROTOCOL_VERSION_3_2};
+
+    fn opts() -> Opts {
+        Opts {
+            user: "postgres".into(),
+            password: Some("secret".into()),
+            ssl_mode: SslMode::Disable,
+            ..Opts::default()
+        }
+    }
+
+    /// Step through the startup message to the first authentication request,
+    /// returning the startup message.
+    fn start_with(sm: &mut ConnectionStateMachine) -> Vec<u8> {
+        let mut buffer_set = BufferSet::new();
+        assert!(matches!(sm.step(&mut buffer_set).unwrap(), Action::Write));
+        let startup = buffer_set.write_buffer.clone();
+        assert!(matches!(
+            sm.step(&mut buffer_set).unwrap(),
+            Action::ReadMessage
+        ));
+        startup
+    }
+
+    fn start(channel_binding: ChannelBinding, data: Option<Vec<u8>>) -> ConnectionStateMachine {
+        let mut sm = ConnectionStateMachine::new(Opts {
+            channel_binding,
+            ..opts()
+        });
+        sm.set_channel_binding_data(data);
+        start_with(&mut sm);
+        sm
+    }
+
+    /// Receive a message during startup.
+    fn receive(sm: &mut ConnectionStateMachine, type_byte: u8, payload: &[u8]) -> Result<Action> {
+        let mut buffer_set = BufferSet::new();
+        buffer_set.type_byte = type_byte;
+        buffer_set.read_buffer = payload.to_vec();
+        sm.step(&mut buffer_set)
+    }
+
+    /// NegotiateProtocolVersion payload.
+    fn negotiate(newest_minor_version: u32, options: &[&str]) -> Vec<u8> {
+        let mut payload = (3 << 16 | newest_minor_version).to_be_bytes().to_vec();
+        payload.extend_from_slice(&(options.len() as u32).to_be_bytes());
+        for option in options {
+            payload.extend_from_slice(option.as_bytes());
+            payload.push(0);
+        }
+        payload
+    }
+
+    /// Receive an authentication request and return the client's response.
+    fn authenticate(
+        sm: &mut ConnectionStateMachine,
+        auth_type: i32,
+        mechanisms: &[&str],
+    ) -> Result<Vec<u8>> {
+        let mut buffer_set = BufferSet::new();
+        buffer_set.type_byte = msg_type::AUTHENTICATION;
+        buffer_set.read_buffer = auth_type.to_be_bytes().to_vec();
+        for mechanism in mechanisms {
+            buffer_set
+                .read_buffer
+                .extend_from_slice(mechanism.as_bytes());
+            buffer_set.read_buffer.push(0);
+        }
+        buffer_set.read_buffer.push(0);
+        sm.step(&mut buffer_set)?;
+        Ok(buffer_set.write_buffer)
+    }
+
+    fn contains(haystack: &[u8], needle: &str) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle.as_bytes())
+    }
+
+    #[test]
+    fn test_sasl_channel_binding() {
+        let both = [SCRAM_SHA_256_PLUS, SCRAM_SHA_256];
+        let cb_data = Some(vec![7; 32]);
+
+        let mut sm = start(ChannelBinding::Prefer, cb_data.clone());
+        let msg = authenticate(&mut sm, 10, &both).unwrap();
+        assert!(contains(&msg, "SCRAM-SHA-256-PLUS\0"));
+        assert!(contains(&msg, "p=tls-server-end-point,,n=,r="));
+
+        // TLS, but the server doesn't offer SCRAM-SHA-256-PLUS
+        let mut sm = start(ChannelBinding::Prefer, cb_data.clone());
+        let msg = authenticate(&mut sm, 10, &[SCRAM_SHA_256]).unwrap();
+        assert!(contains(&msg, "SCRAM-SHA-256\0"));
+        assert!(contains(&msg, "y,,n=,r="));
+
+        // No TLS
+        let mut sm = start(ChannelBinding::Prefer, None);
+        let msg = authenticate(&mut sm, 10, &[SCRAM_SHA_256]).unwrap();
+        assert!(contains(&msg, "n,,n=,r="));
+
+        let mut sm = start(ChannelBinding::Disable, cb_data.clone());
+        let msg = authenticate(&mut sm, 10, &both).unwrap();
+        assert!(contains(&msg, "SCRAM-SHA-256\0"));
+        assert!(contains(&msg, "n,,n=,r="));
+
+        let mut sm = start(ChannelBinding::Require, cb_data.clone());
+        let msg = authenticate(&mut sm, 10, &both).unwrap();
+        assert!(contains(&msg, "p=tls-server-end-point,,n=,r="));
+    }
+
+    #[test]
+    fn test_channel_binding_required() {
+        let mut sm = start(ChannelBinding::Require, None);
+        assert!(authenticate(&mut sm, 10, &[SCRAM_SHA_256_PLUS, SCRAM_SHA_256]).is_err());
+
+        let mut sm = start(ChannelBinding::Require, Some(vec![7; 32]));
+        assert!(authenticate(&mut sm, 10, &[SCRAM_SHA_256]).is_err());
+
+        // Trust, cleartext and MD5 authentication have no channel binding
+        for auth_type in [0, 3] {
+            let mut sm = start(ChannelBinding::Require, Some(vec![7; 32]));
+            assert!(authenticate(&mut sm, auth_type, &[]).is_err());
+        }
+
+        let mut sm = start(ChannelBinding::Prefer, Some(vec![7; 32]));
+        assert!(authenticate(&mut sm, 0, &[]).is_ok());
+    }
+
+    #[test]
+    fn test_negotiate_protocol_version() {
+        let mut sm = ConnectionStateMachine::new(opts());
+        let startup = start_with(&mut sm);
+        assert_eq!(&startup[4..8], &PROTOCOL_VERSION_3_2.to_be_bytes());
+        assert_eq!(sm.protocol_version(), ProtocolVersion::V3_2);
+
+        // The server supports 3.0 and continues with authentication
+        let action = receive(
+            &mut sm,
+            msg_type::NEGOTIATE_PROTOCOL_VERSION,
+            &negotiate(0, &[]),
+        );
+        assert!(matches!(action.unwrap(), Action::ReadMessage));
+        assert_eq!(sm.protocol_version(), ProtocolVersion::V3_0);
+        assert!(authenticate(&mut sm, 0, &[]).is_ok());
+
+        // 3.0 cancel keys are 4 bytes
+        let mut key = 1234_u32.to_be_bytes().to_vec();
+        key.extend_from_slice(&[7; 32]);
+        assert!(receive(&mut sm, msg_type::BACKEND_KEY_DATA, &key).is_err());
+        key.truncate(8);
+        assert!(receive(&mut sm, msg_type::BACKEND_KEY_DATA, &key).is_ok());
+        assert_eq!(sm.backend_key().unwrap().secret_key(), &[7; 4]);
+    }
+
+    #[test]
+    fn test_negotiate_protocol_version_invalid() {
+        let mut sm = ConnectionStateMachine::new(Opts {
+            min_protocol_version: ProtocolVersion::V3_2,
+            ..opts()
+        });
+        start_with(&mut sm);
+        let action = receive(
+            &mut sm,
+            msg_type::NEGOTIATE_PROTOCOL_VERSION,
+            &negotiate(0, &[]),
+        );
+        assert!(matches!(action, Err(Error::Unsupported(_))));
+
+        for payload in [
+            negotiate(1, &[]),
+            negotiate(2, &[]),
+            negotiate(0, &["_pq_.x"]),
+        ] {
+            let mut sm = ConnectionStateMachine::new(opts());
+            start_with(&mut sm);
+            let action = receive(&mut sm, msg_type::NEGOTIATE_PROTOCOL_VERSION, &payload);
+            assert!(matches!(action, Err(Error::Protocol(_))));
+        }
+
+        let mut sm = ConnectionStateMachine::new(Opts {
+            max_protocol_version: ProtocolVersion::V3_0,
+            ..opts()
+        });
+        let startup = start_with(&mut sm);
+        assert_eq!(&startup[4..8], &PROTOCOL_VERSION_3_0.to_be_bytes());
+
+        let mut sm = ConnectionStateMachine::new(Opts {
+            min_protocol_version: ProtocolVersion::V3_2,
+            max_protocol_version: ProtocolVersion::V3_0,
+            ..opts()
+        });
+        assert!(sm.step(&mut BufferSet::new()).is_err());
+    }
+
+    #[test]
+    fn test_direct_ssl_negotiation() {
+        let mut buffer_set = BufferSet::new();
+        let mut sm = ConnectionStateMachine::new(Opts {
+            ssl_mode: SslMode::Require,
+            ssl_negotiation: SslNegotiation::Direct,
+            ..opts()
+        });
+        if cfg!(any(
+            feature = "sync-tls",
+            feature = "tokio-tls",
+            feature = "sync-rustls",
+            feature = "tokio-rustls"
+        )) {
+            // The TLS handshake starts without SSLRequest
+            assert!(matches!(
+                sm.step(&mut buffer_set).unwrap(),
+                Action::TlsHandshake
+            ));
+            assert!(buffer_set.write_buffer.is_empty());
+            assert!(matches!(sm.step(&mut buffer_set).unwrap(), Action::Write));
+            assert_eq!(
+                &buffer_set.write_buffer[4..8],
+                &PROTOCOL_VERSION_3_2.to_be_bytes()
+            );
+        } else {
+            assert!(sm.step(&mut buffer_set).is_err());
+        }
+
+        // Direct negotiation can't fall back to an unencrypted connection
+        let mut sm = ConnectionStateMachine::new(Opts {
+            ssl_mode: SslMode::Prefer,
+            ssl_negotiation: SslNegotiation::Direct,
+            ..opts()
+        });
+        assert!(matches!(
+            sm.step(&mut buffer_set),
+            Err(Error::InvalidUsage(_))
+        ));
+    }
+
+    #[test]
+    fn test_require_auth() {
+        let require = |require_auth: &str| {
+            let mut sm = ConnectionStateMachine::new(Opts {
+                require_auth: require_auth.parse().unwrap(),
+                ..opts()
+            });
+            start_with(&mut sm);
+            sm
+        };
+
+        // Cleartext password is refused before the password is sent
+        let mut sm = require("scram-sha-256");
+        let err = authenticate(&mut sm, 3, &[]).unwrap_err();
+        assert!(err.to_string().contains("cleartext password"), "{}", err);
+
+        let mut sm = require("!password");
+        assert!(authenticate(&mut sm, 3, &[]).is_err());
+        let mut sm = require("!password");
+        assert!(authenticate(&mut sm, 10, &[SCRAM_SHA_256]).is_ok());
+
+        let mut sm = require("password,md5");
+        assert!(authenticate(&mut sm, 10, &[SCRAM_SHA_256]).is_err());
+        let mut sm = require("password,md5");
+        let msg = authenticate(&mut sm, 3, &[]).unwrap();
+        assert!(contains(&msg, "secret\0"));
+
+        // AuthenticationOk without a request means no authentication
+        let mut sm = require("scram-sha-256");
+        assert!(authenticate(&mut sm, 0, &[]).is_err());
+        let mut sm = require("scram-sha-256,none");
+        assert!(authenticate(&mut sm, 0, &[]).is_ok());
+        let mut sm = require("!none");
+        assert!(authenticate(&mut sm, 0, &[]).is_err());
+    }
+
+    #[test]
+    fn test_password_provider() {
+        let provider = |opts: &Opts| Ok(format!("token-for-{}", opts.user));
+        let mut sm = ConnectionStateMachine::new(Opts {
+            password: Some("static".into()),
+            password_provider: Some(Arc::new(provider)),
+            ..opts()
+        });
+        start_with(&mut sm);
+
+        // The password is requested when the server asks for it
+        let mut buffer_set = BufferSet::new();
+        buffer_set.type_byte = msg_type::AUTHENTICATION;
+        buffer_set.read_buffer = 3_i32.to_be_bytes().to_vec();
+        assert!(matches!(
+            sm.step(&mut buffer_set).unwrap(),
+            Action::RequestPassword
+        ));
+        sm.set_password("token-for-postgres".into());
+        assert!(matches!(sm.step(&mut buffer_set).unwrap(), Action::Write));
+        assert!(contains(&buffer_set.write_buffer, "token-for-postgres\0"));
+
+        // Not requested without authentication
+        let mut sm = ConnectionStateMachine::new(Opts {
+            password_provider: Some(Arc::new(provider)),
+            ..opts()
+        });
+        start_with(&mut sm);
+        assert!(authenticate(&mut sm, 0, &[]).is_ok());
+
+        // A driver that doesn't set the password gets an error instead of a loop
+        let mut sm = ConnectionStateMachine::new(Opts {
+            password_provider: Some(Arc::new(provider)),
+            ..opts()
+        });
+        start_with(&mut sm);
+        assert!(matches!(
+            sm.step(&mut buffer_set).unwrap(),
+            Action::RequestPassword
+        ));
+        assert!(sm.step(&mut buffer_set).is_err());
+    }
+
+    #[test]
+    fn test_oauth_bearer() {
+        let provider = |_: &Opts| Ok(Some("abc.def".to_string()));
+        let oauth = |require_auth: &str| {
+            let mut sm = ConnectionStateMachine::new(Opts {
+                oauth_token_provider: Some(Arc::new(provider)),
+                require_auth: require_auth.parse().unwrap(),
+                ..opts()
+            });
+            start_with(&mut sm);
+            sm
+        };
+        let request_token = |sm: &mut ConnectionStateMachine| {
+            let mut buffer_set = BufferSet::new();
+            buffer_set.type_byte = msg_type::AUTHENTICATION;
+            buffer_set.read_buffer = 10_i32.to_be_bytes().to_vec();
+            buffer_set.read_buffer.extend_from_slice(b"OAUTHBEARER\0\0");
+            assert!(matches!(
+                sm.step(&mut buffer_set).unwrap(),
+                Action::RequestOAuthToken
+            ));
+            buffer_set
+        };
+
+        // The token is sent, and authentication completes without SASLFinal
+        let mut sm = oauth("");
+        let mut buffer_set = request_token(&mut sm);
+        sm.set_oauth_token(Some("abc.def".into()));
+        assert!(matches!(sm.step(&mut buffer_set).unwrap(), Action::Write));
+        assert!(contains(
+            &buffer_set.write_buffer,
+            "OAUTHBEARER\0\0\0\0\x19n,,\x01auth=Bearer abc.def\x01\x01"
+        ));
+        assert!(matches!(
+            sm.step(&mut buffer_set).unwrap(),
+            Action::ReadMessage
+        ));
+        assert!(authenticate(&mut sm, 0, &[]).is_ok());
+
+        // Without a token, the server's discovery information is returned
+        let mut sm = oauth("oauth");
+        let mut buffer_set = request_token(&mut sm);
+        sm.set_oauth_token(None);
+        assert!(matches!(sm.step(&mut buffer_set).unwrap(), Action::Write));
+        assert!(contains(
+            &buffer_set.write_buffer,
+            "\0\0\0\0\x05n,,\x01\x01"
+        ));
+        sm.step(&mut buffer_set).unwrap();
+        let mut payload = 11_i32.to_be_bytes().to_vec();
+        payload.extend_from_slice(
+            br#"{"status":"invalid_token","openid-configuration":"https://sso.example.com/.well-known/openid-configuration","scope":"openid"}"#,
+        );
+        let mut buffer_set = BufferSet::new();
+        buffer_set.type_byte = msg_type::AUTHENTICATION;
+        buffer_set.read_buffer = payload;
+        assert!(matches!(sm.step(&mut buffer_set).unwrap(), Action::Write));
+        assert_eq!(&buffer_set.write_buffer[..], b"p\0\0\0\x05\x01");
+        sm.step(&mut buffer_set).unwrap();
+        match receive(&mut sm, msg_type::ERROR_RESPONSE, b"SFATAL\0C28000\0\0") {
+            Err(Error::OAuthBearer(discovery)) => {
+                assert_eq!(discovery.status, "invalid_token");
+                assert_eq!(discovery.scope.as_deref(), Some("openid"));
+                assert_eq!(
+                    discovery.openid_configuration.as_deref(),
+                    Some("https://sso.example.com/.well-known/openid-configuration")
+                );
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // SCRAM is used when the client has no token provider
+        let mut sm = ConnectionStateMachine::new(opts());
+        start_with(&mut sm);
+        let msg = authenticate(&mut sm, 10, &[OAUTHBEARER, SCRAM_SHA_256]).unwrap();
+        assert!(contains(&msg, "SCRAM-SHA-256\0"));
+        let mut sm = ConnectionStateMachine::new(opts());
+        start_with(&mut sm);
+        let err = authenticate(&mut sm, 10, &[OAUTHBEARER]).unwrap_err();
+        assert!(err.to_string().contains("oauth_token_provider"), "{}", err);
+
+        let mut sm = oauth("scram-sha-256");
+        assert!(authenticate(&mut sm, 10, &[OAUTHBEARER]).is_err());
+    }
+}
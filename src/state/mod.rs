@@ -5,11 +5,13 @@
 
 pub mod action;
 pub mod batch_prepare;
+pub mod cancel;
 pub mod connection;
 pub mod extended;
 pub mod simple_query;
 
 pub use action::{Action, AsyncMessage};
+pub use cancel::CancelStateMachine;
 pub use connection::ConnectionStateMachine;
 pub use extended::ExtendedQueryStateMachine;
 pub use simple_query::SimpleQueryStateMachine;
@@ -0,0 +1,101 @@
+//! Query cancellation.
+
+use crate::buffer_set::BufferSet;
+use crate::error::{Error, Result};
+use crate::opts::Opts;
+use crate::protocol::backend::BackendKeyData;
+use crate::state::StateMachine;
+use crate::state::action::Action;
+use crate::state::cancel::CancelStateMachine;
+
+use super::stream::Stream;
+
+/// Token for cancelling the query running on a connection.
+///
+/// Created by [`Conn::cancel_token()`](super::Conn::cancel_token). The token can be
+/// cloned and sent to other threads or tasks. [`cancel()`](Self::cancel) opens a new
+/// connection to the server with the same options and sends a CancelRequest.
+///
+/// Cancellation is best-effort: if the query has already completed, the request
+/// has no effect. A cancelled query fails with SQLSTATE `57014` (query_canceled).
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    opts: Opts,
+    backend_key: Option<BackendKeyData>,
+}
+
+impl CancelToken {
+    pub(crate) fn new(opts: Opts, backend_key: Option<BackendKeyData>) -> Self {
+        Self { opts, backend_key }
+    }
+
+    /// Get the backend key data sent in the CancelRequest.
+    pub fn backend_key(&self) -> Option<&BackendKeyData> {
+        self.backend_key.as_ref()
+    }
+
+    /// Request cancellation of the query currently running on the connection.
+    pub fn cancel(&self) -> Result<()> {
+        let Some(backend_key) = &self.backend_key else {
+            return Err(Error::InvalidUsage(
+                "server did not send BackendKeyData, cancellation is not possible".into(),
+            ));
+        };
+
+        let mut stream = Stream::connect(&self.opts)?;
+        let mut buffer_set = BufferSet::new();
+        let mut state_machine = CancelStateMachine::new(
+            self.opts.ssl_mode,
+            self.opts.ssl_negotiation,
+            backend_key.clone(),
+        );
+
+        loop {
+            match state_machine.step(&mut buffer_set)? {
+                Action::WriteAndReadByte => {
+                    stream.write_all(&buffer_set.write_buffer)?;
+                    stream.flush()?;
+                    let byte = stream.read_u8()?;
+                    state_machine.set_ssl_response(byte);
+                }
+                Action::Write => {
+                    stream.write_all(&buffer_set.write_buffer)?;
+                    stream.flush()?;
+                }
+                Action::TlsHandshake => {
+                    #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
+                    {
+                        stream = stream.upgrade_to_tls(&self.opts)?;
+                    }
+                    #[cfg(not(any(feature = "sync-tls", feature = "sync-rustls")))]
+                    {
+                        return Err(Error::Unsupported(
+                            "TLS requested but sync-tls or sync-rustls feature not enabled".into(),
+                        ));
+                    }
+                }
+                Action::RequestPassword | Action::RequestOAuthToken => {
+                    return Err(Error::Protocol(
+                        "unexpected credential request during cancel request".into(),
+                    ));
+                }
+                Action::ReadMessage
+                | Action::WriteAndReadMessage
+                | Action::HandleAsyncMessageAndReadMessage(_) => {
+                    return Err(Error::Protocol(
+                        "unexpected read during cancel request".into(),
+                    ));
+                }
+                Action::Finished => break,
+            }
+        }
+
+        // The server closes the connection once the request has been processed
+        if stream.read_u8().is_ok() {
+            return Err(Error::Protocol(
+                "unexpected data after CancelRequest".into(),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,6 @@
 //! Synchronous PostgreSQL connection.
 
-use std::net::TcpStream;
-use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
 
 use crate::buffer_pool::PooledBufferSet;
 use crate::conversion::ToParams;
@@ -9,8 +8,9 @@ use crate::error::{Error, Result};
 use crate::handler::{
     AsyncMessageHandler, BinaryHandler, DropHandler, FirstRowHandler, TextHandler,
 };
-use crate::opts::Opts;
-use crate::protocol::backend::BackendKeyData;
+use crate::opts::{Opts, ProtocolVersion, SslMode, TargetSessionAttrs};
+use crate::pgpass;
+use crate::protocol::backend::{BackendKeyData, CopyInResponse, CopyOutResponse};
 use crate::protocol::frontend::write_terminate;
 use crate::protocol::types::TransactionStatus;
 use crate::state::StateMachine;
@@ -20,6 +20,8 @@ use crate::state::extended::{BindStateMachine, ExtendedQueryStateMachine, Prepar
 use crate::state::simple_query::SimpleQueryStateMachine;
 use crate::statement::IntoStatement;
 
+use super::cancel::CancelToken;
+use super::copy::{CopyIn, CopyOut, read_until_ready};
 use super::stream::Stream;
 use super::unnamed_portal::UnnamedPortal;
 
@@ -28,44 +30,172 @@ pub struct Conn {
     pub(crate) stream: Stream,
     pub(crate) buffer_set: PooledBufferSet,
     backend_key: Option<BackendKeyData>,
+    protocol_version: ProtocolVersion,
     server_params: Vec<(String, String)>,
     pub(crate) transaction_status: TransactionStatus,
     pub(crate) is_broken: bool,
+    opts: Opts,
     name_counter: u64,
     async_message_handler: Option<Box<dyn AsyncMessageHandler>>,
+    query_timeout: Option<Duration>,
+    /// Deadline of the running query, if a query timeout is set
+    deadline: Option<Instant>,
+    /// Whether a CancelRequest was sent because the deadline passed
+    timed_out: bool,
 }
 
 impl Conn {
     /// Connect to a PostgreSQL server.
+    ///
+    /// With multiple hosts, each host is tried in turn until one accepts the
+    /// connection and satisfies [`Opts::target_session_attrs`].
     pub fn new<O: TryInto<Opts>>(opts: O) -> Result<Self>
     where
         Error: From<O::Error>,
     {
         let opts = opts.try_into()?;
 
-        let stream = if let Some(socket_path) = &opts.socket {
-            Stream::unix(UnixStream::connect(socket_path)?)
-        } else {
-            if opts.host.is_empty() {
-                return Err(Error::InvalidUsage("host is empty".into()));
+        let candidates = opts.host_candidates();
+        let passes = match opts.target_session_attrs {
+            TargetSessionAttrs::PreferStandby => {
+                vec![TargetSessionAttrs::Standby, TargetSessionAttrs::Any]
+            }
+            attrs => vec![attrs],
+        };
+
+        let mut last_error = None;
+        for attrs in passes {
+            for host_opts in &candidates {
+                match Self::connect_host(host_opts.clone(), attrs) {
+                    Ok(conn) => return Ok(conn),
+                    Err(e) => last_error = Some(e),
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| Error::InvalidUsage("no hosts to connect to".into())))
+    }
+
+    /// Connect to a single host and check its session attributes.
+    fn connect_host(mut opts: Opts, attrs: TargetSessionAttrs) -> Result<Self> {
+        if opts.password.is_none() && !opts.has_password_provider() {
+            opts.password = pgpass::lookup(&opts);
+        }
+        let stream = Stream::connect(&opts)?;
+        let mut conn = match Self::new_with_stream(stream, opts.clone()) {
+            // The server may reject unencrypted connections, retry with SSL
+            Err(Error::Server(_)) if opts.ssl_mode == SslMode::Allow => {
+                opts.ssl_mode = SslMode::Require;
+                let ssl_stream = Stream::connect(&opts)?;
+                Self::new_with_stream(ssl_stream, opts)?
             }
-            let addr = format!("{}:{}", opts.host, opts.port);
-            let tcp = TcpStream::connect(&addr)?;
-            tcp.set_nodelay(true)?;
-            Stream::tcp(tcp)
+            other => other?,
         };
+        conn.check_session_attrs(attrs)?;
+        Ok(conn)
+    }
+
+    /// Check that the server satisfies the target session attributes.
+    fn check_session_attrs(&mut self, attrs: TargetSessionAttrs) -> Result<()> {
+        let mismatch = match attrs {
+            TargetSessionAttrs::Any => None,
+            TargetSessionAttrs::ReadWrite => self.is_read_only()?.then_some("session is read-only"),
+            TargetSessionAttrs::ReadOnly => {
+                (!self.is_read_only()?).then_some("session is not read-only")
+            }
+            TargetSessionAttrs::Primary => self
+                .is_hot_standby()?
+                .then_some("server is in hot standby mode"),
+            TargetSessionAttrs::Standby | TargetSessionAttrs::PreferStandby => {
+                (!self.is_hot_standby()?).then_some("server is not in hot standby mode")
+            }
+        };
+        match mismatch {
+            Some(reason) => Err(Error::SessionAttrsMismatch(format!(
+                "{} ({}:{})",
+                reason, self.opts.host, self.opts.port
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Get a server parameter reported in ParameterStatus.
+    fn server_param(&self, name: &str) -> Option<&str> {
+        self.server_params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Check if the server is in hot standby mode.
+    ///
+    /// Uses the `in_hot_standby` parameter reported by PostgreSQL 14+, or
+    /// `pg_is_in_recovery()` on older servers.
+    fn is_hot_standby(&mut self) -> Result<bool> {
+        if let Some(value) = self.server_param("in_hot_standby") {
+            return Ok(value == "on");
+        }
+        let row: Option<(bool,)> = self.query_first("SELECT pg_catalog.pg_is_in_recovery()")?;
+        Ok(row.is_some_and(|(in_recovery,)| in_recovery))
+    }
 
-        Self::new_with_stream(stream, opts)
+    /// Check if the session is read-only by default.
+    ///
+    /// Uses the `default_transaction_read_only` parameter reported by PostgreSQL 14+,
+    /// or `SHOW transaction_read_only` on older servers.
+    fn is_read_only(&mut self) -> Result<bool> {
+        if let Some(value) = self.server_param("default_transaction_read_only") {
+            let read_only = value == "on";
+            return Ok(read_only || self.is_hot_standby()?);
+        }
+        let row: Option<(String,)> = self.query_first("SHOW transaction_read_only")?;
+        Ok(row.is_some_and(|(value,)| value == "on"))
     }
 
     /// Connect using an existing stream.
+    ///
+    /// With `connect_timeout` set, the SSL negotiation and startup must complete
+    /// within the timeout.
+    pub fn new_with_stream(stream: Stream, options: Opts) -> Result<Self> {
+        let deadline = options
+            .connect_timeout
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+        let conn = match Self::startup(stream, &options, deadline) {
+            // A socket timeout may surface as an I/O or TLS error
+            Err(_) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                return Err(connect_timed_out());
+            }
+            other => other?,
+        };
+
+        // Upgrade to Unix socket if connected via TCP to loopback
+        let conn = if options.prefer_unix_socket && conn.stream.is_tcp_loopback() {
+            conn.try_upgrade_to_unix_socket(&options)
+        } else {
+            conn
+        };
+
+        Ok(conn)
+    }
+
+    /// Run the SSL negotiation and startup, until the server is ready for queries.
     #[allow(unused_mut)]
-    pub fn new_with_stream(mut stream: Stream, options: Opts) -> Result<Self> {
+    fn startup(mut stream: Stream, options: &Opts, deadline: Option<Instant>) -> Result<Self> {
         let mut buffer_set = options.buffer_pool.get_buffer_set();
         let mut state_machine = ConnectionStateMachine::new(options.clone());
 
         // Drive the connection state machine
         loop {
+            if let Some(deadline) = deadline {
+                // Bound every socket operation by the time left
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(connect_timed_out());
+                }
+                let bound = |timeout: Option<Duration>| {
+                    Some(timeout.map_or(remaining, |timeout| timeout.min(remaining)))
+                };
+                stream.set_timeouts(bound(options.read_timeout), bound(options.write_timeout))?;
+            }
             match state_machine.step(&mut buffer_set)? {
                 Action::WriteAndReadByte => {
                     stream.write_all(&buffer_set.write_buffer)?;
@@ -86,17 +216,36 @@ impl Conn {
                     stream.read_message(&mut buffer_set)?;
                 }
                 Action::TlsHandshake => {
-                    #[cfg(feature = "sync-tls")]
+                    #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
                     {
-                        stream = stream.upgrade_to_tls(&options.host)?;
+                        stream = stream.upgrade_to_tls(options)?;
+                        state_machine.set_channel_binding_data(stream.tls_server_end_point());
                     }
-                    #[cfg(not(feature = "sync-tls"))]
+                    #[cfg(not(any(feature = "sync-tls", feature = "sync-rustls")))]
                     {
                         return Err(Error::Unsupported(
-                            "TLS requested but sync-tls feature not enabled".into(),
+                            "TLS requested but sync-tls or sync-rustls feature not enabled".into(),
                         ));
                     }
                 }
+                Action::RequestPassword => {
+                    let provider = options.password_provider.as_ref().ok_or_else(|| {
+                        Error::InvalidUsage(
+                            "async_password_provider requires the tokio client, \
+                             use password_provider"
+                                .into(),
+                        )
+                    })?;
+                    state_machine.set_password(provider.password(options)?);
+                }
+                Action::RequestOAuthToken => {
+                    let provider = options.oauth_token_provider.as_ref().ok_or_else(|| {
+                        Error::InvalidUsage(
+                            "OAuth token requested without an OAuth token provider".into(),
+                        )
+                    })?;
+                    state_machine.set_oauth_token(provider.token(options)?);
+                }
                 Action::HandleAsyncMessageAndReadMessage(_) => {
                     // Ignore async messages during startup, read next message
                     stream.read_message(&mut buffer_set)?;
@@ -104,26 +253,25 @@ impl Conn {
                 Action::Finished => break,
             }
         }
+        if deadline.is_some() {
+            stream.set_timeouts(options.read_timeout, options.write_timeout)?;
+        }
 
-        let conn = Self {
+        Ok(Self {
             stream,
             buffer_set,
             backend_key: state_machine.backend_key().cloned(),
+            protocol_version: state_machine.protocol_version(),
             server_params: state_machine.take_server_params(),
             transaction_status: state_machine.transaction_status(),
             is_broken: false,
             name_counter: 0,
             async_message_handler: None,
-        };
-
-        // Upgrade to Unix socket if connected via TCP to loopback
-        let conn = if options.prefer_unix_socket && conn.stream.is_tcp_loopback() {
-            conn.try_upgrade_to_unix_socket(&options)
-        } else {
-            conn
-        };
-
-        Ok(conn)
+            query_timeout: options.query_timeout,
+            deadline: None,
+            timed_out: false,
+            opts: options.clone(),
+        })
     }
 
     /// Try to upgrade to Unix socket connection.
@@ -153,7 +301,7 @@ impl Conn {
         let socket_path = format!("{}/.s.PGSQL.{}", socket_dir, opts.port);
 
         // Connect via Unix socket
-        let unix_stream = match UnixStream::connect(&socket_path) {
+        let unix_stream = match Stream::connect_unix(&socket_path, opts) {
             Ok(s) => s,
             Err(_) => return self,
         };
@@ -162,7 +310,7 @@ impl Conn {
         let mut opts_unix = opts.clone();
         opts_unix.prefer_unix_socket = false;
 
-        match Self::new_with_stream(Stream::unix(unix_stream), opts_unix) {
+        match Self::new_with_stream(unix_stream, opts_unix) {
             Ok(new_conn) => new_conn,
             Err(_) => self,
         }
@@ -173,6 +321,14 @@ impl Conn {
         self.backend_key.as_ref()
     }
 
+    /// Get a token for cancelling the query running on this connection.
+    ///
+    /// The token is `Send + Sync + Clone` and can be used from another thread or task
+    /// while this connection is busy.
+    pub fn cancel_token(&self) -> CancelToken {
+        CancelToken::new(self.opts.clone(), self.backend_key.clone())
+    }
+
     /// Get the connection ID (backend process ID).
     ///
     /// Returns 0 if the backend key data is not available.
@@ -180,6 +336,13 @@ impl Conn {
         self.backend_key.as_ref().map_or(0, |k| k.process_id())
     }
 
+    /// Get the protocol version negotiated with the server.
+    ///
+    /// Servers older than PostgreSQL 18 only support protocol 3.0.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
     /// Get server parameters.
     pub fn server_params(&self) -> &[(String, String)] {
         &self.server_params
@@ -200,6 +363,78 @@ impl Conn {
         self.is_broken
     }
 
+    /// Get the query timeout.
+    pub fn query_timeout(&self) -> Option<Duration> {
+        self.query_timeout
+    }
+
+    /// Set the query timeout, overriding [`Opts::query_timeout`].
+    ///
+    /// Applies to `query*`, `exec*`, `exec_iter` and pipeline `sync`/`claim`. When the
+    /// timeout passes, a CancelRequest is sent on a separate connection, the resulting
+    /// error is drained, and [`Error::Timeout`] is returned. The connection stays usable.
+    pub fn set_query_timeout(&mut self, timeout: Option<Duration>) {
+        self.query_timeout = timeout;
+    }
+
+    /// Start the query timeout clock.
+    pub(crate) fn start_deadline(&mut self) {
+        self.deadline = self.query_timeout.map(|timeout| Instant::now() + timeout);
+        self.timed_out = false;
+    }
+
+    /// Stop the query timeout clock.
+    pub(crate) fn clear_deadline(&mut self) {
+        self.deadline = None;
+        self.timed_out = false;
+    }
+
+    /// Replace the query_canceled error caused by a timeout with `Error::Timeout`.
+    pub(crate) fn map_timeout<T>(&self, result: Result<T>) -> Result<T> {
+        match result {
+            Err(e) if self.timed_out && e.sqlstate() == Some("57014") => Err(Error::Timeout),
+            other => other,
+        }
+    }
+
+    fn finish_deadline<T>(&mut self, result: Result<T>) -> Result<T> {
+        let result = self.map_timeout(result);
+        self.clear_deadline();
+        result
+    }
+
+    /// Read a message, cancelling the running query once the deadline passes.
+    pub(crate) fn read_message(&mut self) -> Result<()> {
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.stream.wait_readable(remaining)? {
+                self.deadline = None;
+                self.timed_out = true;
+                if self.cancel_token().cancel().is_err() {
+                    // The query keeps running, its response cannot be awaited
+                    self.is_broken = true;
+                    return Err(Error::Timeout);
+                }
+            }
+        }
+        self.stream.read_message(&mut self.buffer_set)?;
+        Ok(())
+    }
+
+    /// Read and discard messages until ReadyForQuery.
+    fn drain_until_ready(&mut self) -> Result<()> {
+        use crate::protocol::backend::{ReadyForQuery, msg_type};
+
+        loop {
+            self.read_message()?;
+            if self.buffer_set.type_byte == msg_type::READY_FOR_QUERY {
+                let ready = ReadyForQuery::parse(&self.buffer_set.read_buffer)?;
+                self.transaction_status = ready.transaction_status().unwrap_or_default();
+                return Ok(());
+            }
+        }
+    }
+
     /// Generate the next unique portal name.
     pub(crate) fn next_portal_name(&mut self) -> String {
         self.name_counter += 1;
@@ -233,7 +468,7 @@ impl Conn {
         loop {
             match state_machine.step(&mut self.buffer_set)? {
                 Action::ReadMessage => {
-                    self.stream.read_message(&mut self.buffer_set)?;
+                    self.read_message()?;
                 }
                 Action::Write => {
                     self.stream.write_all(&self.buffer_set.write_buffer)?;
@@ -242,7 +477,7 @@ impl Conn {
                 Action::WriteAndReadMessage => {
                     self.stream.write_all(&self.buffer_set.write_buffer)?;
                     self.stream.flush()?;
-                    self.stream.read_message(&mut self.buffer_set)?;
+                    self.read_message()?;
                 }
                 Action::Finished => break,
                 _ => return Err(Error::Protocol("Unexpected action in bind".into())),
@@ -294,6 +529,7 @@ impl Conn {
         let mut pipeline = super::pipeline::Pipeline::new_inner(self);
         let result = f(&mut pipeline);
         pipeline.cleanup();
+        self.clear_deadline();
         result
     }
 
@@ -306,14 +542,24 @@ impl Conn {
     /// Drive a state machine to completion.
     fn drive<S: StateMachine>(&mut self, state_machine: &mut S) -> Result<()> {
         loop {
-            match state_machine.step(&mut self.buffer_set)? {
+            let action = match state_machine.step(&mut self.buffer_set) {
+                Ok(action) => action,
+                Err(e) => {
+                    // On error, drain to ReadyForQuery to leave connection in clean state
+                    if !e.is_connection_broken() && !matches!(e, Error::Protocol(_)) {
+                        self.drain_until_ready()?;
+                    }
+                    return Err(e);
+                }
+            };
+            match action {
                 Action::WriteAndReadByte => {
                     return Err(Error::Protocol(
                         "Unexpected WriteAndReadByte in query state machine".into(),
                     ));
                 }
                 Action::ReadMessage => {
-                    self.stream.read_message(&mut self.buffer_set)?;
+                    self.read_message()?;
                 }
                 Action::Write => {
                     self.stream.write_all(&self.buffer_set.write_buffer)?;
@@ -322,19 +568,24 @@ impl Conn {
                 Action::WriteAndReadMessage => {
                     self.stream.write_all(&self.buffer_set.write_buffer)?;
                     self.stream.flush()?;
-                    self.stream.read_message(&mut self.buffer_set)?;
+                    self.read_message()?;
                 }
                 Action::TlsHandshake => {
                     return Err(Error::Protocol(
                         "Unexpected TlsHandshake in query state machine".into(),
                     ));
                 }
+                Action::RequestPassword | Action::RequestOAuthToken => {
+                    return Err(Error::Protocol(
+                        "Unexpected credential request in query state machine".into(),
+                    ));
+                }
                 Action::HandleAsyncMessageAndReadMessage(ref async_msg) => {
                     if let Some(ref mut h) = self.async_message_handler {
                         h.handle(async_msg);
                     }
                     // Read next message after handling async message
-                    self.stream.read_message(&mut self.buffer_set)?;
+                    self.read_message()?;
                 }
                 Action::Finished => {
                     self.transaction_status = state_machine.transaction_status();
@@ -347,7 +598,9 @@ impl Conn {
 
     /// Execute a simple query with a handler.
     pub fn query<H: TextHandler>(&mut self, sql: &str, handler: &mut H) -> Result<()> {
-        let result = self.query_inner(sql, handler);
+        self.start_deadline();
+        let inner = self.query_inner(sql, handler);
+        let result = self.finish_deadline(inner);
         if let Err(e) = &result
             && e.is_connection_broken()
         {
@@ -397,6 +650,129 @@ impl Conn {
         Ok(handler.into_row())
     }
 
+    // === COPY Protocol ===
+
+    /// Start a `COPY ... FROM STDIN` operation.
+    ///
+    /// Returns a [`CopyIn`] writer that implements [`std::io::Write`]. The data
+    /// must be in the format specified by the COPY statement (text, CSV or binary).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use std::io::Write;
+    ///
+    /// let mut writer = conn.copy_in("COPY users (id, name) FROM STDIN (FORMAT csv)")?;
+    /// writeln!(writer, "1,alice")?;
+    /// writeln!(writer, "2,bob")?;
+    /// let rows = writer.finish()?;
+    /// ```
+    pub fn copy_in(&mut self, sql: &str) -> Result<CopyIn<'_>> {
+        let result = self.copy_in_inner(sql);
+        if let Err(e) = &result
+            && e.is_connection_broken()
+        {
+            self.is_broken = true;
+        }
+        Ok(CopyIn::new(self, result?))
+    }
+
+    fn copy_in_inner(&mut self, sql: &str) -> Result<CopyInResponse> {
+        use crate::protocol::backend::msg_type;
+
+        self.start_copy(
+            sql,
+            msg_type::COPY_IN_RESPONSE,
+            "copy_in requires a COPY ... FROM STDIN statement",
+        )?;
+        CopyInResponse::parse(&self.buffer_set.read_buffer)
+    }
+
+    /// Start a `COPY ... TO STDOUT` operation.
+    ///
+    /// Returns a [`CopyOut`] reader that implements [`std::io::Read`]. Data is
+    /// read from the socket one CopyData message at a time, so memory usage
+    /// does not depend on the size of the table.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let mut reader = conn.copy_out("COPY users TO STDOUT (FORMAT csv)")?;
+    /// let mut file = std::fs::File::create("users.csv")?;
+    /// std::io::copy(&mut reader, &mut file)?;
+    /// let rows = reader.finish()?;
+    /// ```
+    pub fn copy_out(&mut self, sql: &str) -> Result<CopyOut<'_>> {
+        let result = self.copy_out_inner(sql);
+        if let Err(e) = &result
+            && e.is_connection_broken()
+        {
+            self.is_broken = true;
+        }
+        Ok(CopyOut::new(self, result?))
+    }
+
+    fn copy_out_inner(&mut self, sql: &str) -> Result<CopyOutResponse> {
+        use crate::protocol::backend::msg_type;
+
+        self.start_copy(
+            sql,
+            msg_type::COPY_OUT_RESPONSE,
+            "copy_out requires a COPY ... TO STDOUT statement",
+        )?;
+        CopyOutResponse::parse(&self.buffer_set.read_buffer)
+    }
+
+    /// Send a COPY statement and read until the expected Copy*Response.
+    ///
+    /// On success, the response message is left in the read buffer.
+    fn start_copy(&mut self, sql: &str, expected: u8, usage: &str) -> Result<()> {
+        use crate::protocol::backend::{ErrorResponse, RawMessage, ReadyForQuery, msg_type};
+        use crate::protocol::frontend::{write_copy_fail, write_query};
+
+        self.buffer_set.write_buffer.clear();
+        write_query(&mut self.buffer_set.write_buffer, sql);
+        self.stream.write_all(&self.buffer_set.write_buffer)?;
+        self.stream.flush()?;
+
+        loop {
+            self.read_message()?;
+            let type_byte = self.buffer_set.type_byte;
+
+            if RawMessage::is_async_type(type_byte) {
+                continue;
+            }
+
+            match type_byte {
+                t if t == expected => return Ok(()),
+                msg_type::ERROR_RESPONSE => {
+                    let error = ErrorResponse::parse(&self.buffer_set.read_buffer)?;
+                    read_until_ready(self)?;
+                    return Err(error.into_error());
+                }
+                msg_type::READY_FOR_QUERY => {
+                    let ready = ReadyForQuery::parse(&self.buffer_set.read_buffer)?;
+                    self.transaction_status = ready.transaction_status().unwrap_or_default();
+                    return Err(Error::InvalidUsage(usage.into()));
+                }
+                _ => {
+                    if type_byte == msg_type::COPY_IN_RESPONSE {
+                        // The server waits for data, reject it so that it proceeds to ReadyForQuery
+                        self.buffer_set.write_buffer.clear();
+                        write_copy_fail(&mut self.buffer_set.write_buffer, usage);
+                        self.stream.write_all(&self.buffer_set.write_buffer)?;
+                        self.stream.flush()?;
+                    }
+                    match read_until_ready(self) {
+                        Ok(_) | Err(Error::Server(_)) => {}
+                        Err(e) => return Err(e),
+                    }
+                    return Err(Error::InvalidUsage(usage.into()));
+                }
+            }
+        }
+    }
+
     /// Close the connection gracefully.
     pub fn close(mut self) -> Result<()> {
         self.buffer_set.write_buffer.clear();
@@ -459,12 +835,12 @@ impl Conn {
         loop {
             match state_machine.step(&mut self.buffer_set)? {
                 Action::ReadMessage => {
-                    self.stream.read_message(&mut self.buffer_set)?;
+                    self.read_message()?;
                 }
                 Action::WriteAndReadMessage => {
                     self.stream.write_all(&self.buffer_set.write_buffer)?;
                     self.stream.flush()?;
-                    self.stream.read_message(&mut self.buffer_set)?;
+                    self.read_message()?;
                 }
                 Action::Finished => {
                     self.transaction_status = state_machine.transaction_status();
@@ -532,7 +908,9 @@ impl Conn {
         params: P,
         handler: &mut H,
     ) -> Result<()> {
-        let result = self.exec_inner(&statement, &params, handler);
+        self.start_deadline();
+        let inner = self.exec_inner(&statement, &params, handler);
+        let result = self.finish_deadline(inner);
         if let Err(e) = &result
             && e.is_connection_broken()
         {
@@ -678,7 +1056,9 @@ impl Conn {
         params_list: &[P],
         chunk_size: usize,
     ) -> Result<()> {
-        let result = self.exec_batch_inner(&statement, params_list, chunk_size);
+        self.start_deadline();
+        let inner = self.exec_batch_inner(&statement, params_list, chunk_size);
+        let result = self.finish_deadline(inner);
         if let Err(e) = &result
             && e.is_connection_broken()
         {
@@ -759,19 +1139,18 @@ impl Conn {
         &mut self,
         state_machine: &mut crate::state::extended::BatchStateMachine,
     ) -> Result<()> {
-        use crate::protocol::backend::{ReadyForQuery, msg_type};
         use crate::state::action::Action;
 
         loop {
             let step_result = state_machine.step(&mut self.buffer_set);
             match step_result {
                 Ok(Action::ReadMessage) => {
-                    self.stream.read_message(&mut self.buffer_set)?;
+                    self.read_message()?;
                 }
                 Ok(Action::WriteAndReadMessage) => {
                     self.stream.write_all(&self.buffer_set.write_buffer)?;
                     self.stream.flush()?;
-                    self.stream.read_message(&mut self.buffer_set)?;
+                    self.read_message()?;
                 }
                 Ok(Action::Finished) => {
                     break;
@@ -779,15 +1158,7 @@ impl Conn {
                 Ok(_) => return Err(Error::Protocol("Unexpected action in batch".into())),
                 Err(e) => {
                     // On error, drain to ReadyForQuery to leave connection in clean state
-                    loop {
-                        self.stream.read_message(&mut self.buffer_set)?;
-                        if self.buffer_set.type_byte == msg_type::READY_FOR_QUERY {
-                            let ready = ReadyForQuery::parse(&self.buffer_set.read_buffer)?;
-                            self.transaction_status =
-                                ready.transaction_status().unwrap_or_default();
-                            break;
-                        }
-                    }
+                    self.drain_until_ready()?;
                     return Err(e);
                 }
             }
@@ -903,7 +1274,7 @@ impl Conn {
         self.stream.flush()?;
 
         loop {
-            self.stream.read_message(&mut self.buffer_set)?;
+            self.read_message()?;
             let type_byte = self.buffer_set.type_byte;
 
             if RawMessage::is_async_type(type_byte) {
@@ -979,7 +1350,7 @@ impl Conn {
         let mut column_buffer: Vec<u8> = Vec::new();
 
         loop {
-            self.stream.read_message(&mut self.buffer_set)?;
+            self.read_message()?;
             let type_byte = self.buffer_set.type_byte;
 
             if RawMessage::is_async_type(type_byte) {
@@ -1053,7 +1424,7 @@ impl Conn {
         let mut pending_error: Option<Error> = None;
 
         loop {
-            self.stream.read_message(&mut self.buffer_set)?;
+            self.read_message()?;
             let type_byte = self.buffer_set.type_byte;
 
             if RawMessage::is_async_type(type_byte) {
@@ -1136,7 +1507,9 @@ impl Conn {
         P: ToParams,
         F: FnOnce(&mut UnnamedPortal<'_>) -> Result<T>,
     {
-        let result = self.exec_iter_inner(&statement, &params, f);
+        self.start_deadline();
+        let inner = self.exec_iter_inner(&statement, &params, f);
+        let result = self.finish_deadline(inner);
         if let Err(e) = &result
             && e.is_connection_broken()
         {
@@ -1171,9 +1544,18 @@ impl Conn {
 
         // Drive the state machine to completion (ParseComplete + BindComplete)
         loop {
-            match state_machine.step(&mut self.buffer_set)? {
+            let action = match state_machine.step(&mut self.buffer_set) {
+                Ok(action) => action,
+                Err(e @ Error::Server(_)) if !e.is_connection_broken() => {
+                    // The server discards messages until Sync after an error
+                    self.lowlevel_sync_inner()?;
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            };
+            match action {
                 Action::ReadMessage => {
-                    self.stream.read_message(&mut self.buffer_set)?;
+                    self.read_message()?;
                 }
                 Action::Write => {
                     self.stream.write_all(&self.buffer_set.write_buffer)?;
@@ -1182,7 +1564,7 @@ impl Conn {
                 Action::WriteAndReadMessage => {
                     self.stream.write_all(&self.buffer_set.write_buffer)?;
                     self.stream.flush()?;
-                    self.stream.read_message(&mut self.buffer_set)?;
+                    self.read_message()?;
                 }
                 Action::Finished => break,
                 _ => return Err(Error::Protocol("Unexpected action in bind".into())),
@@ -1227,7 +1609,7 @@ impl Conn {
         self.stream.flush()?;
 
         loop {
-            self.stream.read_message(&mut self.buffer_set)?;
+            self.read_message()?;
             let type_byte = self.buffer_set.type_byte;
 
             if RawMessage::is_async_type(type_byte) {
@@ -1263,3 +1645,11 @@ impl Drop for Conn {
         let _ = self.stream.flush();
     }
 }
+
+/// Error returned when the connection setup exceeds `connect_timeout`.
+fn connect_timed_out() -> Error {
+    Error::Io(std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        "connection setup timed out",
+    ))
+}
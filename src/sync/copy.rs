@@ -0,0 +1,630 @@
+//! COPY FROM STDIN / TO STDOUT support.
+
+use std::io::{Read, Write};
+
+use crate::conversion::{FromRow, ToParams};
+use crate::copy::{
+    BinaryCopyDecoder, BinaryCopyEncoder, CopyTextDecoder, CopyTextEncoder, CopyTextOptions,
+    ToCopyRow,
+};
+use crate::error::{Error, Result};
+use crate::protocol::backend::{
+    CommandComplete, CopyInResponse, CopyOutResponse, ErrorResponse, RawMessage, ReadyForQuery,
+    msg_type,
+};
+use crate::protocol::frontend::{write_copy_data, write_copy_done, write_copy_fail};
+use crate::protocol::types::Oid;
+
+use super::Conn;
+
+/// Buffered CopyData bytes are sent to the server once this size is reached.
+const COPY_IN_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Writer for a `COPY ... FROM STDIN` operation.
+///
+/// Created by [`Conn::copy_in()`]. Bytes written are sent to the server as
+/// CopyData messages. Call [`finish()`](Self::finish) to complete the COPY
+/// and get the number of rows copied.
+///
+/// If the writer is dropped without calling `finish()`, the COPY is aborted
+/// with CopyFail and the connection is left ready for the next query.
+///
+/// # Example
+///
+/// ```ignore
+/// use std::io::Write;
+///
+/// let mut writer = conn.copy_in("COPY users (id, name) FROM STDIN")?;
+/// writer.write_all(b"1\talice\n2\tbob\n")?;
+/// let rows = writer.finish()?;
+/// assert_eq!(rows, 2);
+/// ```
+pub struct CopyIn<'a> {
+    conn: &'a mut Conn,
+    response: CopyInResponse,
+    done: bool,
+}
+
+impl<'a> CopyIn<'a> {
+    pub(crate) fn new(conn: &'a mut Conn, response: CopyInResponse) -> Self {
+        conn.buffer_set.write_buffer.clear();
+        Self {
+            conn,
+            response,
+            done: false,
+        }
+    }
+
+    /// Get the CopyInResponse sent by the server (overall and per-column formats).
+    pub fn response(&self) -> &CopyInResponse {
+        &self.response
+    }
+
+    /// Send CopyDone and wait for the server to finish the COPY.
+    ///
+    /// Returns the number of rows copied.
+    pub fn finish(mut self) -> Result<u64> {
+        self.done = true;
+        let result = self.finish_inner();
+        self.mark_broken_on_error(result)
+    }
+
+    fn finish_inner(&mut self) -> Result<u64> {
+        write_copy_done(&mut self.conn.buffer_set.write_buffer);
+        self.send_buffer()?;
+        self.conn.stream.flush()?;
+        let rows = read_until_ready(self.conn)?;
+        Ok(rows.unwrap_or(0))
+    }
+
+    /// Abort the COPY by sending CopyFail with the given message.
+    ///
+    /// Data already sent is discarded by the server. The server's resulting
+    /// error is consumed, leaving the connection ready for the next query.
+    pub fn abort(mut self, message: &str) -> Result<()> {
+        self.done = true;
+        let result = self.abort_inner(message);
+        self.mark_broken_on_error(result)
+    }
+
+    fn abort_inner(&mut self, message: &str) -> Result<()> {
+        self.conn.buffer_set.write_buffer.clear();
+        write_copy_fail(&mut self.conn.buffer_set.write_buffer, message);
+        self.send_buffer()?;
+        self.conn.stream.flush()?;
+        match read_until_ready(self.conn) {
+            Ok(_) | Err(Error::Server(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write the buffered CopyData messages to the socket.
+    fn send_buffer(&mut self) -> std::io::Result<()> {
+        let buffer_set = &mut self.conn.buffer_set;
+        if !buffer_set.write_buffer.is_empty() {
+            self.conn.stream.write_all(&buffer_set.write_buffer)?;
+            buffer_set.write_buffer.clear();
+        }
+        Ok(())
+    }
+
+    fn mark_broken_on_error<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result
+            && e.is_connection_broken()
+        {
+            self.conn.is_broken = true;
+        }
+        result
+    }
+}
+
+/// Typed writer for a `COPY ... FROM STDIN (FORMAT binary)` operation.
+///
+/// Rows are encoded with [`BinaryCopyEncoder`] for the given column type OIDs
+/// and sent through the wrapped [`CopyIn`].
+///
+/// # Example
+///
+/// ```ignore
+/// use zero_postgres::protocol::types::oid;
+///
+/// let copy_in = conn.copy_in("COPY users (id, name) FROM STDIN (FORMAT binary)")?;
+/// let mut writer = BinaryCopyInWriter::new(copy_in, &[oid::INT4, oid::TEXT])?;
+/// writer.write_row(&(1, "alice"))?;
+/// writer.write_row(&(2, "bob"))?;
+/// let rows = writer.finish()?;
+/// ```
+pub struct BinaryCopyInWriter<'a> {
+    copy_in: CopyIn<'a>,
+    encoder: BinaryCopyEncoder,
+    buf: Vec<u8>,
+}
+
+impl<'a> BinaryCopyInWriter<'a> {
+    /// Create a writer for columns of the given type OIDs.
+    ///
+    /// The COPY must use the binary format.
+    pub fn new(copy_in: CopyIn<'a>, oids: &[Oid]) -> Result<Self> {
+        if !copy_in.response().is_binary() {
+            return Err(Error::InvalidUsage(
+                "BinaryCopyInWriter requires COPY ... FROM STDIN (FORMAT binary)".into(),
+            ));
+        }
+        let encoder = BinaryCopyEncoder::new(oids)?;
+        let mut buf = Vec::new();
+        encoder.write_header(&mut buf);
+        Ok(Self {
+            copy_in,
+            encoder,
+            buf,
+        })
+    }
+
+    /// Encode and write one row.
+    pub fn write_row<P: ToParams>(&mut self, row: &P) -> Result<()> {
+        self.encoder.write_row(row, &mut self.buf)?;
+        if self.buf.len() >= COPY_IN_BUFFER_SIZE {
+            self.copy_in.write_all(&self.buf)?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Write the trailer and complete the COPY.
+    ///
+    /// Returns the number of rows copied.
+    pub fn finish(mut self) -> Result<u64> {
+        self.encoder.write_trailer(&mut self.buf);
+        self.copy_in.write_all(&self.buf)?;
+        self.copy_in.finish()
+    }
+
+    /// Abort the COPY. See [`CopyIn::abort()`].
+    pub fn abort(self, message: &str) -> Result<()> {
+        self.copy_in.abort(message)
+    }
+}
+
+/// Typed writer for a `COPY ... FROM STDIN` operation in text or CSV format.
+///
+/// Rows are encoded with [`CopyTextEncoder`] and sent through the wrapped [`CopyIn`].
+///
+/// # Example
+///
+/// ```ignore
+/// use zero_postgres::copy::CopyTextOptions;
+///
+/// let copy_in = conn.copy_in("COPY users (id, name) FROM STDIN (FORMAT csv)")?;
+/// let mut writer = TextCopyInWriter::new(copy_in, CopyTextOptions::csv())?;
+/// writer.write_row(&(1, "alice"))?;
+/// writer.write_row(&(2, None::<&str>))?;
+/// let rows = writer.finish()?;
+/// ```
+pub struct TextCopyInWriter<'a> {
+    copy_in: CopyIn<'a>,
+    encoder: CopyTextEncoder,
+    buf: Vec<u8>,
+}
+
+impl<'a> TextCopyInWriter<'a> {
+    /// Create a writer with the given options, which must match the COPY statement.
+    ///
+    /// Returns an error if the options can't represent every value; see
+    /// [`CopyTextOptions::validate()`].
+    pub fn new(copy_in: CopyIn<'a>, options: CopyTextOptions) -> Result<Self> {
+        if copy_in.response().is_binary() {
+            return Err(Error::InvalidUsage(
+                "TextCopyInWriter requires COPY ... FROM STDIN in text or CSV format".into(),
+            ));
+        }
+        options.validate()?;
+        Ok(Self {
+            copy_in,
+            encoder: CopyTextEncoder::new(options),
+            buf: Vec::new(),
+        })
+    }
+
+    /// Encode and write one row.
+    pub fn write_row<R: ToCopyRow>(&mut self, row: &R) -> Result<()> {
+        self.encoder.write_row(row, &mut self.buf)?;
+        if self.buf.len() >= COPY_IN_BUFFER_SIZE {
+            self.copy_in.write_all(&self.buf)?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Complete the COPY.
+    ///
+    /// Returns the number of rows copied.
+    pub fn finish(mut self) -> Result<u64> {
+        self.copy_in.write_all(&self.buf)?;
+        self.copy_in.finish()
+    }
+
+    /// Abort the COPY. See [`CopyIn::abort()`].
+    pub fn abort(self, message: &str) -> Result<()> {
+        self.copy_in.abort(message)
+    }
+}
+
+impl Write for CopyIn<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.conn.buffer_set.write_buffer.len() >= COPY_IN_BUFFER_SIZE
+            && let Err(e) = self.send_buffer()
+        {
+            self.conn.is_broken = true;
+            return Err(e);
+        }
+        write_copy_data(&mut self.conn.buffer_set.write_buffer, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let result = self.send_buffer().and_then(|()| self.conn.stream.flush());
+        if result.is_err() {
+            self.conn.is_broken = true;
+        }
+        result
+    }
+}
+
+impl Drop for CopyIn<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let result = self.abort_inner("COPY aborted by client");
+            let _ = self.mark_broken_on_error(result);
+        }
+    }
+}
+
+/// Reader for a `COPY ... TO STDOUT` operation.
+///
+/// Created by [`Conn::copy_out()`]. Each CopyData message is read from the
+/// socket only when the previous one has been consumed. Read the data with
+/// [`std::io::Read`] or chunk by chunk with [`next_chunk()`](Self::next_chunk).
+///
+/// If the reader is dropped before the end of data, the remaining data is
+/// read and discarded so that the connection is left ready for the next query.
+///
+/// # Example
+///
+/// ```ignore
+/// let mut reader = conn.copy_out("COPY users TO STDOUT")?;
+/// while let Some(chunk) = reader.next_chunk()? {
+///     // chunk is one row in text and CSV formats
+/// }
+/// let rows = reader.finish()?;
+/// ```
+pub struct CopyOut<'a> {
+    conn: &'a mut Conn,
+    response: CopyOutResponse,
+    /// Read position in the current CopyData payload
+    pos: usize,
+    /// Length of the current CopyData payload, 0 if there is none
+    len: usize,
+    rows: Option<u64>,
+    done: bool,
+}
+
+impl<'a> CopyOut<'a> {
+    pub(crate) fn new(conn: &'a mut Conn, response: CopyOutResponse) -> Self {
+        Self {
+            conn,
+            response,
+            pos: 0,
+            len: 0,
+            rows: None,
+            done: false,
+        }
+    }
+
+    /// Get the CopyOutResponse sent by the server (overall and per-column formats).
+    pub fn response(&self) -> &CopyOutResponse {
+        &self.response
+    }
+
+    /// Read the next CopyData payload.
+    ///
+    /// Returns `None` once the server has sent all data.
+    pub fn next_chunk(&mut self) -> Result<Option<&[u8]>> {
+        if !self.fill()? {
+            return Ok(None);
+        }
+        let chunk = self.conn.buffer_set.read_buffer.get(self.pos..self.len);
+        self.pos = self.len;
+        Ok(chunk)
+    }
+
+    /// Read and discard the remaining data.
+    ///
+    /// Returns the number of rows copied.
+    pub fn finish(mut self) -> Result<u64> {
+        while self.next_chunk()?.is_some() {}
+        Ok(self.rows.unwrap_or(0))
+    }
+
+    /// Make sure there is unread data in the current CopyData payload.
+    ///
+    /// Returns `false` at the end of data.
+    fn fill(&mut self) -> Result<bool> {
+        if self.pos < self.len {
+            return Ok(true);
+        }
+        let result = self.read_next_chunk();
+        let has_data = self.mark_broken_on_error(result)?;
+        self.pos = 0;
+        self.len = if has_data {
+            self.conn.buffer_set.read_buffer.len()
+        } else {
+            0
+        };
+        Ok(has_data)
+    }
+
+    /// Read messages until the next CopyData (returns `true`) or ReadyForQuery (returns `false`).
+    fn read_next_chunk(&mut self) -> Result<bool> {
+        let mut pending_error: Option<Error> = None;
+
+        while !self.done {
+            let conn = &mut *self.conn;
+            conn.stream.read_message(&mut conn.buffer_set)?;
+            let type_byte = conn.buffer_set.type_byte;
+
+            if RawMessage::is_async_type(type_byte) {
+                continue;
+            }
+
+            match type_byte {
+                msg_type::COPY_DATA => return Ok(true),
+                msg_type::COMMAND_COMPLETE => {
+                    let complete = CommandComplete::parse(&conn.buffer_set.read_buffer)?;
+                    self.rows = complete.rows_affected();
+                }
+                msg_type::ERROR_RESPONSE => {
+                    let error = ErrorResponse::parse(&conn.buffer_set.read_buffer)?;
+                    pending_error = Some(error.into_error());
+                }
+                msg_type::READY_FOR_QUERY => {
+                    let ready = ReadyForQuery::parse(&conn.buffer_set.read_buffer)?;
+                    conn.transaction_status = ready.transaction_status().unwrap_or_default();
+                    self.done = true;
+                }
+                _ => {
+                    // CopyDone
+                }
+            }
+        }
+
+        match pending_error {
+            Some(e) => Err(e),
+            None => Ok(false),
+        }
+    }
+
+    fn mark_broken_on_error<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result
+            && e.is_connection_broken()
+        {
+            self.conn.is_broken = true;
+            self.done = true;
+        }
+        result
+    }
+}
+
+/// Typed reader for a `COPY ... TO STDOUT (FORMAT binary)` operation.
+///
+/// Tuples are decoded with [`BinaryCopyDecoder`] for the given column type OIDs
+/// from the data of the wrapped [`CopyOut`].
+///
+/// # Example
+///
+/// ```ignore
+/// use zero_postgres::protocol::types::oid;
+///
+/// let copy_out = conn.copy_out("COPY users (id, name) TO STDOUT (FORMAT binary)")?;
+/// let mut reader = BinaryCopyOutReader::new(copy_out, &[oid::INT4, oid::TEXT])?;
+/// while let Some((id, name)) = reader.read_row::<(i32, String)>()? {
+///     println!("{id}: {name}");
+/// }
+/// let rows = reader.finish()?;
+/// ```
+pub struct BinaryCopyOutReader<'a> {
+    copy_out: CopyOut<'a>,
+    decoder: BinaryCopyDecoder,
+}
+
+impl<'a> BinaryCopyOutReader<'a> {
+    /// Create a reader for columns of the given type OIDs.
+    ///
+    /// The COPY must use the binary format.
+    pub fn new(copy_out: CopyOut<'a>, oids: &[Oid]) -> Result<Self> {
+        if !copy_out.response().is_binary() {
+            return Err(Error::InvalidUsage(
+                "BinaryCopyOutReader requires COPY ... TO STDOUT (FORMAT binary)".into(),
+            ));
+        }
+        Ok(Self {
+            copy_out,
+            decoder: BinaryCopyDecoder::new(oids),
+        })
+    }
+
+    /// Read and decode the next row.
+    ///
+    /// Returns `None` once all rows have been read.
+    pub fn read_row<T: for<'r> FromRow<'r>>(&mut self) -> Result<Option<T>> {
+        loop {
+            if let Some(row) = self.decoder.next_row()? {
+                return Ok(Some(row));
+            }
+            match self.copy_out.next_chunk()? {
+                Some(chunk) => self.decoder.push(chunk),
+                None if self.decoder.is_finished() => return Ok(None),
+                None => {
+                    return Err(Error::Decode(
+                        "binary COPY data ended without trailer".into(),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Read and discard the remaining data.
+    ///
+    /// Returns the number of rows copied.
+    pub fn finish(self) -> Result<u64> {
+        self.copy_out.finish()
+    }
+}
+
+/// Typed reader for a `COPY ... TO STDOUT` operation in text or CSV format.
+///
+/// Rows are decoded with [`CopyTextDecoder`] for the given column type OIDs
+/// from the data of the wrapped [`CopyOut`].
+///
+/// # Example
+///
+/// ```ignore
+/// use zero_postgres::copy::CopyTextOptions;
+/// use zero_postgres::protocol::types::oid;
+///
+/// let copy_out = conn.copy_out("COPY users (id, name) TO STDOUT (FORMAT csv)")?;
+/// let mut reader = TextCopyOutReader::new(copy_out, CopyTextOptions::csv(), &[oid::INT4, oid::TEXT])?;
+/// while let Some((id, name)) = reader.read_row::<(i32, Option<String>)>()? {
+///     println!("{id}: {name:?}");
+/// }
+/// ```
+pub struct TextCopyOutReader<'a> {
+    copy_out: CopyOut<'a>,
+    decoder: CopyTextDecoder,
+}
+
+impl<'a> TextCopyOutReader<'a> {
+    /// Create a reader with the given options for columns of the given type OIDs.
+    ///
+    /// The options must match the COPY statement.
+    pub fn new(copy_out: CopyOut<'a>, options: CopyTextOptions, oids: &[Oid]) -> Result<Self> {
+        if copy_out.response().is_binary() {
+            return Err(Error::InvalidUsage(
+                "TextCopyOutReader requires COPY ... TO STDOUT in text or CSV format".into(),
+            ));
+        }
+        Ok(Self {
+            copy_out,
+            decoder: CopyTextDecoder::new(options, oids),
+        })
+    }
+
+    /// Read and decode the next row.
+    ///
+    /// Returns `None` once all rows have been read.
+    pub fn read_row<T: for<'r> FromRow<'r>>(&mut self) -> Result<Option<T>> {
+        loop {
+            if let Some(row) = self.decoder.next_row()? {
+                return Ok(Some(row));
+            }
+            match self.copy_out.next_chunk()? {
+                Some(chunk) => self.decoder.push(chunk),
+                None if self.decoder.is_empty() => return Ok(None),
+                None => {
+                    return Err(Error::Decode(
+                        "COPY data ended with an incomplete row".into(),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Read and discard the remaining data.
+    ///
+    /// Returns the number of rows copied.
+    pub fn finish(self) -> Result<u64> {
+        self.copy_out.finish()
+    }
+}
+
+impl Read for CopyOut<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.fill() {
+                Ok(true) => {}
+                Ok(false) => return Ok(0),
+                Err(Error::Io(e)) => return Err(e),
+                Err(e) => return Err(std::io::Error::other(e)),
+            }
+            let n = (self.len - self.pos).min(buf.len());
+            let src = self.conn.buffer_set.read_buffer.get(self.pos..self.pos + n);
+            if let (Some(dst), Some(src)) = (buf.get_mut(..n), src) {
+                dst.copy_from_slice(src);
+            }
+            self.pos += n;
+            // Skip empty CopyData messages, 0 means end of data
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+impl Drop for CopyOut<'_> {
+    fn drop(&mut self) {
+        while !self.done {
+            self.len = 0;
+            if self.fill().is_err() {
+                // A server error is followed by ReadyForQuery, other errors leave the stream unusable
+                if !self.done {
+                    self.conn.is_broken = true;
+                }
+                break;
+            }
+        }
+    }
+}
+
+/// Read messages until ReadyForQuery.
+///
+/// Returns the row count from the last CommandComplete, or the server error if one was received.
+pub(crate) fn read_until_ready(conn: &mut Conn) -> Result<Option<u64>> {
+    let mut rows = None;
+    let mut pending_error: Option<Error> = None;
+
+    loop {
+        conn.stream.read_message(&mut conn.buffer_set)?;
+        let type_byte = conn.buffer_set.type_byte;
+
+        if RawMessage::is_async_type(type_byte) {
+            continue;
+        }
+
+        match type_byte {
+            msg_type::COMMAND_COMPLETE => {
+                let complete = CommandComplete::parse(&conn.buffer_set.read_buffer)?;
+                rows = complete.rows_affected();
+            }
+            msg_type::ERROR_RESPONSE => {
+                let error = ErrorResponse::parse(&conn.buffer_set.read_buffer)?;
+                pending_error = Some(error.into_error());
+            }
+            msg_type::READY_FOR_QUERY => {
+                let ready = ReadyForQuery::parse(&conn.buffer_set.read_buffer)?;
+                conn.transaction_status = ready.transaction_status().unwrap_or_default();
+                return match pending_error {
+                    Some(e) => Err(e),
+                    None => Ok(rows),
+                };
+            }
+            _ => {
+                // Ignore other messages before ReadyForQuery
+            }
+        }
+    }
+}
@@ -1,6 +1,8 @@
 //! Synchronous PostgreSQL client.
 
+mod cancel;
 mod conn;
+mod copy;
 mod named_portal;
 mod pipeline;
 mod pool;
@@ -8,9 +10,13 @@ mod stream;
 mod transaction;
 mod unnamed_portal;
 
+pub use cancel::CancelToken;
 pub use conn::Conn;
+pub use copy::{
+    BinaryCopyInWriter, BinaryCopyOutReader, CopyIn, CopyOut, TextCopyInWriter, TextCopyOutReader,
+};
 pub use named_portal::NamedPortal;
 pub use pipeline::Pipeline;
-pub use pool::{Pool, PooledConn};
+pub use pool::{Pool, PoolHooks, PooledConn};
 pub use transaction::Transaction;
 pub use unnamed_portal::UnnamedPortal;
@@ -111,9 +111,11 @@ impl<'a> Pipeline<'a> {
             let _ = self.sync();
         }
 
-        // Drain remaining tickets
+        // Drain remaining tickets (the server skips them after an error)
         while self.claim_seq < self.queue_seq {
-            let _ = self.drain_one();
+            if !self.aborted {
+                self.drain_one();
+            }
             self.claim_seq += 1;
         }
 
@@ -128,12 +130,15 @@ impl<'a> Pipeline<'a> {
         };
         let mut handler = crate::handler::DropHandler::new();
 
-        let _ = match expectation {
+        let result = match expectation {
             Expectation::ParseBindExecute => self.claim_parse_bind_exec_inner(&mut handler),
             // When draining, we don't have the statement ref, but we also don't need row desc
             // since we're just dropping the results
             Expectation::BindExecute => self.claim_bind_exec_inner(&mut handler, None),
         };
+        if result.is_err() {
+            self.aborted = true;
+        }
     }
 
     // ========================================================================
@@ -231,6 +236,8 @@ impl<'a> Pipeline<'a> {
     /// After calling sync, you must claim all queued operations in order.
     /// The final ReadyForQuery message will be consumed when all operations
     /// are claimed.
+    ///
+    /// Starts the connection's query timeout, which covers the following claims.
     pub fn sync(&mut self) -> Result<()> {
         let result = self.sync_inner();
         if let Err(e) = &result
@@ -248,6 +255,7 @@ impl<'a> Pipeline<'a> {
             .write_all(&self.conn.buffer_set.write_buffer)?;
         self.conn.stream.flush()?;
         self.conn.buffer_set.write_buffer.clear();
+        self.conn.start_deadline();
         Ok(())
     }
 
@@ -255,7 +263,7 @@ impl<'a> Pipeline<'a> {
     fn finish(&mut self) -> Result<()> {
         // Wait for ReadyForQuery
         loop {
-            self.conn.stream.read_message(&mut self.conn.buffer_set)?;
+            self.conn.read_message()?;
             let type_byte = self.conn.buffer_set.type_byte;
 
             // Handle async messages
@@ -272,6 +280,7 @@ impl<'a> Pipeline<'a> {
             if type_byte == msg_type::READY_FOR_QUERY {
                 let ready = ReadyForQuery::parse(&self.conn.buffer_set.read_buffer)?;
                 self.conn.transaction_status = ready.transaction_status().unwrap_or_default();
+                self.conn.clear_deadline();
                 // Reset pipeline state
                 self.queue_seq = 0;
                 self.claim_seq = 0;
@@ -317,6 +326,7 @@ impl<'a> Pipeline<'a> {
             Some(Expectation::BindExecute) => self.claim_bind_exec_inner(handler, ticket.stmt),
             None => Err(Error::Protocol("unexpected expectation type".into())),
         };
+        let result = self.conn.map_timeout(result);
 
         if let Err(e) = &result {
             if e.is_connection_broken() {
@@ -515,7 +525,7 @@ impl<'a> Pipeline<'a> {
     /// Read the next message, skipping async messages and handling errors.
     fn read_next_message(&mut self) -> Result<()> {
         loop {
-            self.conn.stream.read_message(&mut self.conn.buffer_set)?;
+            self.conn.read_message()?;
             let type_byte = self.conn.buffer_set.type_byte;
 
             // Handle async messages
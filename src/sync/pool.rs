@@ -1,68 +1,525 @@
+//! Synchronous connection pool.
+
+use std::collections::VecDeque;
 use std::mem::ManuallyDrop;
 use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
-
-use crossbeam_queue::ArrayQueue;
-use std_semaphore::Semaphore;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::opts::Opts;
+use crate::pool_stats::{PoolCounters, PoolStats};
 
 use super::Conn;
 
+/// Hooks run by a [`Pool`] on its connections.
+///
+/// Returning an error from a hook closes the connection. All hooks do nothing
+/// by default.
+///
+/// # Example
+///
+/// ```ignore
+/// use zero_postgres::{PoolResetPolicy, Result};
+/// use zero_postgres::sync::{Conn, Pool, PoolHooks};
+///
+/// struct SearchPath;
+///
+/// impl PoolHooks for SearchPath {
+///     fn after_connect(&self, conn: &mut Conn) -> Result<()> {
+///         conn.query_drop("SET search_path TO app, public")?;
+///         Ok(())
+///     }
+/// }
+///
+/// // Keep the session state between checkouts instead of redoing it after `DISCARD ALL`
+/// opts.pool_reset_policy = PoolResetPolicy::RollbackIfInTx;
+/// let pool = Pool::with_hooks(opts, SearchPath);
+/// ```
+pub trait PoolHooks: Send + Sync {
+    /// Called after a new connection is opened, before it is used.
+    ///
+    /// An error is returned from `Pool::get`, or stops the reaper from opening
+    /// connections up to `pool_min_idle`.
+    ///
+    /// `DISCARD ALL` and `RESET ALL` undo session state such as `SET` (and
+    /// `DISCARD ALL` also `LISTEN` and prepared statements), so this is called
+    /// again on a returned connection after the `pool_reset_policy` statement
+    /// runs. With `PoolResetPolicy::RollbackIfInTx` or `None`, the state is kept
+    /// and this is called once per connection.
+    fn after_connect(&self, _conn: &mut Conn) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called before an idle connection is handed out. On error, the next idle
+    /// connection is tried, or a new one is opened.
+    fn before_acquire(&self, _conn: &mut Conn) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when a connection is returned to the pool, after it is reset with
+    /// `pool_reset_policy`. On error, the connection is not reused.
+    fn after_release(&self, _conn: &mut Conn) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Connection pool.
+///
+/// With `pool_max_lifetime`, `pool_idle_timeout` or `pool_min_idle` set, a reaper
+/// thread closes expired idle connections and opens new ones up to `pool_min_idle`.
+/// The thread exits when the pool is closed, or dropped with all its connections.
 pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
     opts: Opts,
-    conns: ArrayQueue<Conn>,
+    /// Idle connections, least recently returned first
+    conns: Mutex<VecDeque<IdleConn>>,
     semaphore: Option<Semaphore>,
+    counters: PoolCounters,
+    /// Notified when a connection is returned, with `conns` as its mutex
+    returned: Condvar,
+    hooks: Option<Box<dyn PoolHooks>>,
+    closed: AtomicBool,
+}
+
+/// Idle connection in the pool.
+struct IdleConn {
+    conn: Conn,
+    created_at: Instant,
+    idle_since: Instant,
 }
 
 impl Pool {
     pub fn new(opts: Opts) -> Self {
-        let semaphore = opts
-            .pool_max_concurrency
-            .map(|n| Semaphore::new(n as isize));
-        Self {
-            conns: ArrayQueue::new(opts.pool_max_idle_conn),
+        Self::build(opts, None)
+    }
+
+    /// Create a pool running `hooks` around connecting, checkout and check-in.
+    pub fn with_hooks(opts: Opts, hooks: impl PoolHooks + 'static) -> Self {
+        Self::build(opts, Some(Box::new(hooks)))
+    }
+
+    fn build(opts: Opts, hooks: Option<Box<dyn PoolHooks>>) -> Self {
+        let semaphore = opts.pool_max_concurrency.map(Semaphore::new);
+        let inner = Arc::new(PoolInner {
+            conns: Mutex::new(VecDeque::with_capacity(opts.pool_max_idle_conn)),
             opts,
             semaphore,
+            counters: PoolCounters::default(),
+            returned: Condvar::new(),
+            hooks,
+            closed: AtomicBool::new(false),
+        });
+        if let Some(interval) = inner.opts.pool_reaper_interval() {
+            let pool = Arc::downgrade(&inner);
+            let _ = thread::Builder::new()
+                .name("zero-postgres-pool-reaper".into())
+                .spawn(move || run_reaper(&pool, interval));
         }
+        Self { inner }
+    }
+
+    pub fn get(&self) -> Result<PooledConn> {
+        self.get_with_deadline(None)
+    }
+
+    /// Get a connection, waiting at most `timeout` for one to be returned when
+    /// `pool_max_concurrency` connections are in use.
+    ///
+    /// Returns `Error::PoolExhausted` if no connection became available in time.
+    pub fn get_timeout(&self, timeout: Duration) -> Result<PooledConn> {
+        self.get_with_deadline(Instant::now().checked_add(timeout))
+    }
+
+    /// Get a snapshot of the pool statistics.
+    pub fn stats(&self) -> PoolStats {
+        self.inner.counters.snapshot(self.inner.lock_idle().len())
     }
 
-    pub fn get(self: &Arc<Self>) -> Result<PooledConn> {
-        if let Some(sem) = &self.semaphore {
-            sem.acquire();
+    /// Close the pool, waiting at most `timeout` for checked-out connections to be returned.
+    ///
+    /// Later calls to `get`, callers waiting for a connection and callers still
+    /// checking one out fail with `Error::PoolClosed`. Idle connections are
+    /// closed now, and checked-out connections when they are returned.
+    ///
+    /// Returns false if connections were still checked out after `timeout`.
+    pub fn close(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        self.inner.closed.store(true, Ordering::SeqCst);
+        if let Some(sem) = &self.inner.semaphore {
+            sem.close();
         }
-        let conn = loop {
-            match self.conns.pop() {
-                Some(mut c) => {
-                    if c.ping().is_ok() {
-                        break c;
+        self.inner.close_idle();
+
+        let mut conns = self.inner.lock_idle();
+        while self.inner.counters.in_use() > 0 {
+            conns = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return false;
                     }
-                    // Connection dead, try next one
+                    self.inner
+                        .returned
+                        .wait_timeout(conns, remaining)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
                 }
-                None => break Conn::new(self.opts.clone())?,
-            }
-        };
+                None => self
+                    .inner
+                    .returned
+                    .wait(conns)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+        true
+    }
+
+    /// Returns true if the pool was closed.
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    fn get_with_deadline(&self, deadline: Option<Instant>) -> Result<PooledConn> {
+        if self.inner.is_closed() {
+            return Err(Error::PoolClosed);
+        }
+        let counters = &self.inner.counters;
+        if let Some(sem) = &self.inner.semaphore {
+            let waiting = counters.start_waiting();
+            let acquired = sem.acquire(deadline);
+            drop(waiting);
+            acquired?;
+        } else {
+            counters.record_wait(Duration::ZERO);
+        }
+
+        // Counted as in use before checking out, so `close` waits for the checkout
+        let in_use = InUse::new(&self.inner);
+        if self.inner.is_closed() {
+            return Err(Error::PoolClosed);
+        }
+        let (conn, created_at) = self.inner.checkout()?;
+        // Closed while checking out
+        if self.inner.is_closed() {
+            let _ = conn.close();
+            return Err(Error::PoolClosed);
+        }
         Ok(PooledConn {
             conn: ManuallyDrop::new(conn),
-            pool: Arc::clone(self),
+            created_at,
+            used: false,
+            in_use,
         })
     }
+}
+
+impl PoolInner {
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
 
-    fn check_in(&self, mut conn: Conn) {
+    fn lock_idle(&self) -> MutexGuard<'_, VecDeque<IdleConn>> {
+        self.conns.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn pop_idle(&self) -> Option<IdleConn> {
+        self.lock_idle().pop_front()
+    }
+
+    /// Add an idle connection. Returns false, dropping the connection, if
+    /// `pool_max_idle_conn` connections are idle.
+    fn push_idle(&self, idle: IdleConn) -> bool {
+        let mut conns = self.lock_idle();
+        if conns.len() >= self.opts.pool_max_idle_conn {
+            return false;
+        }
+        conns.push_back(idle);
+        true
+    }
+
+    /// Close all idle connections.
+    fn close_idle(&self) {
+        let conns = std::mem::take(&mut *self.lock_idle());
+        for idle in conns {
+            let _ = idle.conn.close();
+        }
+    }
+
+    /// Take a live idle connection, or open a new one.
+    fn checkout(&self) -> Result<(Conn, Instant)> {
+        loop {
+            let Some(idle) = self.pop_idle() else {
+                return Ok((self.connect()?, Instant::now()));
+            };
+            if self.is_expired(&idle, Instant::now()) {
+                continue;
+            }
+            let IdleConn {
+                mut conn,
+                created_at,
+                ..
+            } = idle;
+            if conn.ping().is_err() {
+                // Connection dead, try next one
+                self.counters.record_closed_on_error();
+                continue;
+            }
+            if let Some(hooks) = &self.hooks
+                && hooks.before_acquire(&mut conn).is_err()
+            {
+                self.counters.record_closed_on_error();
+                continue;
+            }
+            return Ok((conn, created_at));
+        }
+    }
+
+    /// Open a new connection and run the `after_connect` hook on it.
+    fn connect(&self) -> Result<Conn> {
+        let mut conn = Conn::new(self.opts.clone())?;
+        self.counters.record_created();
+        if let Some(hooks) = &self.hooks
+            && let Err(e) = hooks.after_connect(&mut conn)
+        {
+            self.counters.record_closed_on_error();
+            return Err(e);
+        }
+        Ok(conn)
+    }
+
+    fn check_in(&self, mut conn: Conn, created_at: Instant, used: bool) {
+        if self.is_closed() {
+            let _ = conn.close();
+            return;
+        }
         if conn.is_broken() {
+            self.counters.record_closed_on_error();
             return;
         }
-        if conn.query_drop("DISCARD ALL").is_err() {
+        if self.is_too_old(created_at, Instant::now()) {
             return;
         }
-        let _ = self.conns.push(conn);
+        // A connection that ran nothing since checkout is left as is
+        if used {
+            let policy = &self.opts.pool_reset_policy;
+            let status = conn.transaction_status();
+            if policy.rolls_back(status) && conn.query_drop("ROLLBACK").is_err() {
+                self.counters.record_closed_on_error();
+                return;
+            }
+            if let Some(sql) = policy.statement(status) {
+                if conn.query_drop(&sql).is_err() {
+                    self.counters.record_closed_on_error();
+                    return;
+                }
+                // Restore the session state undone by the reset
+                if let Some(hooks) = &self.hooks
+                    && hooks.after_connect(&mut conn).is_err()
+                {
+                    self.counters.record_closed_on_error();
+                    return;
+                }
+            }
+        }
+        if conn.in_transaction() {
+            // Not reset, so it can't be reused
+            return;
+        }
+        if let Some(hooks) = &self.hooks
+            && hooks.after_release(&mut conn).is_err()
+        {
+            self.counters.record_closed_on_error();
+            return;
+        }
+        let _ = self.push_idle(IdleConn {
+            conn,
+            created_at,
+            idle_since: Instant::now(),
+        });
+        // Closed while returning the connection
+        if self.is_closed() {
+            self.close_idle();
+        }
+    }
+
+    fn is_too_old(&self, created_at: Instant, now: Instant) -> bool {
+        self.opts
+            .pool_max_lifetime
+            .is_some_and(|max_lifetime| now.duration_since(created_at) >= max_lifetime)
+    }
+
+    /// Returns true if the idle connection exceeded `pool_max_lifetime` or `pool_idle_timeout`.
+    fn is_expired(&self, idle: &IdleConn, now: Instant) -> bool {
+        self.is_too_old(idle.created_at, now)
+            || self
+                .opts
+                .pool_idle_timeout
+                .is_some_and(|timeout| now.duration_since(idle.idle_since) >= timeout)
+    }
+
+    /// Close expired idle connections and open new ones up to `pool_min_idle`.
+    fn reap(&self) {
+        // Live connections stay in the queue, so `get` can take them meanwhile
+        let now = Instant::now();
+        let expired: VecDeque<IdleConn> = {
+            let mut conns = self.lock_idle();
+            let (expired, live) = std::mem::take(&mut *conns)
+                .into_iter()
+                .partition(|idle| self.is_expired(idle, now));
+            *conns = live;
+            expired
+        };
+        for idle in expired {
+            let _ = idle.conn.close();
+        }
+
+        while self.lock_idle().len() < self.opts.pool_min_idle && !self.is_closed() {
+            let Ok(conn) = self.connect() else {
+                break;
+            };
+            let created_at = Instant::now();
+            let idle = IdleConn {
+                conn,
+                created_at,
+                idle_since: created_at,
+            };
+            if !self.push_idle(idle) {
+                break;
+            }
+        }
+        // Closed while opening connections
+        if self.is_closed() {
+            self.close_idle();
+        }
+    }
+}
+
+/// Counting semaphore limiting the connections in use to `pool_max_concurrency`.
+struct Semaphore {
+    permits: Mutex<Permits>,
+    released: Condvar,
+}
+
+struct Permits {
+    available: usize,
+    closed: bool,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Self {
+            permits: Mutex::new(Permits {
+                available: permits,
+                closed: false,
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait for a permit until the deadline.
+    fn acquire(&self, deadline: Option<Instant>) -> Result<()> {
+        let mut permits = self.permits.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if permits.closed {
+                return Err(Error::PoolClosed);
+            }
+            if permits.available > 0 {
+                break;
+            }
+            permits = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        return Err(Error::PoolExhausted);
+                    }
+                    self.released
+                        .wait_timeout(permits, timeout)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .released
+                    .wait(permits)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+        permits.available -= 1;
+        Ok(())
+    }
+
+    /// Fail current and future waiters with `Error::PoolClosed`.
+    fn close(&self) {
+        self.permits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .closed = true;
+        self.released.notify_all();
+    }
+
+    fn release(&self) {
+        self.permits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .available += 1;
+        self.released.notify_one();
+    }
+}
+
+/// Reap the pool every `interval` until it is closed or dropped.
+fn run_reaper(pool: &Weak<PoolInner>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let Some(pool) = pool.upgrade() else {
+            break;
+        };
+        if pool.is_closed() {
+            break;
+        }
+        pool.reap();
+    }
+}
+
+/// A connection slot in use, from before checkout until the connection is
+/// returned. Dropping it releases the slot and wakes `Pool::close`.
+struct InUse {
+    pool: Arc<PoolInner>,
+}
+
+impl InUse {
+    /// Take a slot after acquiring a permit, if `pool_max_concurrency` is set.
+    fn new(pool: &Arc<PoolInner>) -> Self {
+        pool.counters.checked_out();
+        Self {
+            pool: Arc::clone(pool),
+        }
+    }
+}
+
+impl Drop for InUse {
+    fn drop(&mut self) {
+        self.pool.counters.checked_in();
+        // Taking the lock orders this after a `close` checking `in_use`
+        drop(self.pool.lock_idle());
+        self.pool.returned.notify_all();
+        if let Some(sem) = &self.pool.semaphore {
+            sem.release();
+        }
     }
 }
 
 pub struct PooledConn {
-    pool: Arc<Pool>,
     conn: ManuallyDrop<Conn>,
+    created_at: Instant,
+    /// Whether the connection was borrowed mutably, which any query needs
+    used: bool,
+    /// Released after the connection is checked in
+    in_use: InUse,
 }
 
 impl Deref for PooledConn {
@@ -74,6 +531,7 @@ impl Deref for PooledConn {
 
 impl DerefMut for PooledConn {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        self.used = true;
         &mut self.conn
     }
 }
@@ -82,9 +540,6 @@ impl Drop for PooledConn {
     fn drop(&mut self) {
         // SAFETY: conn is never accessed after this
         let conn = unsafe { ManuallyDrop::take(&mut self.conn) };
-        self.pool.check_in(conn);
-        if let Some(sem) = &self.pool.semaphore {
-            sem.release();
-        }
+        self.in_use.pool.check_in(conn, self.created_at, self.used);
     }
 }
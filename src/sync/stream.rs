@@ -1,14 +1,19 @@
-use std::io::{BufReader, Read, Write};
-use std::net::TcpStream;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
 use std::os::unix::net::UnixStream;
+use std::time::Duration;
 
-#[cfg(feature = "sync-tls")]
-use native_tls::TlsStream;
+#[cfg(all(feature = "sync-tls", not(feature = "sync-rustls")))]
+type TlsStream = native_tls::TlsStream<TcpStream>;
+#[cfg(feature = "sync-rustls")]
+type TlsStream = Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>;
+
+use crate::opts::Opts;
 
 pub enum Stream {
     Tcp(BufReader<TcpStream>),
-    #[cfg(feature = "sync-tls")]
-    Tls(BufReader<TlsStream<TcpStream>>),
+    #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
+    Tls(BufReader<TlsStream>),
     Unix(BufReader<UnixStream>),
 }
 
@@ -21,24 +26,44 @@ impl Stream {
         Self::Unix(BufReader::new(stream))
     }
 
-    /// Upgrade a TCP stream to TLS.
+    /// Connect to the Unix socket or TCP address in the options.
+    ///
+    /// Applies the connect timeout, TCP keepalive and socket timeouts from the options.
+    pub fn connect(opts: &Opts) -> Result<Self, crate::error::Error> {
+        if let Some(socket_path) = &opts.socket {
+            return Ok(Self::connect_unix(socket_path, opts)?);
+        }
+        if opts.host.is_empty() {
+            return Err(crate::error::Error::InvalidUsage("host is empty".into()));
+        }
+        let addr = format!("{}:{}", opts.host, opts.port);
+        let tcp = match opts.connect_timeout {
+            Some(timeout) => connect_timeout(&addr, timeout)?,
+            None => TcpStream::connect(&addr)?,
+        };
+        opts.configure_tcp(socket2::SockRef::from(&tcp))?;
+        tcp.set_read_timeout(opts.read_timeout)?;
+        tcp.set_write_timeout(opts.write_timeout)?;
+        Ok(Self::tcp(tcp))
+    }
+
+    /// Connect to a Unix socket, applying the socket timeouts from the options.
+    pub fn connect_unix(path: &str, opts: &Opts) -> std::io::Result<Self> {
+        let unix = UnixStream::connect(path)?;
+        unix.set_read_timeout(opts.read_timeout)?;
+        unix.set_write_timeout(opts.write_timeout)?;
+        Ok(Self::unix(unix))
+    }
+
+    /// Upgrade a TCP stream to TLS, using the SSL mode and certificates in `opts`.
     ///
     /// Returns an error if this is not a TCP stream or if the TLS handshake fails.
-    #[cfg(feature = "sync-tls")]
-    pub fn upgrade_to_tls(self, host: &str) -> Result<Self, crate::error::Error> {
+    #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
+    pub fn upgrade_to_tls(self, opts: &Opts) -> Result<Self, crate::error::Error> {
         match self {
             Stream::Tcp(buf_reader) => {
-                let tcp_stream = buf_reader.into_inner();
-                let connector = native_tls::TlsConnector::new()?;
-                let tls_stream = connector.connect(host, tcp_stream).map_err(|e| match e {
-                    native_tls::HandshakeError::Failure(e) => crate::error::Error::Tls(e),
-                    native_tls::HandshakeError::WouldBlock(_) => {
-                        crate::error::Error::Io(std::io::Error::new(
-                            std::io::ErrorKind::WouldBlock,
-                            "TLS handshake would block",
-                        ))
-                    }
-                })?;
+                let tls_stream = tls_handshake(opts, buf_reader.into_inner())?;
+                crate::tls::check_alpn(opts, tls_alpn(&tls_stream).as_deref())?;
                 Ok(Stream::Tls(BufReader::new(tls_stream)))
             }
             Stream::Tls(_) => Err(crate::error::Error::InvalidUsage(
@@ -50,11 +75,23 @@ impl Stream {
         }
     }
 
+    /// Get the `tls-server-end-point` channel binding data of a TLS stream.
+    ///
+    /// Returns `None` if this is not a TLS stream or the hash of the server
+    /// certificate can't be computed.
+    #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
+    pub fn tls_server_end_point(&self) -> Option<Vec<u8>> {
+        match self {
+            Stream::Tls(r) => tls_server_end_point(r.get_ref()),
+            _ => None,
+        }
+    }
+
     pub fn read_u8(&mut self) -> std::io::Result<u8> {
         let mut buf = [0u8; 1];
         let n = match self {
             Stream::Tcp(r) => r.read(&mut buf),
-            #[cfg(feature = "sync-tls")]
+            #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
             Stream::Tls(r) => r.read(&mut buf),
             Stream::Unix(r) => r.read(&mut buf),
         }?;
@@ -83,10 +120,85 @@ impl Stream {
         Ok(())
     }
 
+    /// Wait until data is available to read, up to `timeout`.
+    ///
+    /// Returns `Ok(false)` if the timeout elapsed. No data is consumed, so a
+    /// subsequent `read_message` is not affected by the timeout.
+    pub fn wait_readable(&mut self, timeout: Duration) -> std::io::Result<bool> {
+        let buffered = match self {
+            Stream::Tcp(r) => !r.buffer().is_empty(),
+            #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
+            Stream::Tls(r) => !r.buffer().is_empty(),
+            Stream::Unix(r) => !r.buffer().is_empty(),
+        };
+        if buffered {
+            return Ok(true);
+        }
+
+        // A shorter socket read timeout still applies and breaks the connection
+        let read_timeout = self.read_timeout()?;
+        let socket_timeout = read_timeout.filter(|t| *t < timeout);
+
+        self.set_read_timeout(Some(socket_timeout.unwrap_or(timeout)))?;
+        let result = match self {
+            Stream::Tcp(r) => r.fill_buf().map(|_| ()),
+            #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
+            Stream::Tls(r) => r.fill_buf().map(|_| ()),
+            Stream::Unix(r) => r.fill_buf().map(|_| ()),
+        };
+        self.set_read_timeout(read_timeout)?;
+        match result {
+            Ok(()) => Ok(true),
+            Err(e)
+                if socket_timeout.is_none()
+                    && matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_timeout(&self) -> std::io::Result<Option<Duration>> {
+        match self {
+            Stream::Tcp(r) => r.get_ref().read_timeout(),
+            #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
+            Stream::Tls(r) => tls_tcp(r.get_ref()).read_timeout(),
+            Stream::Unix(r) => r.get_ref().read_timeout(),
+        }
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(r) => r.get_ref().set_read_timeout(timeout),
+            #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
+            Stream::Tls(r) => tls_tcp(r.get_ref()).set_read_timeout(timeout),
+            Stream::Unix(r) => r.get_ref().set_read_timeout(timeout),
+        }
+    }
+
+    /// Set the socket read and write timeouts.
+    pub fn set_timeouts(
+        &mut self,
+        read: Option<Duration>,
+        write: Option<Duration>,
+    ) -> std::io::Result<()> {
+        self.set_read_timeout(read)?;
+        match self {
+            Stream::Tcp(r) => r.get_ref().set_write_timeout(write),
+            #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
+            Stream::Tls(r) => tls_tcp(r.get_ref()).set_write_timeout(write),
+            Stream::Unix(r) => r.get_ref().set_write_timeout(write),
+        }
+    }
+
     fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
         match self {
             Stream::Tcp(r) => r.read_exact(buf),
-            #[cfg(feature = "sync-tls")]
+            #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
             Stream::Tls(r) => r.read_exact(buf),
             Stream::Unix(r) => r.read_exact(buf),
         }
@@ -95,7 +207,7 @@ impl Stream {
     pub fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
         match self {
             Stream::Tcp(r) => r.get_mut().write_all(buf),
-            #[cfg(feature = "sync-tls")]
+            #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
             Stream::Tls(r) => r.get_mut().write_all(buf),
             Stream::Unix(r) => r.get_mut().write_all(buf),
         }
@@ -105,7 +217,7 @@ impl Stream {
     pub fn flush(&mut self) -> std::io::Result<()> {
         match self {
             Stream::Tcp(r) => r.get_mut().flush(),
-            #[cfg(feature = "sync-tls")]
+            #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
             Stream::Tls(r) => r.get_mut().flush(),
             Stream::Unix(r) => r.get_mut().flush(),
         }
@@ -118,10 +230,8 @@ impl Stream {
                 .peer_addr()
                 .map(|addr| addr.ip().is_loopback())
                 .unwrap_or(false),
-            #[cfg(feature = "sync-tls")]
-            Self::Tls(r) => r
-                .get_ref()
-                .get_ref()
+            #[cfg(any(feature = "sync-tls", feature = "sync-rustls"))]
+            Self::Tls(r) => tls_tcp(r.get_ref())
                 .peer_addr()
                 .map(|addr| addr.ip().is_loopback())
                 .unwrap_or(false),
@@ -129,3 +239,82 @@ impl Stream {
         }
     }
 }
+
+/// Perform the TLS handshake with native-tls.
+#[cfg(all(feature = "sync-tls", not(feature = "sync-rustls")))]
+fn tls_handshake(opts: &Opts, tcp: TcpStream) -> Result<TlsStream, crate::error::Error> {
+    let connector = crate::tls::native_tls_connector(opts)?;
+    connector.connect(&opts.host, tcp).map_err(|e| match e {
+        native_tls::HandshakeError::Failure(e) => crate::error::Error::Tls(e),
+        native_tls::HandshakeError::WouldBlock(_) => crate::error::Error::Io(std::io::Error::new(
+            std::io::ErrorKind::WouldBlock,
+            "TLS handshake would block",
+        )),
+    })
+}
+
+/// Perform the TLS handshake with rustls.
+#[cfg(feature = "sync-rustls")]
+fn tls_handshake(opts: &Opts, mut tcp: TcpStream) -> Result<TlsStream, crate::error::Error> {
+    let config = crate::tls::rustls_client_config(opts)?;
+    let mut conn = rustls::ClientConnection::new(config, crate::tls::server_name(opts)?)?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut tcp)
+            .map_err(crate::tls::handshake_error)?;
+    }
+    Ok(Box::new(rustls::StreamOwned::new(conn, tcp)))
+}
+
+/// Get the protocol selected by ALPN with native-tls.
+#[cfg(all(feature = "sync-tls", not(feature = "sync-rustls")))]
+fn tls_alpn(stream: &TlsStream) -> Option<Vec<u8>> {
+    stream.negotiated_alpn().ok().flatten()
+}
+
+/// Get the protocol selected by ALPN with rustls.
+#[cfg(feature = "sync-rustls")]
+fn tls_alpn(stream: &TlsStream) -> Option<Vec<u8>> {
+    stream.conn.alpn_protocol().map(<[u8]>::to_vec)
+}
+
+/// Get the `tls-server-end-point` channel binding data with native-tls.
+#[cfg(all(feature = "sync-tls", not(feature = "sync-rustls")))]
+fn tls_server_end_point(stream: &TlsStream) -> Option<Vec<u8>> {
+    stream.tls_server_end_point().ok().flatten()
+}
+
+/// Get the `tls-server-end-point` channel binding data with rustls.
+#[cfg(feature = "sync-rustls")]
+fn tls_server_end_point(stream: &TlsStream) -> Option<Vec<u8>> {
+    let cert = stream.conn.peer_certificates()?.first()?;
+    crate::tls::tls_server_end_point(cert)
+}
+
+/// Get the TCP stream under a TLS stream.
+#[cfg(all(feature = "sync-tls", not(feature = "sync-rustls")))]
+fn tls_tcp(stream: &TlsStream) -> &TcpStream {
+    stream.get_ref()
+}
+
+/// Get the TCP stream under a TLS stream.
+#[cfg(feature = "sync-rustls")]
+fn tls_tcp(stream: &TlsStream) -> &TcpStream {
+    &stream.sock
+}
+
+/// Connect to the first reachable address, with a timeout for each attempt.
+fn connect_timeout(addr: &str, timeout: Duration) -> std::io::Result<TcpStream> {
+    let mut last_error = None;
+    for socket_addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_addr, timeout) {
+            Ok(tcp) => return Ok(tcp),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("could not resolve {}", addr),
+        )
+    }))
+}
@@ -0,0 +1,215 @@
+//! `tls-server-end-point` channel binding data (RFC 5929).
+//!
+//! The channel binding data is the hash of the DER server certificate, using the
+//! hash function of the certificate's signature algorithm. MD5 and SHA-1 are
+//! replaced by SHA-256.
+
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+
+/// Signature algorithm OIDs (DER content bytes) and their hash functions.
+const SIGNATURE_ALGORITHMS: &[(&[u8], HashAlgorithm)] = &[
+    // md5WithRSAEncryption
+    (&[42, 134, 72, 134, 247, 13, 1, 1, 4], HashAlgorithm::Sha256),
+    // sha1WithRSAEncryption
+    (&[42, 134, 72, 134, 247, 13, 1, 1, 5], HashAlgorithm::Sha256),
+    // sha256WithRSAEncryption
+    (
+        &[42, 134, 72, 134, 247, 13, 1, 1, 11],
+        HashAlgorithm::Sha256,
+    ),
+    // sha384WithRSAEncryption
+    (
+        &[42, 134, 72, 134, 247, 13, 1, 1, 12],
+        HashAlgorithm::Sha384,
+    ),
+    // sha512WithRSAEncryption
+    (
+        &[42, 134, 72, 134, 247, 13, 1, 1, 13],
+        HashAlgorithm::Sha512,
+    ),
+    // sha224WithRSAEncryption
+    (
+        &[42, 134, 72, 134, 247, 13, 1, 1, 14],
+        HashAlgorithm::Sha224,
+    ),
+    // ecdsa-with-SHA1
+    (&[42, 134, 72, 206, 61, 4, 1], HashAlgorithm::Sha256),
+    // ecdsa-with-SHA224
+    (&[42, 134, 72, 206, 61, 4, 3, 1], HashAlgorithm::Sha224),
+    // ecdsa-with-SHA256
+    (&[42, 134, 72, 206, 61, 4, 3, 2], HashAlgorithm::Sha256),
+    // ecdsa-with-SHA384
+    (&[42, 134, 72, 206, 61, 4, 3, 3], HashAlgorithm::Sha384),
+    // ecdsa-with-SHA512
+    (&[42, 134, 72, 206, 61, 4, 3, 4], HashAlgorithm::Sha512),
+];
+
+/// RSASSA-PSS, whose hash function is in the algorithm parameters.
+const RSASSA_PSS: &[u8] = &[42, 134, 72, 134, 247, 13, 1, 1, 10];
+
+/// Hash algorithm OIDs (DER content bytes) used in RSASSA-PSS parameters.
+const HASH_ALGORITHMS: &[(&[u8], HashAlgorithm)] = &[
+    // sha1
+    (&[43, 14, 3, 2, 26], HashAlgorithm::Sha256),
+    // sha256
+    (&[96, 134, 72, 1, 101, 3, 4, 2, 1], HashAlgorithm::Sha256),
+    // sha384
+    (&[96, 134, 72, 1, 101, 3, 4, 2, 2], HashAlgorithm::Sha384),
+    // sha512
+    (&[96, 134, 72, 1, 101, 3, 4, 2, 3], HashAlgorithm::Sha512),
+    // sha224
+    (&[96, 134, 72, 1, 101, 3, 4, 2, 4], HashAlgorithm::Sha224),
+];
+
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_OID: u8 = 0x06;
+/// `[0]` explicit tag of the RSASSA-PSS `hashAlgorithm` parameter
+const TAG_PSS_HASH: u8 = 0xa0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HashAlgorithm {
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+/// Compute the `tls-server-end-point` channel binding data of a DER certificate.
+///
+/// Returns `None` if the certificate can't be parsed or its signature algorithm
+/// has no defined hash function (e.g. Ed25519).
+pub(crate) fn tls_server_end_point(cert: &[u8]) -> Option<Vec<u8>> {
+    let hash = match signature_hash_algorithm(cert)? {
+        HashAlgorithm::Sha224 => Sha224::digest(cert).to_vec(),
+        HashAlgorithm::Sha256 => Sha256::digest(cert).to_vec(),
+        HashAlgorithm::Sha384 => Sha384::digest(cert).to_vec(),
+        HashAlgorithm::Sha512 => Sha512::digest(cert).to_vec(),
+    };
+    Some(hash)
+}
+
+/// Find the hash function of the certificate's `signatureAlgorithm`.
+fn signature_hash_algorithm(cert: &[u8]) -> Option<HashAlgorithm> {
+    // Certificate ::= SEQUENCE { tbsCertificate, signatureAlgorithm, signatureValue }
+    let (certificate, _) = read_element(cert, TAG_SEQUENCE)?;
+    let (_tbs_certificate, rest) = read_element(certificate, TAG_SEQUENCE)?;
+    let (algorithm_identifier, _) = read_element(rest, TAG_SEQUENCE)?;
+
+    // AlgorithmIdentifier ::= SEQUENCE { algorithm OID, parameters ANY OPTIONAL }
+    let (oid, parameters) = read_element(algorithm_identifier, TAG_OID)?;
+    if oid == RSASSA_PSS {
+        return pss_hash_algorithm(parameters);
+    }
+    lookup(SIGNATURE_ALGORITHMS, oid)
+}
+
+/// Find the hash function of RSASSA-PSS parameters.
+fn pss_hash_algorithm(parameters: &[u8]) -> Option<HashAlgorithm> {
+    // RSASSA-PSS-params ::= SEQUENCE { hashAlgorithm [0] AlgorithmIdentifier DEFAULT sha1, ... }
+    let (params, _) = read_element(parameters, TAG_SEQUENCE)?;
+    let Some((hash_algorithm, _)) = read_element(params, TAG_PSS_HASH) else {
+        return Some(HashAlgorithm::Sha256);
+    };
+    let (algorithm_identifier, _) = read_element(hash_algorithm, TAG_SEQUENCE)?;
+    let (oid, _) = read_element(algorithm_identifier, TAG_OID)?;
+    lookup(HASH_ALGORITHMS, oid)
+}
+
+fn lookup(table: &[(&[u8], HashAlgorithm)], oid: &[u8]) -> Option<HashAlgorithm> {
+    table
+        .iter()
+        .find(|(algorithm, _)| *algorithm == oid)
+        .map(|(_, hash)| *hash)
+}
+
+/// Read a DER element with the expected tag, returning its content and the rest of the input.
+fn read_element(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&actual, rest) = input.split_first()?;
+    if actual != tag {
+        return None;
+    }
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (usize::from(first), rest)
+    } else {
+        let num_bytes = usize::from(first & 0x7f);
+        if num_bytes == 0 || num_bytes > 4 || rest.len() < num_bytes {
+            return None;
+        }
+        let (len_bytes, rest) = rest.split_at(num_bytes);
+        let len = len_bytes
+            .iter()
+            .fold(0_usize, |len, &b| (len << 8) | usize::from(b));
+        (len, rest)
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode a DER element (content shorter than 64 KiB).
+    fn element(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        match content.len() {
+            len @ 0..0x80 => out.push(len as u8),
+            len @ 0x80..0x100 => out.extend([0x81, len as u8]),
+            len => out.extend([0x82, (len >> 8) as u8, len as u8]),
+        }
+        out.extend_from_slice(content);
+        out
+    }
+
+    /// Build a certificate-shaped DER structure with the given signature algorithm.
+    fn certificate(algorithm_identifier: &[u8]) -> Vec<u8> {
+        let tbs = element(TAG_SEQUENCE, &[0x02; 200]);
+        let signature = element(0x03, &[0, 1, 2, 3]);
+        let content = [tbs.as_slice(), algorithm_identifier, &signature].concat();
+        element(TAG_SEQUENCE, &content)
+    }
+
+    fn algorithm(oid: &[u8], parameters: &[u8]) -> Vec<u8> {
+        element(
+            TAG_SEQUENCE,
+            &[&element(TAG_OID, oid)[..], parameters].concat(),
+        )
+    }
+
+    #[test]
+    fn test_signature_hash_algorithm() {
+        let null = [0x05, 0x00];
+        for (oid, expected) in SIGNATURE_ALGORITHMS {
+            let cert = certificate(&algorithm(oid, &null));
+            assert_eq!(signature_hash_algorithm(&cert), Some(*expected));
+        }
+
+        // ecdsa-with-SHA384 without parameters
+        let cert = certificate(&algorithm(&[42, 134, 72, 206, 61, 4, 3, 3], &[]));
+        let hash = tls_server_end_point(&cert).unwrap();
+        assert_eq!(hash, Sha384::digest(&cert).to_vec());
+
+        // Ed25519 has no hash function
+        let cert = certificate(&algorithm(&[43, 101, 112], &[]));
+        assert_eq!(tls_server_end_point(&cert), None);
+
+        assert_eq!(tls_server_end_point(&[]), None);
+        assert_eq!(tls_server_end_point(&[0x30, 0x82, 0x01]), None);
+    }
+
+    #[test]
+    fn test_pss_hash_algorithm() {
+        let sha512 = algorithm(&[96, 134, 72, 1, 101, 3, 4, 2, 3], &[0x05, 0x00]);
+        let params = element(TAG_SEQUENCE, &element(TAG_PSS_HASH, &sha512));
+        let cert = certificate(&algorithm(RSASSA_PSS, &params));
+        assert_eq!(signature_hash_algorithm(&cert), Some(HashAlgorithm::Sha512));
+
+        // hashAlgorithm defaults to SHA-1, replaced by SHA-256
+        let params = element(TAG_SEQUENCE, &[]);
+        let cert = certificate(&algorithm(RSASSA_PSS, &params));
+        assert_eq!(signature_hash_algorithm(&cert), Some(HashAlgorithm::Sha256));
+    }
+}
@@ -0,0 +1,70 @@
+//! TLS configuration for the native-tls and rustls backends.
+//!
+//! `Allow`, `Prefer` and `Require` only encrypt the connection, unless `ssl_root_cert`
+//! is set, in which case the server certificate is verified as with `VerifyCa`.
+//! `VerifyCa` checks the certificate chain, and `VerifyFull` also checks that the
+//! certificate matches the host name.
+//!
+//! Both backends offer ALPN `postgresql`, which the server requires for direct
+//! TLS negotiation (`SslNegotiation::Direct`).
+//!
+//! When both backends are enabled for a client, rustls is used.
+
+#[cfg(any(feature = "sync-rustls", feature = "tokio-rustls"))]
+mod channel_binding;
+#[cfg(any(
+    all(feature = "sync-tls", not(feature = "sync-rustls")),
+    all(feature = "tokio-tls", not(feature = "tokio-rustls"))
+))]
+mod native;
+#[cfg(any(feature = "sync-rustls", feature = "tokio-rustls"))]
+mod rustls_config;
+
+#[cfg(any(feature = "sync-rustls", feature = "tokio-rustls"))]
+pub(crate) use channel_binding::tls_server_end_point;
+#[cfg(any(
+    all(feature = "sync-tls", not(feature = "sync-rustls")),
+    all(feature = "tokio-tls", not(feature = "tokio-rustls"))
+))]
+pub(crate) use native::native_tls_connector;
+#[cfg(any(feature = "sync-rustls", feature = "tokio-rustls"))]
+pub(crate) use rustls_config::{handshake_error, rustls_client_config, server_name};
+
+use crate::error::{Error, Result};
+use crate::opts::{Opts, SslMode, SslNegotiation};
+
+/// ALPN protocol name of the PostgreSQL protocol.
+const ALPN_POSTGRESQL: &str = "postgresql";
+
+/// Check the protocol selected by ALPN after the TLS handshake.
+///
+/// With direct negotiation the server must select `postgresql`, so that a TLS
+/// server of another protocol is not mistaken for PostgreSQL.
+pub(crate) fn check_alpn(opts: &Opts, alpn: Option<&[u8]>) -> Result<()> {
+    if opts.ssl_negotiation == SslNegotiation::Direct && alpn != Some(ALPN_POSTGRESQL.as_bytes()) {
+        return Err(Error::Protocol(
+            "direct TLS connection was established without ALPN protocol \"postgresql\"".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns true if the server certificate chain must be verified.
+fn verify_chain(opts: &Opts) -> bool {
+    match opts.ssl_mode {
+        SslMode::VerifyCa | SslMode::VerifyFull => true,
+        SslMode::Disable | SslMode::Allow | SslMode::Prefer | SslMode::Require => {
+            opts.ssl_root_cert.is_some()
+        }
+    }
+}
+
+/// Read a certificate, key or CRL file named by the `key` option.
+fn read_file(key: &str, path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| {
+        Error::Io(std::io::Error::new(
+            e.kind(),
+            format!("could not read {} file \"{}\": {}", key, path, e),
+        ))
+    })
+}
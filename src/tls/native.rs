@@ -0,0 +1,91 @@
+//! native-tls connector configuration.
+
+use crate::error::{Error, Result};
+use crate::opts::{Opts, SslMode};
+
+use super::{ALPN_POSTGRESQL, read_file, verify_chain};
+
+/// Build a native-tls connector for the SSL mode and certificate options.
+pub(crate) fn native_tls_connector(opts: &Opts) -> Result<native_tls::TlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+
+    builder.danger_accept_invalid_certs(!verify_chain(opts));
+    builder.danger_accept_invalid_hostnames(opts.ssl_mode != SslMode::VerifyFull);
+    builder.request_alpns(&[ALPN_POSTGRESQL]);
+
+    if let Some(path) = opts
+        .ssl_root_cert
+        .as_deref()
+        .filter(|path| *path != "system")
+    {
+        let pem = read_file("sslrootcert", path)?;
+        for cert in split_pem_certificates(&pem) {
+            builder.add_root_certificate(native_tls::Certificate::from_pem(cert)?);
+        }
+        builder.disable_built_in_roots(true);
+    }
+
+    match (&opts.ssl_cert, &opts.ssl_key) {
+        (Some(cert), Some(key)) => {
+            let cert = read_file("sslcert", cert)?;
+            let key = read_file("sslkey", key)?;
+            builder.identity(native_tls::Identity::from_pkcs8(&cert, &key)?);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(Error::InvalidUsage(
+                "sslcert and sslkey must be set together".into(),
+            ));
+        }
+    }
+
+    if opts.ssl_crl.is_some() {
+        return Err(Error::Unsupported(
+            "sslcrl is not supported by the native-tls backend".into(),
+        ));
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Split a PEM bundle into individual certificates.
+fn split_pem_certificates(pem: &[u8]) -> Vec<&[u8]> {
+    const END: &[u8] = b"-----END CERTIFICATE-----";
+
+    let mut certs = Vec::new();
+    let mut rest = pem;
+    while let Some(end) = memchr::memmem::find(rest, END) {
+        let (cert, tail) = rest.split_at(end + END.len());
+        certs.push(cert);
+        rest = tail;
+    }
+    certs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_pem_certificates() {
+        let pem = b"-----BEGIN CERTIFICATE-----\nAAA\n-----END CERTIFICATE-----\n\
+                    -----BEGIN CERTIFICATE-----\nBBB\n-----END CERTIFICATE-----\n";
+        let certs = split_pem_certificates(pem);
+        assert_eq!(certs.len(), 2);
+        assert!(certs[0].ends_with(b"-----END CERTIFICATE-----"));
+        assert!(certs[1].windows(3).any(|w| w == b"BBB"));
+        assert!(split_pem_certificates(b"").is_empty());
+    }
+
+    #[test]
+    fn test_mismatched_client_cert() {
+        let opts = Opts {
+            ssl_cert: Some("client.crt".into()),
+            ..Opts::default()
+        };
+        assert!(matches!(
+            native_tls_connector(&opts),
+            Err(Error::InvalidUsage(_))
+        ));
+    }
+}
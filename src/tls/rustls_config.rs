@@ -0,0 +1,274 @@
+//! rustls client configuration.
+
+use std::sync::Arc;
+
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{
+    CertificateDer, CertificateRevocationListDer, PrivateKeyDer, ServerName, UnixTime,
+};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+use crate::error::{Error, Result};
+use crate::opts::{Opts, SslMode};
+
+use super::{ALPN_POSTGRESQL, read_file, verify_chain};
+
+/// Get the rustls client configuration for the SSL mode and certificate options.
+///
+/// Returns `opts.rustls_config` if set, with ALPN `postgresql` added if it has no
+/// ALPN protocols.
+pub(crate) fn rustls_client_config(opts: &Opts) -> Result<Arc<ClientConfig>> {
+    if let Some(config) = &opts.rustls_config {
+        if !config.alpn_protocols.is_empty() {
+            return Ok(Arc::clone(config));
+        }
+        let mut config = ClientConfig::clone(config);
+        config.alpn_protocols = vec![ALPN_POSTGRESQL.as_bytes().to_vec()];
+        return Ok(Arc::new(config));
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let verifier: Arc<dyn ServerCertVerifier> = if verify_chain(opts) {
+        let mut builder = WebPkiServerVerifier::builder_with_provider(
+            Arc::new(root_store(opts)?),
+            Arc::clone(&provider),
+        );
+        if let Some(path) = &opts.ssl_crl {
+            let pem = read_file("sslcrl", path)?;
+            let crls = CertificateRevocationListDer::pem_slice_iter(&pem)
+                .collect::<core::result::Result<Vec<_>, _>>()
+                .map_err(|e| pem_error("sslcrl", e))?;
+            builder = builder.with_crls(crls).only_check_end_entity_revocation();
+        }
+        let webpki = builder.build().map_err(|e| {
+            Error::InvalidUsage(format!("invalid TLS verifier configuration: {}", e))
+        })?;
+        if opts.ssl_mode == SslMode::VerifyFull {
+            webpki
+        } else {
+            Arc::new(NoHostnameVerification(webpki))
+        }
+    } else {
+        Arc::new(NoVerification(Arc::clone(&provider)))
+    };
+
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+
+    let mut config = match (&opts.ssl_cert, &opts.ssl_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert_pem = read_file("sslcert", cert_path)?;
+            let key_pem = read_file("sslkey", key_path)?;
+            let certs = CertificateDer::pem_slice_iter(&cert_pem)
+                .collect::<core::result::Result<Vec<_>, _>>()
+                .map_err(|e| pem_error("sslcert", e))?;
+            let private_key =
+                PrivateKeyDer::from_pem_slice(&key_pem).map_err(|e| pem_error("sslkey", e))?;
+            builder.with_client_auth_cert(certs, private_key)?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => {
+            return Err(Error::InvalidUsage(
+                "sslcert and sslkey must be set together".into(),
+            ));
+        }
+    };
+
+    config.alpn_protocols = vec![ALPN_POSTGRESQL.as_bytes().to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+/// Get the server name to send in SNI and verify the certificate against.
+pub(crate) fn server_name(opts: &Opts) -> Result<ServerName<'static>> {
+    ServerName::try_from(opts.host.clone())
+        .map_err(|e| Error::InvalidUsage(format!("invalid TLS server name {}: {}", opts.host, e)))
+}
+
+/// Convert an I/O error from the TLS handshake, surfacing rustls errors.
+pub(crate) fn handshake_error(e: std::io::Error) -> Error {
+    match e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+    {
+        Some(tls_error) => Error::Rustls(tls_error.clone()),
+        None => Error::Io(e),
+    }
+}
+
+/// Trusted roots: `sslrootcert`, or the bundled webpki roots for `"system"` and `None`.
+fn root_store(opts: &Opts) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match opts.ssl_root_cert.as_deref() {
+        Some(path) if path != "system" => {
+            let pem = read_file("sslrootcert", path)?;
+            for cert in CertificateDer::pem_slice_iter(&pem) {
+                roots.add(cert.map_err(|e| pem_error("sslrootcert", e))?)?;
+            }
+        }
+        _ => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    Ok(roots)
+}
+
+fn pem_error(key: &str, e: rustls::pki_types::pem::Error) -> Error {
+    Error::InvalidUsage(format!("invalid {} file: {}", key, e))
+}
+
+/// Verifier for `VerifyCa`: checks the certificate chain but not the host name.
+#[derive(Debug)]
+struct NoHostnameVerification(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for NoHostnameVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> core::result::Result<ServerCertVerified, rustls::Error> {
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            other => other,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> core::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> core::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+/// Verifier for encryption-only modes: accepts any server certificate.
+///
+/// Handshake signatures are still checked, so the connection is encrypted with the
+/// key of the presented certificate.
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> core::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> core::result::Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> core::result::Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_config() {
+        let config = rustls_client_config(&Opts::default()).unwrap();
+        let opts = Opts {
+            rustls_config: Some(Arc::clone(&config)),
+            ssl_cert: Some("client.crt".into()),
+            ..Opts::default()
+        };
+        assert!(Arc::ptr_eq(&rustls_client_config(&opts).unwrap(), &config));
+        assert_eq!(config.alpn_protocols, vec![b"postgresql".to_vec()]);
+
+        // ALPN is added to a configuration without it
+        let mut without_alpn = ClientConfig::clone(&config);
+        without_alpn.alpn_protocols.clear();
+        let opts = Opts {
+            rustls_config: Some(Arc::new(without_alpn)),
+            ..Opts::default()
+        };
+        let config = rustls_client_config(&opts).unwrap();
+        assert_eq!(config.alpn_protocols, vec![b"postgresql".to_vec()]);
+    }
+
+    #[test]
+    fn test_mismatched_client_cert() {
+        let opts = Opts {
+            ssl_key: Some("client.key".into()),
+            ..Opts::default()
+        };
+        assert!(matches!(
+            rustls_client_config(&opts),
+            Err(Error::InvalidUsage(_))
+        ));
+    }
+
+    #[test]
+    fn test_server_name() {
+        let opts = Opts {
+            host: "127.0.0.1".into(),
+            ..Opts::default()
+        };
+        assert!(matches!(
+            server_name(&opts).unwrap(),
+            ServerName::IpAddress(_)
+        ));
+    }
+}
@@ -1,6 +1,7 @@
 //! Asynchronous PostgreSQL connection.
 
-use tokio::net::TcpStream;
+use std::time::{Duration, Instant};
+
 use tokio::net::UnixStream;
 
 use crate::buffer_pool::PooledBufferSet;
@@ -9,8 +10,9 @@ use crate::error::{Error, Result};
 use crate::handler::{
     AsyncMessageHandler, BinaryHandler, DropHandler, FirstRowHandler, TextHandler,
 };
-use crate::opts::Opts;
-use crate::protocol::backend::BackendKeyData;
+use crate::opts::{Opts, ProtocolVersion, SslMode, TargetSessionAttrs};
+use crate::pgpass;
+use crate::protocol::backend::{BackendKeyData, CopyInResponse, CopyOutResponse};
 use crate::protocol::frontend::write_terminate;
 use crate::protocol::types::TransactionStatus;
 use crate::state::StateMachine;
@@ -20,6 +22,8 @@ use crate::state::extended::{BindStateMachine, ExtendedQueryStateMachine, Prepar
 use crate::state::simple_query::SimpleQueryStateMachine;
 use crate::statement::IntoStatement;
 
+use super::cancel::CancelToken;
+use super::copy::{CopyIn, CopyOut, read_until_ready};
 use super::stream::Stream;
 
 /// Asynchronous PostgreSQL connection.
@@ -27,39 +31,162 @@ pub struct Conn {
     pub(crate) stream: Stream,
     pub(crate) buffer_set: PooledBufferSet,
     backend_key: Option<BackendKeyData>,
+    protocol_version: ProtocolVersion,
     server_params: Vec<(String, String)>,
     pub(crate) transaction_status: TransactionStatus,
     pub(crate) is_broken: bool,
+    opts: Opts,
     name_counter: u64,
     async_message_handler: Option<Box<dyn AsyncMessageHandler>>,
+    query_timeout: Option<Duration>,
+    /// Deadline of the running query, if a query timeout is set
+    deadline: Option<Instant>,
+    /// Whether a CancelRequest was sent because the deadline passed
+    timed_out: bool,
 }
 
 impl Conn {
     /// Connect to a PostgreSQL server.
+    ///
+    /// With multiple hosts, each host is tried in turn until one accepts the
+    /// connection and satisfies [`Opts::target_session_attrs`].
     pub async fn new<O: TryInto<Opts>>(opts: O) -> Result<Self>
     where
         Error: From<O::Error>,
     {
         let opts = opts.try_into()?;
 
-        let stream = if let Some(socket_path) = &opts.socket {
-            Stream::unix(UnixStream::connect(socket_path).await?)
-        } else {
-            if opts.host.is_empty() {
-                return Err(Error::InvalidUsage("host is empty".into()));
+        let candidates = opts.host_candidates();
+        let passes = match opts.target_session_attrs {
+            TargetSessionAttrs::PreferStandby => {
+                vec![TargetSessionAttrs::Standby, TargetSessionAttrs::Any]
+            }
+            attrs => vec![attrs],
+        };
+
+        let mut last_error = None;
+        for attrs in passes {
+            for host_opts in &candidates {
+                match Self::connect_host(host_opts.clone(), attrs).await {
+                    Ok(conn) => return Ok(conn),
+                    Err(e) => last_error = Some(e),
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| Error::InvalidUsage("no hosts to connect to".into())))
+    }
+
+    /// Connect to a single host and check its session attributes.
+    async fn connect_host(mut opts: Opts, attrs: TargetSessionAttrs) -> Result<Self> {
+        if opts.password.is_none() && !opts.has_password_provider() {
+            opts.password = pgpass::lookup(&opts);
+        }
+        let stream = Stream::connect(&opts).await?;
+        let mut conn = match Self::new_with_stream(stream, opts.clone()).await {
+            // The server may reject unencrypted connections, retry with SSL
+            Err(Error::Server(_)) if opts.ssl_mode == SslMode::Allow => {
+                opts.ssl_mode = SslMode::Require;
+                let ssl_stream = Stream::connect(&opts).await?;
+                Self::new_with_stream(ssl_stream, opts).await?
             }
-            let addr = format!("{}:{}", opts.host, opts.port);
-            let tcp = TcpStream::connect(&addr).await?;
-            tcp.set_nodelay(true)?;
-            Stream::tcp(tcp)
+            other => other?,
         };
+        conn.check_session_attrs(attrs).await?;
+        Ok(conn)
+    }
+
+    /// Check that the server satisfies the target session attributes.
+    async fn check_session_attrs(&mut self, attrs: TargetSessionAttrs) -> Result<()> {
+        let mismatch = match attrs {
+            TargetSessionAttrs::Any => None,
+            TargetSessionAttrs::ReadWrite => {
+                self.is_read_only().await?.then_some("session is read-only")
+            }
+            TargetSessionAttrs::ReadOnly => {
+                (!self.is_read_only().await?).then_some("session is not read-only")
+            }
+            TargetSessionAttrs::Primary => self
+                .is_hot_standby()
+                .await?
+                .then_some("server is in hot standby mode"),
+            TargetSessionAttrs::Standby | TargetSessionAttrs::PreferStandby => {
+                (!self.is_hot_standby().await?).then_some("server is not in hot standby mode")
+            }
+        };
+        match mismatch {
+            Some(reason) => Err(Error::SessionAttrsMismatch(format!(
+                "{} ({}:{})",
+                reason, self.opts.host, self.opts.port
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Get a server parameter reported in ParameterStatus.
+    fn server_param(&self, name: &str) -> Option<&str> {
+        self.server_params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
 
-        Self::new_with_stream(stream, opts).await
+    /// Check if the server is in hot standby mode.
+    ///
+    /// Uses the `in_hot_standby` parameter reported by PostgreSQL 14+, or
+    /// `pg_is_in_recovery()` on older servers.
+    async fn is_hot_standby(&mut self) -> Result<bool> {
+        if let Some(value) = self.server_param("in_hot_standby") {
+            return Ok(value == "on");
+        }
+        let row: Option<(bool,)> = self
+            .query_first("SELECT pg_catalog.pg_is_in_recovery()")
+            .await?;
+        Ok(row.is_some_and(|(in_recovery,)| in_recovery))
+    }
+
+    /// Check if the session is read-only by default.
+    ///
+    /// Uses the `default_transaction_read_only` parameter reported by PostgreSQL 14+,
+    /// or `SHOW transaction_read_only` on older servers.
+    async fn is_read_only(&mut self) -> Result<bool> {
+        if let Some(value) = self.server_param("default_transaction_read_only") {
+            let read_only = value == "on";
+            return Ok(read_only || self.is_hot_standby().await?);
+        }
+        let row: Option<(String,)> = self.query_first("SHOW transaction_read_only").await?;
+        Ok(row.is_some_and(|(value,)| value == "on"))
     }
 
     /// Connect using an existing stream.
+    ///
+    /// With `connect_timeout` set, the SSL negotiation and startup must complete
+    /// within the timeout.
+    pub async fn new_with_stream(stream: Stream, options: Opts) -> Result<Self> {
+        let conn = match options.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, Self::startup(stream, &options))
+                .await
+                .map_err(|_elapsed| {
+                    Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "connection setup timed out",
+                    ))
+                })??,
+            None => Self::startup(stream, &options).await?,
+        };
+
+        // Upgrade to Unix socket if connected via TCP to loopback
+        let conn = if options.prefer_unix_socket && conn.stream.is_tcp_loopback() {
+            conn.try_upgrade_to_unix_socket(&options).await
+        } else {
+            conn
+        };
+
+        Ok(conn)
+    }
+
+    /// Run the SSL negotiation and startup, until the server is ready for queries.
     #[allow(unused_mut)]
-    pub async fn new_with_stream(mut stream: Stream, options: Opts) -> Result<Self> {
+    async fn startup(mut stream: Stream, options: &Opts) -> Result<Self> {
         let mut buffer_set = options.buffer_pool.get_buffer_set();
         let mut state_machine = ConnectionStateMachine::new(options.clone());
 
@@ -85,17 +212,39 @@ impl Conn {
                     stream.read_message(&mut buffer_set).await?;
                 }
                 Action::TlsHandshake => {
-                    #[cfg(feature = "tokio-tls")]
+                    #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
                     {
-                        stream = stream.upgrade_to_tls(&options.host).await?;
+                        stream = stream.upgrade_to_tls(options).await?;
+                        state_machine.set_channel_binding_data(stream.tls_server_end_point());
                     }
-                    #[cfg(not(feature = "tokio-tls"))]
+                    #[cfg(not(any(feature = "tokio-tls", feature = "tokio-rustls")))]
                     {
                         return Err(Error::Unsupported(
-                            "TLS requested but tokio-tls feature not enabled".into(),
+                            "TLS requested but tokio-tls or tokio-rustls feature not enabled"
+                                .into(),
                         ));
                     }
                 }
+                Action::RequestPassword => {
+                    let password = if let Some(provider) = &options.async_password_provider {
+                        provider.password(options).await?
+                    } else if let Some(provider) = &options.password_provider {
+                        provider.password(options)?
+                    } else {
+                        return Err(Error::InvalidUsage(
+                            "password requested without a password provider".into(),
+                        ));
+                    };
+                    state_machine.set_password(password);
+                }
+                Action::RequestOAuthToken => {
+                    let provider = options.oauth_token_provider.as_ref().ok_or_else(|| {
+                        Error::InvalidUsage(
+                            "OAuth token requested without an OAuth token provider".into(),
+                        )
+                    })?;
+                    state_machine.set_oauth_token(provider.token(options)?);
+                }
                 Action::HandleAsyncMessageAndReadMessage(_) => {
                     // Ignore async messages during startup, read next message
                     stream.read_message(&mut buffer_set).await?;
@@ -104,25 +253,21 @@ impl Conn {
             }
         }
 
-        let conn = Self {
+        Ok(Self {
             stream,
             buffer_set,
             backend_key: state_machine.backend_key().cloned(),
+            protocol_version: state_machine.protocol_version(),
             server_params: state_machine.take_server_params(),
             transaction_status: state_machine.transaction_status(),
             is_broken: false,
             name_counter: 0,
             async_message_handler: None,
-        };
-
-        // Upgrade to Unix socket if connected via TCP to loopback
-        let conn = if options.prefer_unix_socket && conn.stream.is_tcp_loopback() {
-            conn.try_upgrade_to_unix_socket(&options).await
-        } else {
-            conn
-        };
-
-        Ok(conn)
+            query_timeout: options.query_timeout,
+            deadline: None,
+            timed_out: false,
+            opts: options.clone(),
+        })
     }
 
     /// Try to upgrade to Unix socket connection.
@@ -179,6 +324,14 @@ impl Conn {
         self.backend_key.as_ref()
     }
 
+    /// Get a token for cancelling the query running on this connection.
+    ///
+    /// The token is `Send + Sync + Clone` and can be used from another thread or task
+    /// while this connection is busy.
+    pub fn cancel_token(&self) -> CancelToken {
+        CancelToken::new(self.opts.clone(), self.backend_key.clone())
+    }
+
     /// Get the connection ID (backend process ID).
     ///
     /// Returns 0 if the backend key data is not available.
@@ -186,6 +339,13 @@ impl Conn {
         self.backend_key.as_ref().map_or(0, |k| k.process_id())
     }
 
+    /// Get the protocol version negotiated with the server.
+    ///
+    /// Servers older than PostgreSQL 18 only support protocol 3.0.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
     /// Get server parameters.
     pub fn server_params(&self) -> &[(String, String)] {
         &self.server_params
@@ -206,6 +366,78 @@ impl Conn {
         self.is_broken
     }
 
+    /// Get the query timeout.
+    pub fn query_timeout(&self) -> Option<Duration> {
+        self.query_timeout
+    }
+
+    /// Set the query timeout, overriding [`Opts::query_timeout`].
+    ///
+    /// Applies to `query*`, `exec*`, `exec_iter` and pipeline `sync`/`claim`. When the
+    /// timeout passes, a CancelRequest is sent on a separate connection, the resulting
+    /// error is drained, and [`Error::Timeout`] is returned. The connection stays usable.
+    pub fn set_query_timeout(&mut self, timeout: Option<Duration>) {
+        self.query_timeout = timeout;
+    }
+
+    /// Start the query timeout clock.
+    pub(crate) fn start_deadline(&mut self) {
+        self.deadline = self.query_timeout.map(|timeout| Instant::now() + timeout);
+        self.timed_out = false;
+    }
+
+    /// Stop the query timeout clock.
+    pub(crate) fn clear_deadline(&mut self) {
+        self.deadline = None;
+        self.timed_out = false;
+    }
+
+    /// Replace the query_canceled error caused by a timeout with `Error::Timeout`.
+    pub(crate) fn map_timeout<T>(&self, result: Result<T>) -> Result<T> {
+        match result {
+            Err(e) if self.timed_out && e.sqlstate() == Some("57014") => Err(Error::Timeout),
+            other => other,
+        }
+    }
+
+    fn finish_deadline<T>(&mut self, result: Result<T>) -> Result<T> {
+        let result = self.map_timeout(result);
+        self.clear_deadline();
+        result
+    }
+
+    /// Read a message, cancelling the running query once the deadline passes.
+    pub(crate) async fn read_message(&mut self) -> Result<()> {
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.stream.wait_readable(remaining).await? {
+                self.deadline = None;
+                self.timed_out = true;
+                if self.cancel_token().cancel().await.is_err() {
+                    // The query keeps running, its response cannot be awaited
+                    self.is_broken = true;
+                    return Err(Error::Timeout);
+                }
+            }
+        }
+        self.stream.read_message(&mut self.buffer_set).await?;
+        Ok(())
+    }
+
+    /// Read and discard messages until ReadyForQuery.
+    async fn drain_until_ready(&mut self) -> Result<()> {
+        use crate::protocol::backend::{ReadyForQuery, msg_type};
+
+        loop {
+            self.read_message().await?;
+            if self.buffer_set.type_byte == msg_type::READY_FOR_QUERY {
+                let ready = ReadyForQuery::parse(&self.buffer_set.read_buffer)?;
+                self.transaction_status = ready.transaction_status().unwrap_or_default();
+                return Ok(());
+            }
+        }
+    }
+
     /// Generate the next unique portal name.
     pub(crate) fn next_portal_name(&mut self) -> String {
         self.name_counter += 1;
@@ -239,7 +471,7 @@ impl Conn {
         loop {
             match state_machine.step(&mut self.buffer_set)? {
                 Action::ReadMessage => {
-                    self.stream.read_message(&mut self.buffer_set).await?;
+                    self.read_message().await?;
                 }
                 Action::Write => {
                     self.stream.write_all(&self.buffer_set.write_buffer).await?;
@@ -248,7 +480,7 @@ impl Conn {
                 Action::WriteAndReadMessage => {
                     self.stream.write_all(&self.buffer_set.write_buffer).await?;
                     self.stream.flush().await?;
-                    self.stream.read_message(&mut self.buffer_set).await?;
+                    self.read_message().await?;
                 }
                 Action::Finished => break,
                 _ => return Err(Error::Protocol("Unexpected action in bind".into())),
@@ -282,14 +514,24 @@ impl Conn {
     /// Drive a state machine to completion.
     async fn drive<S: StateMachine>(&mut self, state_machine: &mut S) -> Result<()> {
         loop {
-            match state_machine.step(&mut self.buffer_set)? {
+            let action = match state_machine.step(&mut self.buffer_set) {
+                Ok(action) => action,
+                Err(e) => {
+                    // On error, drain to ReadyForQuery to leave connection in clean state
+                    if !e.is_connection_broken() && !matches!(e, Error::Protocol(_)) {
+                        self.drain_until_ready().await?;
+                    }
+                    return Err(e);
+                }
+            };
+            match action {
                 Action::WriteAndReadByte => {
                     return Err(Error::Protocol(
                         "Unexpected WriteAndReadByte in query state machine".into(),
                     ));
                 }
                 Action::ReadMessage => {
-                    self.stream.read_message(&mut self.buffer_set).await?;
+                    self.read_message().await?;
                 }
                 Action::Write => {
                     self.stream.write_all(&self.buffer_set.write_buffer).await?;
@@ -298,19 +540,24 @@ impl Conn {
                 Action::WriteAndReadMessage => {
                     self.stream.write_all(&self.buffer_set.write_buffer).await?;
                     self.stream.flush().await?;
-                    self.stream.read_message(&mut self.buffer_set).await?;
+                    self.read_message().await?;
                 }
                 Action::TlsHandshake => {
                     return Err(Error::Protocol(
                         "Unexpected TlsHandshake in query state machine".into(),
                     ));
                 }
+                Action::RequestPassword | Action::RequestOAuthToken => {
+                    return Err(Error::Protocol(
+                        "Unexpected credential request in query state machine".into(),
+                    ));
+                }
                 Action::HandleAsyncMessageAndReadMessage(ref async_msg) => {
                     if let Some(ref mut h) = self.async_message_handler {
                         h.handle(async_msg);
                     }
                     // Read next message after handling async message
-                    self.stream.read_message(&mut self.buffer_set).await?;
+                    self.read_message().await?;
                 }
                 Action::Finished => {
                     self.transaction_status = state_machine.transaction_status();
@@ -323,7 +570,9 @@ impl Conn {
 
     /// Execute a simple query with a handler.
     pub async fn query<H: TextHandler>(&mut self, sql: &str, handler: &mut H) -> Result<()> {
-        let result = self.query_inner(sql, handler).await;
+        self.start_deadline();
+        let inner = self.query_inner(sql, handler).await;
+        let result = self.finish_deadline(inner);
         if let Err(e) = &result
             && e.is_connection_broken()
         {
@@ -364,6 +613,131 @@ impl Conn {
         Ok(handler.into_row())
     }
 
+    // === COPY Protocol ===
+
+    /// Start a `COPY ... FROM STDIN` operation.
+    ///
+    /// Returns a [`CopyIn`] writer that implements [`tokio::io::AsyncWrite`]. The data
+    /// must be in the format specified by the COPY statement (text, CSV or binary).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// let mut writer = conn.copy_in("COPY users (id, name) FROM STDIN (FORMAT csv)").await?;
+    /// writer.write_all(b"1,alice\n2,bob\n").await?;
+    /// let rows = writer.finish().await?;
+    /// ```
+    pub async fn copy_in(&mut self, sql: &str) -> Result<CopyIn<'_>> {
+        let result = self.copy_in_inner(sql).await;
+        if let Err(e) = &result
+            && e.is_connection_broken()
+        {
+            self.is_broken = true;
+        }
+        Ok(CopyIn::new(self, result?))
+    }
+
+    async fn copy_in_inner(&mut self, sql: &str) -> Result<CopyInResponse> {
+        use crate::protocol::backend::msg_type;
+
+        self.start_copy(
+            sql,
+            msg_type::COPY_IN_RESPONSE,
+            "copy_in requires a COPY ... FROM STDIN statement",
+        )
+        .await?;
+        CopyInResponse::parse(&self.buffer_set.read_buffer)
+    }
+
+    /// Start a `COPY ... TO STDOUT` operation.
+    ///
+    /// Returns a [`CopyOut`] reader that implements [`tokio::io::AsyncRead`] and
+    /// [`Stream`](futures_core::Stream) of CopyData chunks. Data is read from the
+    /// socket one CopyData message at a time, so memory usage does not depend
+    /// on the size of the table.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let mut reader = conn.copy_out("COPY users TO STDOUT (FORMAT csv)").await?;
+    /// let mut file = tokio::fs::File::create("users.csv").await?;
+    /// tokio::io::copy(&mut reader, &mut file).await?;
+    /// let rows = reader.finish().await?;
+    /// ```
+    pub async fn copy_out(&mut self, sql: &str) -> Result<CopyOut<'_>> {
+        let result = self.copy_out_inner(sql).await;
+        if let Err(e) = &result
+            && e.is_connection_broken()
+        {
+            self.is_broken = true;
+        }
+        Ok(CopyOut::new(self, result?))
+    }
+
+    async fn copy_out_inner(&mut self, sql: &str) -> Result<CopyOutResponse> {
+        use crate::protocol::backend::msg_type;
+
+        self.start_copy(
+            sql,
+            msg_type::COPY_OUT_RESPONSE,
+            "copy_out requires a COPY ... TO STDOUT statement",
+        )
+        .await?;
+        CopyOutResponse::parse(&self.buffer_set.read_buffer)
+    }
+
+    /// Send a COPY statement and read until the expected Copy*Response.
+    ///
+    /// On success, the response message is left in the read buffer.
+    async fn start_copy(&mut self, sql: &str, expected: u8, usage: &str) -> Result<()> {
+        use crate::protocol::backend::{ErrorResponse, RawMessage, ReadyForQuery, msg_type};
+        use crate::protocol::frontend::{write_copy_fail, write_query};
+
+        self.buffer_set.write_buffer.clear();
+        write_query(&mut self.buffer_set.write_buffer, sql);
+        self.stream.write_all(&self.buffer_set.write_buffer).await?;
+        self.stream.flush().await?;
+
+        loop {
+            self.read_message().await?;
+            let type_byte = self.buffer_set.type_byte;
+
+            if RawMessage::is_async_type(type_byte) {
+                continue;
+            }
+
+            match type_byte {
+                t if t == expected => return Ok(()),
+                msg_type::ERROR_RESPONSE => {
+                    let error = ErrorResponse::parse(&self.buffer_set.read_buffer)?;
+                    read_until_ready(self).await?;
+                    return Err(error.into_error());
+                }
+                msg_type::READY_FOR_QUERY => {
+                    let ready = ReadyForQuery::parse(&self.buffer_set.read_buffer)?;
+                    self.transaction_status = ready.transaction_status().unwrap_or_default();
+                    return Err(Error::InvalidUsage(usage.into()));
+                }
+                _ => {
+                    if type_byte == msg_type::COPY_IN_RESPONSE {
+                        // The server waits for data, reject it so that it proceeds to ReadyForQuery
+                        self.buffer_set.write_buffer.clear();
+                        write_copy_fail(&mut self.buffer_set.write_buffer, usage);
+                        self.stream.write_all(&self.buffer_set.write_buffer).await?;
+                        self.stream.flush().await?;
+                    }
+                    match read_until_ready(self).await {
+                        Ok(_) | Err(Error::Server(_)) => {}
+                        Err(e) => return Err(e),
+                    }
+                    return Err(Error::InvalidUsage(usage.into()));
+                }
+            }
+        }
+    }
+
     /// Close the connection gracefully.
     pub async fn close(mut self) -> Result<()> {
         self.buffer_set.write_buffer.clear();
@@ -443,12 +817,12 @@ impl Conn {
         loop {
             match state_machine.step(&mut self.buffer_set)? {
                 Action::ReadMessage => {
-                    self.stream.read_message(&mut self.buffer_set).await?;
+                    self.read_message().await?;
                 }
                 Action::WriteAndReadMessage => {
                     self.stream.write_all(&self.buffer_set.write_buffer).await?;
                     self.stream.flush().await?;
-                    self.stream.read_message(&mut self.buffer_set).await?;
+                    self.read_message().await?;
                 }
                 Action::Finished => {
                     self.transaction_status = state_machine.transaction_status();
@@ -492,7 +866,9 @@ impl Conn {
         params: P,
         handler: &mut H,
     ) -> Result<()> {
-        let result = self.exec_inner(&statement, &params, handler).await;
+        self.start_deadline();
+        let inner = self.exec_inner(&statement, &params, handler).await;
+        let result = self.finish_deadline(inner);
         if let Err(e) = &result
             && e.is_connection_broken()
         {
@@ -605,9 +981,11 @@ impl Conn {
         params_list: &[P],
         chunk_size: usize,
     ) -> Result<()> {
-        let result = self
+        self.start_deadline();
+        let inner = self
             .exec_batch_inner(&statement, params_list, chunk_size)
             .await;
+        let result = self.finish_deadline(inner);
         if let Err(e) = &result
             && e.is_connection_broken()
         {
@@ -688,19 +1066,18 @@ impl Conn {
         &mut self,
         state_machine: &mut crate::state::extended::BatchStateMachine,
     ) -> Result<()> {
-        use crate::protocol::backend::{ReadyForQuery, msg_type};
         use crate::state::action::Action;
 
         loop {
             let step_result = state_machine.step(&mut self.buffer_set);
             match step_result {
                 Ok(Action::ReadMessage) => {
-                    self.stream.read_message(&mut self.buffer_set).await?;
+                    self.read_message().await?;
                 }
                 Ok(Action::WriteAndReadMessage) => {
                     self.stream.write_all(&self.buffer_set.write_buffer).await?;
                     self.stream.flush().await?;
-                    self.stream.read_message(&mut self.buffer_set).await?;
+                    self.read_message().await?;
                 }
                 Ok(Action::Finished) => {
                     break;
@@ -708,15 +1085,7 @@ impl Conn {
                 Ok(_) => return Err(Error::Protocol("Unexpected action in batch".into())),
                 Err(e) => {
                     // On error, drain to ReadyForQuery to leave connection in clean state
-                    loop {
-                        self.stream.read_message(&mut self.buffer_set).await?;
-                        if self.buffer_set.type_byte == msg_type::READY_FOR_QUERY {
-                            let ready = ReadyForQuery::parse(&self.buffer_set.read_buffer)?;
-                            self.transaction_status =
-                                ready.transaction_status().unwrap_or_default();
-                            break;
-                        }
-                    }
+                    self.drain_until_ready().await?;
                     return Err(e);
                 }
             }
@@ -789,7 +1158,7 @@ impl Conn {
         let mut pending_error: Option<Error> = None;
 
         loop {
-            self.stream.read_message(&mut self.buffer_set).await?;
+            self.read_message().await?;
             let type_byte = self.buffer_set.type_byte;
 
             if RawMessage::is_async_type(type_byte) {
@@ -866,7 +1235,7 @@ impl Conn {
         self.stream.flush().await?;
 
         loop {
-            self.stream.read_message(&mut self.buffer_set).await?;
+            self.read_message().await?;
             let type_byte = self.buffer_set.type_byte;
 
             if RawMessage::is_async_type(type_byte) {
@@ -942,7 +1311,7 @@ impl Conn {
         let mut column_buffer: Vec<u8> = Vec::new();
 
         loop {
-            self.stream.read_message(&mut self.buffer_set).await?;
+            self.read_message().await?;
             let type_byte = self.buffer_set.type_byte;
 
             if RawMessage::is_async_type(type_byte) {
@@ -1027,7 +1396,9 @@ impl Conn {
         F: FnOnce(&mut super::unnamed_portal::UnnamedPortal<'_>) -> Fut,
         Fut: std::future::Future<Output = Result<T>>,
     {
-        let result = self.exec_iter_inner(&statement, &params, f).await;
+        self.start_deadline();
+        let inner = self.exec_iter_inner(&statement, &params, f).await;
+        let result = self.finish_deadline(inner);
         if let Err(e) = &result
             && e.is_connection_broken()
         {
@@ -1063,9 +1434,18 @@ impl Conn {
 
         // Drive the state machine to completion (ParseComplete + BindComplete)
         loop {
-            match state_machine.step(&mut self.buffer_set)? {
+            let action = match state_machine.step(&mut self.buffer_set) {
+                Ok(action) => action,
+                Err(e @ Error::Server(_)) if !e.is_connection_broken() => {
+                    // The server discards messages until Sync after an error
+                    self.lowlevel_sync_inner().await?;
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            };
+            match action {
                 Action::ReadMessage => {
-                    self.stream.read_message(&mut self.buffer_set).await?;
+                    self.read_message().await?;
                 }
                 Action::Write => {
                     self.stream.write_all(&self.buffer_set.write_buffer).await?;
@@ -1074,7 +1454,7 @@ impl Conn {
                 Action::WriteAndReadMessage => {
                     self.stream.write_all(&self.buffer_set.write_buffer).await?;
                     self.stream.flush().await?;
-                    self.stream.read_message(&mut self.buffer_set).await?;
+                    self.read_message().await?;
                 }
                 Action::Finished => break,
                 _ => return Err(Error::Protocol("Unexpected action in bind".into())),
@@ -1119,7 +1499,7 @@ impl Conn {
         self.stream.flush().await?;
 
         loop {
-            self.stream.read_message(&mut self.buffer_set).await?;
+            self.read_message().await?;
             let type_byte = self.buffer_set.type_byte;
 
             if RawMessage::is_async_type(type_byte) {
@@ -1183,6 +1563,7 @@ impl Conn {
         let mut pipeline = super::pipeline::Pipeline::new_inner(self);
         let result = f(&mut pipeline).await;
         pipeline.cleanup().await;
+        self.clear_deadline();
         result
     }
 
@@ -0,0 +1,738 @@
+//! COPY FROM STDIN / TO STDOUT support.
+
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::conversion::{FromRow, ToParams};
+use crate::copy::{
+    BinaryCopyDecoder, BinaryCopyEncoder, CopyTextDecoder, CopyTextEncoder, CopyTextOptions,
+    ToCopyRow,
+};
+use crate::error::{Error, Result};
+use crate::protocol::backend::{
+    CommandComplete, CopyInResponse, CopyOutResponse, ErrorResponse, RawMessage, ReadyForQuery,
+    msg_type,
+};
+use crate::protocol::frontend::{write_copy_data, write_copy_done, write_copy_fail};
+use crate::protocol::types::Oid;
+
+use super::Conn;
+
+/// Buffered CopyData bytes are sent to the server once this size is reached.
+const COPY_IN_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Writer for a `COPY ... FROM STDIN` operation.
+///
+/// Created by [`Conn::copy_in()`]. Bytes written are sent to the server as
+/// CopyData messages. Call [`finish()`](Self::finish) to complete the COPY
+/// and get the number of rows copied, or [`abort()`](Self::abort) to cancel it.
+///
+/// Dropping the writer without completing `finish()` or `abort()` cannot send
+/// CopyFail asynchronously, so the connection is marked as broken instead.
+///
+/// # Example
+///
+/// ```ignore
+/// use tokio::io::AsyncWriteExt;
+///
+/// let mut writer = conn.copy_in("COPY users (id, name) FROM STDIN").await?;
+/// writer.write_all(b"1\talice\n2\tbob\n").await?;
+/// let rows = writer.finish().await?;
+/// assert_eq!(rows, 2);
+/// ```
+pub struct CopyIn<'a> {
+    conn: &'a mut Conn,
+    response: CopyInResponse,
+    /// Number of bytes of `write_buffer` already written to the socket
+    sent: usize,
+    done: bool,
+}
+
+impl<'a> CopyIn<'a> {
+    pub(crate) fn new(conn: &'a mut Conn, response: CopyInResponse) -> Self {
+        conn.buffer_set.write_buffer.clear();
+        Self {
+            conn,
+            response,
+            sent: 0,
+            done: false,
+        }
+    }
+
+    /// Get the CopyInResponse sent by the server (overall and per-column formats).
+    pub fn response(&self) -> &CopyInResponse {
+        &self.response
+    }
+
+    /// Send CopyDone and wait for the server to finish the COPY.
+    ///
+    /// Returns the number of rows copied.
+    pub async fn finish(mut self) -> Result<u64> {
+        let result = self.finish_inner().await;
+        self.done = true;
+        self.mark_broken_on_error(result)
+    }
+
+    async fn finish_inner(&mut self) -> Result<u64> {
+        write_copy_done(&mut self.conn.buffer_set.write_buffer);
+        self.send_buffer().await?;
+        self.conn.stream.flush().await?;
+        let rows = read_until_ready(self.conn).await?;
+        Ok(rows.unwrap_or(0))
+    }
+
+    /// Abort the COPY by sending CopyFail with the given message.
+    ///
+    /// Data already sent is discarded by the server. The server's resulting
+    /// error is consumed, leaving the connection ready for the next query.
+    pub async fn abort(mut self, message: &str) -> Result<()> {
+        let result = self.abort_inner(message).await;
+        self.done = true;
+        self.mark_broken_on_error(result)
+    }
+
+    async fn abort_inner(&mut self, message: &str) -> Result<()> {
+        write_copy_fail(&mut self.conn.buffer_set.write_buffer, message);
+        self.send_buffer().await?;
+        self.conn.stream.flush().await?;
+        match read_until_ready(self.conn).await {
+            Ok(_) | Err(Error::Server(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write the buffered CopyData messages to the socket.
+    async fn send_buffer(&mut self) -> std::io::Result<()> {
+        let buffer_set = &mut self.conn.buffer_set;
+        if self.sent < buffer_set.write_buffer.len() {
+            self.conn
+                .stream
+                .write_all(&buffer_set.write_buffer[self.sent..])
+                .await?;
+        }
+        buffer_set.write_buffer.clear();
+        self.sent = 0;
+        Ok(())
+    }
+
+    /// Poll version of [`send_buffer`](Self::send_buffer).
+    fn poll_send_buffer(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let buffer_set = &mut self.conn.buffer_set;
+        while self.sent < buffer_set.write_buffer.len() {
+            let n = ready!(
+                Pin::new(&mut self.conn.stream)
+                    .poll_write(cx, &buffer_set.write_buffer[self.sent..])
+            )?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.sent += n;
+        }
+        buffer_set.write_buffer.clear();
+        self.sent = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    fn mark_broken_on_error<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result
+            && e.is_connection_broken()
+        {
+            self.conn.is_broken = true;
+        }
+        result
+    }
+}
+
+/// Typed writer for a `COPY ... FROM STDIN (FORMAT binary)` operation.
+///
+/// Rows are encoded with [`BinaryCopyEncoder`] for the given column type OIDs
+/// and sent through the wrapped [`CopyIn`].
+///
+/// # Example
+///
+/// ```ignore
+/// use zero_postgres::protocol::types::oid;
+///
+/// let copy_in = conn.copy_in("COPY users (id, name) FROM STDIN (FORMAT binary)").await?;
+/// let mut writer = BinaryCopyInWriter::new(copy_in, &[oid::INT4, oid::TEXT])?;
+/// writer.write_row(&(1, "alice")).await?;
+/// writer.write_row(&(2, "bob")).await?;
+/// let rows = writer.finish().await?;
+/// ```
+pub struct BinaryCopyInWriter<'a> {
+    copy_in: CopyIn<'a>,
+    encoder: BinaryCopyEncoder,
+    buf: Vec<u8>,
+}
+
+impl<'a> BinaryCopyInWriter<'a> {
+    /// Create a writer for columns of the given type OIDs.
+    ///
+    /// The COPY must use the binary format.
+    pub fn new(copy_in: CopyIn<'a>, oids: &[Oid]) -> Result<Self> {
+        if !copy_in.response().is_binary() {
+            return Err(Error::InvalidUsage(
+                "BinaryCopyInWriter requires COPY ... FROM STDIN (FORMAT binary)".into(),
+            ));
+        }
+        let encoder = BinaryCopyEncoder::new(oids)?;
+        let mut buf = Vec::new();
+        encoder.write_header(&mut buf);
+        Ok(Self {
+            copy_in,
+            encoder,
+            buf,
+        })
+    }
+
+    /// Encode and write one row.
+    pub async fn write_row<P: ToParams>(&mut self, row: &P) -> Result<()> {
+        self.encoder.write_row(row, &mut self.buf)?;
+        if self.buf.len() >= COPY_IN_BUFFER_SIZE {
+            self.copy_in.write_all(&self.buf).await?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Write the trailer and complete the COPY.
+    ///
+    /// Returns the number of rows copied.
+    pub async fn finish(mut self) -> Result<u64> {
+        self.encoder.write_trailer(&mut self.buf);
+        self.copy_in.write_all(&self.buf).await?;
+        self.copy_in.finish().await
+    }
+
+    /// Abort the COPY. See [`CopyIn::abort()`].
+    pub async fn abort(self, message: &str) -> Result<()> {
+        self.copy_in.abort(message).await
+    }
+}
+
+/// Typed writer for a `COPY ... FROM STDIN` operation in text or CSV format.
+///
+/// Rows are encoded with [`CopyTextEncoder`] and sent through the wrapped [`CopyIn`].
+///
+/// # Example
+///
+/// ```ignore
+/// use zero_postgres::copy::CopyTextOptions;
+///
+/// let copy_in = conn.copy_in("COPY users (id, name) FROM STDIN (FORMAT csv)").await?;
+/// let mut writer = TextCopyInWriter::new(copy_in, CopyTextOptions::csv())?;
+/// writer.write_row(&(1, "alice")).await?;
+/// writer.write_row(&(2, None::<&str>)).await?;
+/// let rows = writer.finish().await?;
+/// ```
+pub struct TextCopyInWriter<'a> {
+    copy_in: CopyIn<'a>,
+    encoder: CopyTextEncoder,
+    buf: Vec<u8>,
+}
+
+impl<'a> TextCopyInWriter<'a> {
+    /// Create a writer with the given options, which must match the COPY statement.
+    ///
+    /// Returns an error if the options can't represent every value; see
+    /// [`CopyTextOptions::validate()`].
+    pub fn new(copy_in: CopyIn<'a>, options: CopyTextOptions) -> Result<Self> {
+        if copy_in.response().is_binary() {
+            return Err(Error::InvalidUsage(
+                "TextCopyInWriter requires COPY ... FROM STDIN in text or CSV format".into(),
+            ));
+        }
+        options.validate()?;
+        Ok(Self {
+            copy_in,
+            encoder: CopyTextEncoder::new(options),
+            buf: Vec::new(),
+        })
+    }
+
+    /// Encode and write one row.
+    pub async fn write_row<R: ToCopyRow>(&mut self, row: &R) -> Result<()> {
+        self.encoder.write_row(row, &mut self.buf)?;
+        if self.buf.len() >= COPY_IN_BUFFER_SIZE {
+            self.copy_in.write_all(&self.buf).await?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Complete the COPY.
+    ///
+    /// Returns the number of rows copied.
+    pub async fn finish(mut self) -> Result<u64> {
+        self.copy_in.write_all(&self.buf).await?;
+        self.copy_in.finish().await
+    }
+
+    /// Abort the COPY. See [`CopyIn::abort()`].
+    pub async fn abort(self, message: &str) -> Result<()> {
+        self.copy_in.abort(message).await
+    }
+}
+
+impl AsyncWrite for CopyIn<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if this.conn.buffer_set.write_buffer.len() >= COPY_IN_BUFFER_SIZE
+            && let Err(e) = ready!(this.poll_send_buffer(cx))
+        {
+            this.conn.is_broken = true;
+            return Poll::Ready(Err(e));
+        }
+        write_copy_data(&mut this.conn.buffer_set.write_buffer, buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let result = match ready!(this.poll_send_buffer(cx)) {
+            Ok(()) => ready!(Pin::new(&mut this.conn.stream).poll_flush(cx)),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            this.conn.is_broken = true;
+        }
+        Poll::Ready(result)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        // The COPY is completed by `finish()`, shutdown only flushes pending data
+        self.poll_flush(cx)
+    }
+}
+
+impl Drop for CopyIn<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.conn.is_broken = true;
+        }
+    }
+}
+
+/// Reader for a `COPY ... TO STDOUT` operation.
+///
+/// Created by [`Conn::copy_out()`]. Each CopyData message is read from the
+/// socket only when the previous one has been consumed. Read the data with
+/// [`AsyncRead`], as a [`Stream`] of chunks, or with [`next_chunk()`](Self::next_chunk).
+///
+/// Dropping the reader before the end of data cannot drain the remaining
+/// data asynchronously, so the connection is marked as broken instead.
+/// Call [`finish()`](Self::finish) to discard the remaining data.
+///
+/// # Example
+///
+/// ```ignore
+/// let mut reader = conn.copy_out("COPY users TO STDOUT").await?;
+/// while let Some(chunk) = reader.next_chunk().await? {
+///     // chunk is one row in text and CSV formats
+/// }
+/// let rows = reader.finish().await?;
+/// ```
+pub struct CopyOut<'a> {
+    conn: &'a mut Conn,
+    response: CopyOutResponse,
+    /// Partially read message header (type byte and length)
+    header: [u8; 5],
+    /// Number of bytes read into `header`, or into `read_buffer` once the header is complete
+    filled: usize,
+    header_done: bool,
+    /// Read position in the current CopyData payload
+    pos: usize,
+    /// Length of the current CopyData payload, 0 if there is none
+    len: usize,
+    rows: Option<u64>,
+    pending_error: Option<Error>,
+    done: bool,
+}
+
+impl<'a> CopyOut<'a> {
+    pub(crate) fn new(conn: &'a mut Conn, response: CopyOutResponse) -> Self {
+        Self {
+            conn,
+            response,
+            header: [0; 5],
+            filled: 0,
+            header_done: false,
+            pos: 0,
+            len: 0,
+            rows: None,
+            pending_error: None,
+            done: false,
+        }
+    }
+
+    /// Get the CopyOutResponse sent by the server (overall and per-column formats).
+    pub fn response(&self) -> &CopyOutResponse {
+        &self.response
+    }
+
+    /// Read the next CopyData payload.
+    ///
+    /// Returns `None` once the server has sent all data.
+    pub async fn next_chunk(&mut self) -> Result<Option<&[u8]>> {
+        if !std::future::poll_fn(|cx| self.poll_fill(cx)).await? {
+            return Ok(None);
+        }
+        let chunk = self.conn.buffer_set.read_buffer.get(self.pos..self.len);
+        self.pos = self.len;
+        Ok(chunk)
+    }
+
+    /// Read and discard the remaining data.
+    ///
+    /// Returns the number of rows copied.
+    pub async fn finish(mut self) -> Result<u64> {
+        while self.next_chunk().await?.is_some() {}
+        Ok(self.rows.unwrap_or(0))
+    }
+
+    /// Make sure there is unread data in the current CopyData payload.
+    ///
+    /// Returns `false` at the end of data.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<Result<bool>> {
+        if self.pos < self.len {
+            return Poll::Ready(Ok(true));
+        }
+        let result = ready!(self.poll_next_chunk(cx));
+        let has_data = self.mark_broken_on_error(result)?;
+        self.pos = 0;
+        self.len = if has_data {
+            self.conn.buffer_set.read_buffer.len()
+        } else {
+            0
+        };
+        Poll::Ready(Ok(has_data))
+    }
+
+    /// Read messages until the next CopyData (returns `true`) or ReadyForQuery (returns `false`).
+    fn poll_next_chunk(&mut self, cx: &mut Context<'_>) -> Poll<Result<bool>> {
+        while !self.done {
+            ready!(self.poll_read_message(cx))?;
+            let conn = &mut *self.conn;
+            let type_byte = conn.buffer_set.type_byte;
+
+            if RawMessage::is_async_type(type_byte) {
+                continue;
+            }
+
+            match type_byte {
+                msg_type::COPY_DATA => return Poll::Ready(Ok(true)),
+                msg_type::COMMAND_COMPLETE => {
+                    let complete = CommandComplete::parse(&conn.buffer_set.read_buffer)?;
+                    self.rows = complete.rows_affected();
+                }
+                msg_type::ERROR_RESPONSE => {
+                    let error = ErrorResponse::parse(&conn.buffer_set.read_buffer)?;
+                    self.pending_error = Some(error.into_error());
+                }
+                msg_type::READY_FOR_QUERY => {
+                    let ready = ReadyForQuery::parse(&conn.buffer_set.read_buffer)?;
+                    conn.transaction_status = ready.transaction_status().unwrap_or_default();
+                    self.done = true;
+                }
+                _ => {
+                    // CopyDone
+                }
+            }
+        }
+
+        match self.pending_error.take() {
+            Some(e) => Poll::Ready(Err(e)),
+            None => Poll::Ready(Ok(false)),
+        }
+    }
+
+    /// Poll version of `Stream::read_message()` that keeps the partial state across polls.
+    fn poll_read_message(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let conn = &mut *self.conn;
+
+        if !self.header_done {
+            while self.filled < self.header.len() {
+                let mut buf = ReadBuf::new(self.header.get_mut(self.filled..).unwrap_or_default());
+                ready!(Pin::new(&mut conn.stream).poll_read(cx, &mut buf))?;
+                if buf.filled().is_empty() {
+                    return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+                }
+                self.filled += buf.filled().len();
+            }
+            let [type_byte, length @ ..] = self.header;
+            let payload_len = (u32::from_be_bytes(length) as usize).saturating_sub(4);
+            conn.buffer_set.type_byte = type_byte;
+            conn.buffer_set.read_buffer.clear();
+            conn.buffer_set.read_buffer.resize(payload_len, 0);
+            self.filled = 0;
+            self.header_done = true;
+        }
+
+        while self.filled < conn.buffer_set.read_buffer.len() {
+            let mut buf = ReadBuf::new(
+                conn.buffer_set
+                    .read_buffer
+                    .get_mut(self.filled..)
+                    .unwrap_or_default(),
+            );
+            ready!(Pin::new(&mut conn.stream).poll_read(cx, &mut buf))?;
+            if buf.filled().is_empty() {
+                return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            self.filled += buf.filled().len();
+        }
+        self.filled = 0;
+        self.header_done = false;
+        Poll::Ready(Ok(()))
+    }
+
+    fn mark_broken_on_error<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result
+            && e.is_connection_broken()
+        {
+            self.conn.is_broken = true;
+            self.done = true;
+        }
+        result
+    }
+}
+
+/// Typed reader for a `COPY ... TO STDOUT (FORMAT binary)` operation.
+///
+/// Tuples are decoded with [`BinaryCopyDecoder`] for the given column type OIDs
+/// from the data of the wrapped [`CopyOut`].
+///
+/// # Example
+///
+/// ```ignore
+/// use zero_postgres::protocol::types::oid;
+///
+/// let copy_out = conn.copy_out("COPY users (id, name) TO STDOUT (FORMAT binary)").await?;
+/// let mut reader = BinaryCopyOutReader::new(copy_out, &[oid::INT4, oid::TEXT])?;
+/// while let Some((id, name)) = reader.read_row::<(i32, String)>().await? {
+///     println!("{id}: {name}");
+/// }
+/// let rows = reader.finish().await?;
+/// ```
+pub struct BinaryCopyOutReader<'a> {
+    copy_out: CopyOut<'a>,
+    decoder: BinaryCopyDecoder,
+}
+
+impl<'a> BinaryCopyOutReader<'a> {
+    /// Create a reader for columns of the given type OIDs.
+    ///
+    /// The COPY must use the binary format.
+    pub fn new(copy_out: CopyOut<'a>, oids: &[Oid]) -> Result<Self> {
+        if !copy_out.response().is_binary() {
+            return Err(Error::InvalidUsage(
+                "BinaryCopyOutReader requires COPY ... TO STDOUT (FORMAT binary)".into(),
+            ));
+        }
+        Ok(Self {
+            copy_out,
+            decoder: BinaryCopyDecoder::new(oids),
+        })
+    }
+
+    /// Read and decode the next row.
+    ///
+    /// Returns `None` once all rows have been read.
+    pub async fn read_row<T: for<'r> FromRow<'r>>(&mut self) -> Result<Option<T>> {
+        loop {
+            if let Some(row) = self.decoder.next_row()? {
+                return Ok(Some(row));
+            }
+            match self.copy_out.next_chunk().await? {
+                Some(chunk) => self.decoder.push(chunk),
+                None if self.decoder.is_finished() => return Ok(None),
+                None => {
+                    return Err(Error::Decode(
+                        "binary COPY data ended without trailer".into(),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Read and discard the remaining data.
+    ///
+    /// Returns the number of rows copied.
+    pub async fn finish(self) -> Result<u64> {
+        self.copy_out.finish().await
+    }
+}
+
+/// Typed reader for a `COPY ... TO STDOUT` operation in text or CSV format.
+///
+/// Rows are decoded with [`CopyTextDecoder`] for the given column type OIDs
+/// from the data of the wrapped [`CopyOut`].
+///
+/// # Example
+///
+/// ```ignore
+/// use zero_postgres::copy::CopyTextOptions;
+/// use zero_postgres::protocol::types::oid;
+///
+/// let copy_out = conn.copy_out("COPY users (id, name) TO STDOUT (FORMAT csv)").await?;
+/// let mut reader = TextCopyOutReader::new(copy_out, CopyTextOptions::csv(), &[oid::INT4, oid::TEXT])?;
+/// while let Some((id, name)) = reader.read_row::<(i32, Option<String>)>().await? {
+///     println!("{id}: {name:?}");
+/// }
+/// ```
+pub struct TextCopyOutReader<'a> {
+    copy_out: CopyOut<'a>,
+    decoder: CopyTextDecoder,
+}
+
+impl<'a> TextCopyOutReader<'a> {
+    /// Create a reader with the given options for columns of the given type OIDs.
+    ///
+    /// The options must match the COPY statement.
+    pub fn new(copy_out: CopyOut<'a>, options: CopyTextOptions, oids: &[Oid]) -> Result<Self> {
+        if copy_out.response().is_binary() {
+            return Err(Error::InvalidUsage(
+                "TextCopyOutReader requires COPY ... TO STDOUT in text or CSV format".into(),
+            ));
+        }
+        Ok(Self {
+            copy_out,
+            decoder: CopyTextDecoder::new(options, oids),
+        })
+    }
+
+    /// Read and decode the next row.
+    ///
+    /// Returns `None` once all rows have been read.
+    pub async fn read_row<T: for<'r> FromRow<'r>>(&mut self) -> Result<Option<T>> {
+        loop {
+            if let Some(row) = self.decoder.next_row()? {
+                return Ok(Some(row));
+            }
+            match self.copy_out.next_chunk().await? {
+                Some(chunk) => self.decoder.push(chunk),
+                None if self.decoder.is_empty() => return Ok(None),
+                None => {
+                    return Err(Error::Decode(
+                        "COPY data ended with an incomplete row".into(),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Read and discard the remaining data.
+    ///
+    /// Returns the number of rows copied.
+    pub async fn finish(self) -> Result<u64> {
+        self.copy_out.finish().await
+    }
+}
+
+impl AsyncRead for CopyOut<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        while buf.remaining() > 0 {
+            match ready!(this.poll_fill(cx)) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(Error::Io(e)) => return Poll::Ready(Err(e)),
+                Err(e) => return Poll::Ready(Err(std::io::Error::other(e))),
+            }
+            let n = (this.len - this.pos).min(buf.remaining());
+            if let Some(src) = this.conn.buffer_set.read_buffer.get(this.pos..this.pos + n) {
+                buf.put_slice(src);
+            }
+            this.pos += n;
+            // Skip empty CopyData messages, returning no bytes means end of data
+            if n > 0 {
+                break;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Stream for CopyOut<'_> {
+    type Item = Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match ready!(this.poll_fill(cx)) {
+            Ok(true) => {
+                let chunk = this
+                    .conn
+                    .buffer_set
+                    .read_buffer
+                    .get(this.pos..this.len)
+                    .unwrap_or_default()
+                    .to_vec();
+                this.pos = this.len;
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Ok(false) => Poll::Ready(None),
+            Err(e) => Poll::Ready(Some(Err(e))),
+        }
+    }
+}
+
+impl Drop for CopyOut<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.conn.is_broken = true;
+        }
+    }
+}
+
+/// Read messages until ReadyForQuery.
+///
+/// Returns the row count from the last CommandComplete, or the server error if one was received.
+pub(crate) async fn read_until_ready(conn: &mut Conn) -> Result<Option<u64>> {
+    let mut rows = None;
+    let mut pending_error: Option<Error> = None;
+
+    loop {
+        conn.stream.read_message(&mut conn.buffer_set).await?;
+        let type_byte = conn.buffer_set.type_byte;
+
+        if RawMessage::is_async_type(type_byte) {
+            continue;
+        }
+
+        match type_byte {
+            msg_type::COMMAND_COMPLETE => {
+                let complete = CommandComplete::parse(&conn.buffer_set.read_buffer)?;
+                rows = complete.rows_affected();
+            }
+            msg_type::ERROR_RESPONSE => {
+                let error = ErrorResponse::parse(&conn.buffer_set.read_buffer)?;
+                pending_error = Some(error.into_error());
+            }
+            msg_type::READY_FOR_QUERY => {
+                let ready = ReadyForQuery::parse(&conn.buffer_set.read_buffer)?;
+                conn.transaction_status = ready.transaction_status().unwrap_or_default();
+                return match pending_error {
+                    Some(e) => Err(e),
+                    None => Ok(rows),
+                };
+            }
+            _ => {
+                // Ignore other messages before ReadyForQuery
+            }
+        }
+    }
+}
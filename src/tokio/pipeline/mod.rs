@@ -111,9 +111,11 @@ impl<'a> Pipeline<'a> {
             let _ = self.sync().await;
         }
 
-        // Drain remaining tickets
+        // Drain remaining tickets (the server skips them after an error)
         while self.claim_seq < self.queue_seq {
-            let _ = self.drain_one().await;
+            if !self.aborted {
+                self.drain_one().await;
+            }
             self.claim_seq += 1;
         }
 
@@ -128,12 +130,15 @@ impl<'a> Pipeline<'a> {
         };
         let mut handler = crate::handler::DropHandler::new();
 
-        let _ = match expectation {
+        let result = match expectation {
             Expectation::ParseBindExecute => self.claim_parse_bind_exec_inner(&mut handler).await,
             // When draining, we don't have the statement ref, but we also don't need row desc
             // since we're just dropping the results
             Expectation::BindExecute => self.claim_bind_exec_inner(&mut handler, None).await,
         };
+        if result.is_err() {
+            self.aborted = true;
+        }
     }
 
     // ========================================================================
@@ -232,6 +237,8 @@ impl<'a> Pipeline<'a> {
     /// After calling sync, you must claim all queued operations in order.
     /// The final ReadyForQuery message will be consumed when all operations
     /// are claimed.
+    ///
+    /// Starts the connection's query timeout, which covers the following claims.
     pub async fn sync(&mut self) -> Result<()> {
         let result = self.sync_inner().await;
         if let Err(e) = &result
@@ -250,6 +257,7 @@ impl<'a> Pipeline<'a> {
             .await?;
         self.conn.stream.flush().await?;
         self.conn.buffer_set.write_buffer.clear();
+        self.conn.start_deadline();
         Ok(())
     }
 
@@ -257,10 +265,7 @@ impl<'a> Pipeline<'a> {
     async fn finish(&mut self) -> Result<()> {
         // Wait for ReadyForQuery
         loop {
-            self.conn
-                .stream
-                .read_message(&mut self.conn.buffer_set)
-                .await?;
+            self.conn.read_message().await?;
             let type_byte = self.conn.buffer_set.type_byte;
 
             // Handle async messages
@@ -277,6 +282,7 @@ impl<'a> Pipeline<'a> {
             if type_byte == msg_type::READY_FOR_QUERY {
                 let ready = ReadyForQuery::parse(&self.conn.buffer_set.read_buffer)?;
                 self.conn.transaction_status = ready.transaction_status().unwrap_or_default();
+                self.conn.clear_deadline();
                 // Reset pipeline state
                 self.queue_seq = 0;
                 self.claim_seq = 0;
@@ -328,6 +334,7 @@ impl<'a> Pipeline<'a> {
             }
             None => Err(Error::Protocol("unexpected expectation type".into())),
         };
+        let result = self.conn.map_timeout(result);
 
         if let Err(e) = &result {
             if e.is_connection_broken() {
@@ -535,10 +542,7 @@ impl<'a> Pipeline<'a> {
     /// Read the next message, skipping async messages and handling errors.
     async fn read_next_message(&mut self) -> Result<()> {
         loop {
-            self.conn
-                .stream
-                .read_message(&mut self.conn.buffer_set)
-                .await?;
+            self.conn.read_message().await?;
             let type_byte = self.conn.buffer_set.type_byte;
 
             // Handle async messages
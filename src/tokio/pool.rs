@@ -1,74 +1,486 @@
 //! Asynchronous connection pool.
 
+use std::collections::VecDeque;
+use std::future::Future;
 use std::mem::ManuallyDrop;
 use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
+use std::pin::{Pin, pin};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::{Duration, Instant};
 
-use crossbeam_queue::ArrayQueue;
-use tokio::sync::Semaphore;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::opts::Opts;
+use crate::pool_stats::{PoolCounters, PoolStats};
 
 use super::Conn;
 
+/// Future returned by the [`PoolHooks`] methods.
+pub type PoolHookFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Hooks run by a [`Pool`] on its connections.
+///
+/// Returning an error from a hook closes the connection. All hooks do nothing
+/// by default.
+///
+/// # Example
+///
+/// ```ignore
+/// use zero_postgres::PoolResetPolicy;
+/// use zero_postgres::tokio::{Conn, Pool, PoolHookFuture, PoolHooks};
+///
+/// struct SearchPath;
+///
+/// impl PoolHooks for SearchPath {
+///     fn after_connect<'a>(&'a self, conn: &'a mut Conn) -> PoolHookFuture<'a> {
+///         Box::pin(async move {
+///             conn.query_drop("SET search_path TO app, public").await?;
+///             Ok(())
+///         })
+///     }
+/// }
+///
+/// // Keep the session state between checkouts instead of redoing it after `DISCARD ALL`
+/// opts.pool_reset_policy = PoolResetPolicy::RollbackIfInTx;
+/// let pool = Pool::with_hooks(opts, SearchPath);
+/// ```
+pub trait PoolHooks: Send + Sync {
+    /// Called after a new connection is opened, before it is used.
+    ///
+    /// An error is returned from `Pool::get`, or stops the reaper from opening
+    /// connections up to `pool_min_idle`.
+    ///
+    /// `DISCARD ALL` and `RESET ALL` undo session state such as `SET` (and
+    /// `DISCARD ALL` also `LISTEN` and prepared statements), so this is called
+    /// again on a returned connection after the `pool_reset_policy` statement
+    /// runs. With `PoolResetPolicy::RollbackIfInTx` or `None`, the state is kept
+    /// and this is called once per connection.
+    fn after_connect<'a>(&'a self, _conn: &'a mut Conn) -> PoolHookFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Called before an idle connection is handed out. On error, the next idle
+    /// connection is tried, or a new one is opened.
+    fn before_acquire<'a>(&'a self, _conn: &'a mut Conn) -> PoolHookFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Called when a connection is returned to the pool, after it is reset with
+    /// `pool_reset_policy`. On error, the connection is not reused.
+    fn after_release<'a>(&'a self, _conn: &'a mut Conn) -> PoolHookFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Connection pool.
+///
+/// With `pool_max_lifetime`, `pool_idle_timeout` or `pool_min_idle` set, a reaper
+/// task closes expired idle connections and opens new ones up to `pool_min_idle`.
+/// The task is spawned on the runtime the pool is created or first used on, and
+/// exits when the pool is closed, or dropped with all its connections.
 pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
     opts: Opts,
-    conns: ArrayQueue<Conn>,
+    /// Idle connections, least recently returned first
+    conns: Mutex<VecDeque<IdleConn>>,
     semaphore: Option<Arc<Semaphore>>,
+    reaper_started: AtomicBool,
+    counters: PoolCounters,
+    /// Notified when a connection is returned
+    returned: Notify,
+    hooks: Option<Box<dyn PoolHooks>>,
+    closed: AtomicBool,
+}
+
+/// Idle connection in the pool.
+struct IdleConn {
+    conn: Conn,
+    created_at: Instant,
+    idle_since: Instant,
 }
 
 impl Pool {
     pub fn new(opts: Opts) -> Self {
+        Self::build(opts, None)
+    }
+
+    /// Create a pool running `hooks` around connecting, checkout and check-in.
+    pub fn with_hooks(opts: Opts, hooks: impl PoolHooks + 'static) -> Self {
+        Self::build(opts, Some(Box::new(hooks)))
+    }
+
+    fn build(opts: Opts, hooks: Option<Box<dyn PoolHooks>>) -> Self {
         let semaphore = opts
             .pool_max_concurrency
             .map(|n| Arc::new(Semaphore::new(n)));
-        Self {
-            conns: ArrayQueue::new(opts.pool_max_idle_conn),
+        let inner = Arc::new(PoolInner {
+            conns: Mutex::new(VecDeque::with_capacity(opts.pool_max_idle_conn)),
             opts,
             semaphore,
-        }
+            reaper_started: AtomicBool::new(false),
+            counters: PoolCounters::default(),
+            returned: Notify::new(),
+            hooks,
+            closed: AtomicBool::new(false),
+        });
+        inner.start_reaper();
+        Self { inner }
     }
 
-    pub async fn get(self: &Arc<Self>) -> Result<PooledConn> {
-        let permit = if let Some(sem) = &self.semaphore {
-            Some(Arc::clone(sem).acquire_owned().await.unwrap())
-        } else {
-            None
-        };
-        let conn = loop {
-            match self.conns.pop() {
-                Some(mut c) => {
-                    if c.ping().await.is_ok() {
-                        break c;
+    pub async fn get(&self) -> Result<PooledConn> {
+        self.get_with_timeout(None).await
+    }
+
+    /// Get a connection, waiting at most `timeout` for one to be returned when
+    /// `pool_max_concurrency` connections are in use.
+    ///
+    /// Returns `Error::PoolExhausted` if no connection became available in time.
+    pub async fn get_timeout(&self, timeout: Duration) -> Result<PooledConn> {
+        self.get_with_timeout(Some(timeout)).await
+    }
+
+    /// Get a snapshot of the pool statistics.
+    pub fn stats(&self) -> PoolStats {
+        self.inner.counters.snapshot(self.inner.lock_idle().len())
+    }
+
+    /// Close the pool, waiting at most `timeout` for checked-out connections to be returned.
+    ///
+    /// Later calls to `get`, callers waiting for a connection and callers still
+    /// checking one out fail with `Error::PoolClosed`. Idle connections are
+    /// closed now, and checked-out connections when they are returned.
+    ///
+    /// Returns false if connections were still checked out after `timeout`.
+    pub async fn close(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        self.inner.closed.store(true, Ordering::SeqCst);
+        if let Some(sem) = &self.inner.semaphore {
+            sem.close();
+        }
+        self.inner.close_idle().await;
+
+        loop {
+            // Registered before checking `in_use`, so a return in between isn't missed
+            let mut returned = pin!(self.inner.returned.notified());
+            returned.as_mut().enable();
+            if self.inner.counters.in_use() == 0 {
+                return true;
+            }
+            match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if tokio::time::timeout(remaining, returned).await.is_err() {
+                        return false;
                     }
-                    // Connection dead, try next one
                 }
-                None => break Conn::new(self.opts.clone()).await?,
+                None => returned.await,
             }
+        }
+    }
+
+    /// Returns true if the pool was closed.
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    async fn get_with_timeout(&self, timeout: Option<Duration>) -> Result<PooledConn> {
+        if self.inner.is_closed() {
+            return Err(Error::PoolClosed);
+        }
+        self.inner.start_reaper();
+        let counters = &self.inner.counters;
+        let permit = if let Some(sem) = &self.inner.semaphore {
+            // Also ends the wait if this future is dropped while waiting
+            let waiting = counters.start_waiting();
+            let permit = acquire(sem, timeout).await;
+            drop(waiting);
+            Some(permit?)
+        } else {
+            counters.record_wait(Duration::ZERO);
+            None
         };
+        // Counted as in use before checking out, so `close` waits for the checkout.
+        // On error or cancellation, the slot and permit are released when dropped.
+        let in_use = InUse::new(&self.inner, permit);
+        if self.inner.is_closed() {
+            return Err(Error::PoolClosed);
+        }
+        let (conn, created_at) = self.inner.checkout().await?;
+        // Closed while checking out
+        if self.inner.is_closed() {
+            let _ = conn.close().await;
+            return Err(Error::PoolClosed);
+        }
         Ok(PooledConn {
             conn: ManuallyDrop::new(conn),
-            pool: Arc::clone(self),
-            _permit: permit,
+            created_at,
+            used: false,
+            in_use: ManuallyDrop::new(in_use),
         })
     }
+}
+
+/// Wait for a permit, up to `timeout` if given.
+async fn acquire(sem: &Arc<Semaphore>, timeout: Option<Duration>) -> Result<OwnedSemaphorePermit> {
+    let acquire = Arc::clone(sem).acquire_owned();
+    let permit = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, acquire)
+            .await
+            .map_err(|_elapsed| Error::PoolExhausted)?,
+        None => acquire.await,
+    };
+    // The semaphore is closed by `Pool::close`
+    permit.map_err(|_closed| Error::PoolClosed)
+}
 
-    async fn check_in(&self, mut conn: Conn) {
+impl PoolInner {
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    fn lock_idle(&self) -> MutexGuard<'_, VecDeque<IdleConn>> {
+        self.conns.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn pop_idle(&self) -> Option<IdleConn> {
+        self.lock_idle().pop_front()
+    }
+
+    /// Add an idle connection. Returns false, dropping the connection, if
+    /// `pool_max_idle_conn` connections are idle.
+    fn push_idle(&self, idle: IdleConn) -> bool {
+        let mut conns = self.lock_idle();
+        if conns.len() >= self.opts.pool_max_idle_conn {
+            return false;
+        }
+        conns.push_back(idle);
+        true
+    }
+
+    /// Close all idle connections.
+    async fn close_idle(&self) {
+        let conns = std::mem::take(&mut *self.lock_idle());
+        for idle in conns {
+            let _ = idle.conn.close().await;
+        }
+    }
+
+    /// Spawn the reaper task if the options need one, once a runtime is available.
+    fn start_reaper(self: &Arc<Self>) {
+        let Some(interval) = self.opts.pool_reaper_interval() else {
+            return;
+        };
+        if self.reaper_started.load(Ordering::Relaxed) {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        if !self.reaper_started.swap(true, Ordering::Relaxed) {
+            runtime.spawn(run_reaper(Arc::downgrade(self), interval));
+        }
+    }
+
+    /// Take a live idle connection, or open a new one.
+    async fn checkout(&self) -> Result<(Conn, Instant)> {
+        loop {
+            let Some(idle) = self.pop_idle() else {
+                return Ok((self.connect().await?, Instant::now()));
+            };
+            if self.is_expired(&idle, Instant::now()) {
+                continue;
+            }
+            let IdleConn {
+                mut conn,
+                created_at,
+                ..
+            } = idle;
+            if conn.ping().await.is_err() {
+                // Connection dead, try next one
+                self.counters.record_closed_on_error();
+                continue;
+            }
+            if let Some(hooks) = &self.hooks
+                && hooks.before_acquire(&mut conn).await.is_err()
+            {
+                self.counters.record_closed_on_error();
+                continue;
+            }
+            return Ok((conn, created_at));
+        }
+    }
+
+    /// Open a new connection and run the `after_connect` hook on it.
+    async fn connect(&self) -> Result<Conn> {
+        let mut conn = Conn::new(self.opts.clone()).await?;
+        self.counters.record_created();
+        if let Some(hooks) = &self.hooks
+            && let Err(e) = hooks.after_connect(&mut conn).await
+        {
+            self.counters.record_closed_on_error();
+            return Err(e);
+        }
+        Ok(conn)
+    }
+
+    async fn check_in(&self, mut conn: Conn, created_at: Instant, used: bool) {
+        if self.is_closed() {
+            let _ = conn.close().await;
+            return;
+        }
         if conn.is_broken() {
+            self.counters.record_closed_on_error();
+            return;
+        }
+        if self.is_too_old(created_at, Instant::now()) {
+            return;
+        }
+        // A connection that ran nothing since checkout is left as is
+        if used {
+            let policy = &self.opts.pool_reset_policy;
+            let status = conn.transaction_status();
+            if policy.rolls_back(status) && conn.query_drop("ROLLBACK").await.is_err() {
+                self.counters.record_closed_on_error();
+                return;
+            }
+            if let Some(sql) = policy.statement(status) {
+                if conn.query_drop(&sql).await.is_err() {
+                    self.counters.record_closed_on_error();
+                    return;
+                }
+                // Restore the session state undone by the reset
+                if let Some(hooks) = &self.hooks
+                    && hooks.after_connect(&mut conn).await.is_err()
+                {
+                    self.counters.record_closed_on_error();
+                    return;
+                }
+            }
+        }
+        if conn.in_transaction() {
+            // Not reset, so it can't be reused
             return;
         }
-        if conn.query_drop("DISCARD ALL").await.is_err() {
+        if let Some(hooks) = &self.hooks
+            && hooks.after_release(&mut conn).await.is_err()
+        {
+            self.counters.record_closed_on_error();
             return;
         }
-        let _ = self.conns.push(conn);
+        let _ = self.push_idle(IdleConn {
+            conn,
+            created_at,
+            idle_since: Instant::now(),
+        });
+        // Closed while returning the connection
+        if self.is_closed() {
+            self.close_idle().await;
+        }
+    }
+
+    fn is_too_old(&self, created_at: Instant, now: Instant) -> bool {
+        self.opts
+            .pool_max_lifetime
+            .is_some_and(|max_lifetime| now.duration_since(created_at) >= max_lifetime)
+    }
+
+    /// Returns true if the idle connection exceeded `pool_max_lifetime` or `pool_idle_timeout`.
+    fn is_expired(&self, idle: &IdleConn, now: Instant) -> bool {
+        self.is_too_old(idle.created_at, now)
+            || self
+                .opts
+                .pool_idle_timeout
+                .is_some_and(|timeout| now.duration_since(idle.idle_since) >= timeout)
+    }
+
+    /// Close expired idle connections and open new ones up to `pool_min_idle`.
+    async fn reap(&self) {
+        // Live connections stay in the queue, so `get` can take them meanwhile
+        let now = Instant::now();
+        let expired: VecDeque<IdleConn> = {
+            let mut conns = self.lock_idle();
+            let (expired, live) = std::mem::take(&mut *conns)
+                .into_iter()
+                .partition(|idle| self.is_expired(idle, now));
+            *conns = live;
+            expired
+        };
+        for idle in expired {
+            let _ = idle.conn.close().await;
+        }
+
+        while self.lock_idle().len() < self.opts.pool_min_idle && !self.is_closed() {
+            let Ok(conn) = self.connect().await else {
+                break;
+            };
+            let created_at = Instant::now();
+            let idle = IdleConn {
+                conn,
+                created_at,
+                idle_since: created_at,
+            };
+            if !self.push_idle(idle) {
+                break;
+            }
+        }
+        // Closed while opening connections
+        if self.is_closed() {
+            self.close_idle().await;
+        }
+    }
+}
+
+/// Reap the pool every `interval` until it is closed or dropped.
+async fn run_reaper(pool: Weak<PoolInner>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(pool) = pool.upgrade() else {
+            break;
+        };
+        if pool.is_closed() {
+            break;
+        }
+        pool.reap().await;
+    }
+}
+
+/// A connection slot in use, from before checkout until the connection is
+/// returned. Dropping it releases the slot and its permit and wakes `Pool::close`.
+struct InUse {
+    pool: Arc<PoolInner>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl InUse {
+    fn new(pool: &Arc<PoolInner>, permit: Option<OwnedSemaphorePermit>) -> Self {
+        pool.counters.checked_out();
+        Self {
+            pool: Arc::clone(pool),
+            permit,
+        }
+    }
+}
+
+impl Drop for InUse {
+    fn drop(&mut self) {
+        self.pool.counters.checked_in();
+        self.pool.returned.notify_waiters();
+        drop(self.permit.take());
     }
 }
 
 pub struct PooledConn {
-    pool: Arc<Pool>,
     conn: ManuallyDrop<Conn>,
-    _permit: Option<tokio::sync::OwnedSemaphorePermit>,
+    created_at: Instant,
+    /// Whether the connection was borrowed mutably, which any query needs
+    used: bool,
+    /// Released after the connection is checked in
+    in_use: ManuallyDrop<InUse>,
 }
 
 impl Deref for PooledConn {
@@ -80,6 +492,7 @@ impl Deref for PooledConn {
 
 impl DerefMut for PooledConn {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        self.used = true;
         &mut self.conn
     }
 }
@@ -88,9 +501,14 @@ impl Drop for PooledConn {
     fn drop(&mut self) {
         // SAFETY: conn is never accessed after this
         let conn = unsafe { ManuallyDrop::take(&mut self.conn) };
-        let pool = Arc::clone(&self.pool);
+        // SAFETY: in_use is never accessed after this
+        let in_use = unsafe { ManuallyDrop::take(&mut self.in_use) };
+        let created_at = self.created_at;
+        let used = self.used;
+        // Keep the slot and permit until the connection is back in the pool
         tokio::spawn(async move {
-            pool.check_in(conn).await;
+            in_use.pool.check_in(conn, created_at, used).await;
+            drop(in_use);
         });
     }
 }
@@ -1,16 +1,26 @@
 //! Async stream abstraction for tokio.
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf,
+};
 use tokio::net::TcpStream;
 use tokio::net::UnixStream;
 
-#[cfg(feature = "tokio-tls")]
-use tokio_native_tls::TlsStream;
+#[cfg(all(feature = "tokio-tls", not(feature = "tokio-rustls")))]
+type TlsStream = tokio_native_tls::TlsStream<TcpStream>;
+#[cfg(feature = "tokio-rustls")]
+type TlsStream = Box<tokio_rustls::client::TlsStream<TcpStream>>;
+
+use crate::opts::Opts;
 
 pub enum Stream {
     Tcp(BufReader<TcpStream>),
-    #[cfg(feature = "tokio-tls")]
-    Tls(BufReader<TlsStream<TcpStream>>),
+    #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
+    Tls(BufReader<TlsStream>),
     Unix(BufReader<UnixStream>),
 }
 
@@ -23,20 +33,34 @@ impl Stream {
         Self::Unix(BufReader::new(stream))
     }
 
-    /// Upgrade a TCP stream to TLS.
+    /// Connect to the Unix socket or TCP address in the options.
+    ///
+    /// Applies the connect timeout and TCP keepalive settings from the options.
+    pub async fn connect(opts: &Opts) -> Result<Self, crate::error::Error> {
+        if let Some(socket_path) = &opts.socket {
+            return Ok(Self::unix(UnixStream::connect(socket_path).await?));
+        }
+        if opts.host.is_empty() {
+            return Err(crate::error::Error::InvalidUsage("host is empty".into()));
+        }
+        let addr = format!("{}:{}", opts.host, opts.port);
+        let tcp = match opts.connect_timeout {
+            Some(timeout) => connect_timeout(&addr, timeout).await?,
+            None => TcpStream::connect(&addr).await?,
+        };
+        opts.configure_tcp(socket2::SockRef::from(&tcp))?;
+        Ok(Self::tcp(tcp))
+    }
+
+    /// Upgrade a TCP stream to TLS, using the SSL mode and certificates in `opts`.
     ///
     /// Returns an error if this is not a TCP stream or if the TLS handshake fails.
-    #[cfg(feature = "tokio-tls")]
-    pub async fn upgrade_to_tls(self, host: &str) -> Result<Self, crate::error::Error> {
+    #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
+    pub async fn upgrade_to_tls(self, opts: &Opts) -> Result<Self, crate::error::Error> {
         match self {
             Stream::Tcp(buf_reader) => {
-                let tcp_stream = buf_reader.into_inner();
-                let connector =
-                    tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
-                let tls_stream = connector
-                    .connect(host, tcp_stream)
-                    .await
-                    .map_err(|e| crate::error::Error::Tls(e.into()))?;
+                let tls_stream = tls_handshake(opts, buf_reader.into_inner()).await?;
+                crate::tls::check_alpn(opts, tls_alpn(&tls_stream).as_deref())?;
                 Ok(Stream::Tls(BufReader::new(tls_stream)))
             }
             Stream::Tls(_) => Err(crate::error::Error::InvalidUsage(
@@ -48,10 +72,22 @@ impl Stream {
         }
     }
 
+    /// Get the `tls-server-end-point` channel binding data of a TLS stream.
+    ///
+    /// Returns `None` if this is not a TLS stream or the hash of the server
+    /// certificate can't be computed.
+    #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
+    pub fn tls_server_end_point(&self) -> Option<Vec<u8>> {
+        match self {
+            Stream::Tls(r) => tls_server_end_point(r.get_ref()),
+            _ => None,
+        }
+    }
+
     pub async fn read_u8(&mut self) -> std::io::Result<u8> {
         match self {
             Stream::Tcp(r) => r.read_u8().await,
-            #[cfg(feature = "tokio-tls")]
+            #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
             Stream::Tls(r) => r.read_u8().await,
             Stream::Unix(r) => r.read_u8().await,
         }
@@ -76,10 +112,29 @@ impl Stream {
         Ok(())
     }
 
+    /// Wait until data is available to read, up to `timeout`.
+    ///
+    /// Returns `Ok(false)` if the timeout elapsed. No data is consumed, so a
+    /// subsequent `read_message` is not affected by the timeout.
+    pub async fn wait_readable(&mut self, timeout: Duration) -> std::io::Result<bool> {
+        let fill = async {
+            match self {
+                Stream::Tcp(r) => r.fill_buf().await.map(|_| ()),
+                #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
+                Stream::Tls(r) => r.fill_buf().await.map(|_| ()),
+                Stream::Unix(r) => r.fill_buf().await.map(|_| ()),
+            }
+        };
+        match tokio::time::timeout(timeout, fill).await {
+            Ok(result) => result.map(|()| true),
+            Err(_) => Ok(false),
+        }
+    }
+
     async fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
         match self {
             Stream::Tcp(r) => r.read_exact(buf).await.map(|_| ()),
-            #[cfg(feature = "tokio-tls")]
+            #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
             Stream::Tls(r) => r.read_exact(buf).await.map(|_| ()),
             Stream::Unix(r) => r.read_exact(buf).await.map(|_| ()),
         }
@@ -88,7 +143,7 @@ impl Stream {
     pub async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
         match self {
             Stream::Tcp(r) => r.get_mut().write_all(buf).await,
-            #[cfg(feature = "tokio-tls")]
+            #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
             Stream::Tls(r) => r.get_mut().write_all(buf).await,
             Stream::Unix(r) => r.get_mut().write_all(buf).await,
         }
@@ -97,7 +152,7 @@ impl Stream {
     pub async fn flush(&mut self) -> std::io::Result<()> {
         match self {
             Stream::Tcp(r) => r.get_mut().flush().await,
-            #[cfg(feature = "tokio-tls")]
+            #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
             Stream::Tls(r) => r.get_mut().flush().await,
             Stream::Unix(r) => r.get_mut().flush().await,
         }
@@ -110,12 +165,8 @@ impl Stream {
                 .peer_addr()
                 .map(|addr| addr.ip().is_loopback())
                 .unwrap_or(false),
-            #[cfg(feature = "tokio-tls")]
-            Self::Tls(r) => r
-                .get_ref() // &TlsStream<TcpStream> (tokio_native_tls)
-                .get_ref() // &native_tls::TlsStream<AllowStd<TcpStream>>
-                .get_ref() // &AllowStd<TcpStream>
-                .get_ref() // &TcpStream
+            #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
+            Self::Tls(r) => tls_tcp(r.get_ref())
                 .peer_addr()
                 .map(|addr| addr.ip().is_loopback())
                 .unwrap_or(false),
@@ -123,3 +174,135 @@ impl Stream {
         }
     }
 }
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(r) => Pin::new(r).poll_write(cx, buf),
+            #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
+            Stream::Tls(r) => Pin::new(r).poll_write(cx, buf),
+            Stream::Unix(r) => Pin::new(r).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(r) => Pin::new(r).poll_flush(cx),
+            #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
+            Stream::Tls(r) => Pin::new(r).poll_flush(cx),
+            Stream::Unix(r) => Pin::new(r).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(r) => Pin::new(r).poll_shutdown(cx),
+            #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
+            Stream::Tls(r) => Pin::new(r).poll_shutdown(cx),
+            Stream::Unix(r) => Pin::new(r).poll_shutdown(cx),
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(r) => Pin::new(r).poll_read(cx, buf),
+            #[cfg(any(feature = "tokio-tls", feature = "tokio-rustls"))]
+            Stream::Tls(r) => Pin::new(r).poll_read(cx, buf),
+            Stream::Unix(r) => Pin::new(r).poll_read(cx, buf),
+        }
+    }
+}
+
+/// Perform the TLS handshake with native-tls.
+#[cfg(all(feature = "tokio-tls", not(feature = "tokio-rustls")))]
+async fn tls_handshake(opts: &Opts, tcp: TcpStream) -> Result<TlsStream, crate::error::Error> {
+    let connector = tokio_native_tls::TlsConnector::from(crate::tls::native_tls_connector(opts)?);
+    connector
+        .connect(&opts.host, tcp)
+        .await
+        .map_err(crate::error::Error::Tls)
+}
+
+/// Perform the TLS handshake with rustls.
+#[cfg(feature = "tokio-rustls")]
+async fn tls_handshake(opts: &Opts, tcp: TcpStream) -> Result<TlsStream, crate::error::Error> {
+    let connector = tokio_rustls::TlsConnector::from(crate::tls::rustls_client_config(opts)?);
+    let tls_stream = connector
+        .connect(crate::tls::server_name(opts)?, tcp)
+        .await
+        .map_err(crate::tls::handshake_error)?;
+    Ok(Box::new(tls_stream))
+}
+
+/// Get the protocol selected by ALPN with native-tls.
+#[cfg(all(feature = "tokio-tls", not(feature = "tokio-rustls")))]
+fn tls_alpn(stream: &TlsStream) -> Option<Vec<u8>> {
+    stream.get_ref().negotiated_alpn().ok().flatten()
+}
+
+/// Get the protocol selected by ALPN with rustls.
+#[cfg(feature = "tokio-rustls")]
+fn tls_alpn(stream: &TlsStream) -> Option<Vec<u8>> {
+    stream.get_ref().1.alpn_protocol().map(<[u8]>::to_vec)
+}
+
+/// Get the `tls-server-end-point` channel binding data with native-tls.
+#[cfg(all(feature = "tokio-tls", not(feature = "tokio-rustls")))]
+fn tls_server_end_point(stream: &TlsStream) -> Option<Vec<u8>> {
+    stream.get_ref().tls_server_end_point().ok().flatten()
+}
+
+/// Get the `tls-server-end-point` channel binding data with rustls.
+#[cfg(feature = "tokio-rustls")]
+fn tls_server_end_point(stream: &TlsStream) -> Option<Vec<u8>> {
+    let cert = stream.get_ref().1.peer_certificates()?.first()?;
+    crate::tls::tls_server_end_point(cert)
+}
+
+/// Get the TCP stream under a TLS stream.
+#[cfg(all(feature = "tokio-tls", not(feature = "tokio-rustls")))]
+fn tls_tcp(stream: &TlsStream) -> &TcpStream {
+    stream
+        .get_ref() // &native_tls::TlsStream<AllowStd<TcpStream>>
+        .get_ref() // &AllowStd<TcpStream>
+        .get_ref() // &TcpStream
+}
+
+/// Get the TCP stream under a TLS stream.
+#[cfg(feature = "tokio-rustls")]
+fn tls_tcp(stream: &TlsStream) -> &TcpStream {
+    stream.get_ref().0
+}
+
+/// Connect to the first reachable address, with a timeout for each attempt.
+async fn connect_timeout(addr: &str, timeout: Duration) -> std::io::Result<TcpStream> {
+    let mut last_error = None;
+    for socket_addr in tokio::net::lookup_host(addr).await? {
+        match tokio::time::timeout(timeout, TcpStream::connect(socket_addr)).await {
+            Ok(Ok(tcp)) => return Ok(tcp),
+            Ok(Err(e)) => last_error = Some(e),
+            Err(_) => {
+                last_error = Some(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("connection to {} timed out", socket_addr),
+                ));
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("could not resolve {}", addr),
+        )
+    }))
+}
@@ -0,0 +1,59 @@
+//! Tests for query cancellation
+
+use std::env;
+use std::thread;
+use std::time::{Duration, Instant};
+use zero_postgres::sync::Conn;
+
+fn get_conn() -> Conn {
+    let mut db_url =
+        env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://localhost/postgres".to_string());
+    if !db_url.contains("sslmode=") {
+        if db_url.contains('?') {
+            db_url.push_str("&sslmode=disable");
+        } else {
+            db_url.push_str("?sslmode=disable");
+        }
+    }
+    Conn::new(db_url.as_str()).expect("Failed to connect")
+}
+
+#[test]
+fn test_cancel_running_query() {
+    let mut conn = get_conn();
+    let token = conn.cancel_token();
+
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        token.cancel().unwrap();
+    });
+
+    let start = Instant::now();
+    let err = conn.query_drop("SELECT pg_sleep(10)").unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(err.sqlstate(), Some("57014"));
+
+    canceller.join().unwrap();
+
+    // The connection is usable after the cancelled query
+    assert!(!conn.is_broken());
+    conn.query_drop("SELECT 1").unwrap();
+}
+
+#[test]
+fn test_cancel_idle_connection() {
+    let mut conn = get_conn();
+
+    // Cancelling when nothing is running has no effect
+    conn.cancel_token().cancel().unwrap();
+
+    let rows: Vec<(i32,)> = conn.query_collect("SELECT 1").unwrap();
+    assert_eq!(rows, vec![(1,)]);
+}
+
+#[test]
+fn test_cancel_token_is_send_sync() {
+    fn assert_send_sync<T: Send + Sync + Clone>() {}
+    assert_send_sync::<zero_postgres::sync::CancelToken>();
+    assert_send_sync::<zero_postgres::tokio::CancelToken>();
+}
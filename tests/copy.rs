@@ -0,0 +1,466 @@
+//! Tests for COPY FROM STDIN / TO STDOUT
+
+use std::env;
+use std::io::{Read, Write};
+use zero_postgres::Error;
+use zero_postgres::copy::CopyTextOptions;
+use zero_postgres::protocol::types::oid;
+use zero_postgres::sync::{
+    BinaryCopyInWriter, BinaryCopyOutReader, Conn, TextCopyInWriter, TextCopyOutReader,
+};
+
+fn get_conn() -> Conn {
+    let mut db_url =
+        env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://localhost/postgres".to_string());
+    if !db_url.contains("sslmode=") {
+        if db_url.contains('?') {
+            db_url.push_str("&sslmode=disable");
+        } else {
+            db_url.push_str("?sslmode=disable");
+        }
+    }
+    Conn::new(db_url.as_str()).expect("Failed to connect")
+}
+
+fn create_table(conn: &mut Conn) {
+    conn.query_drop("CREATE TEMP TABLE copy_test (id INT, name TEXT)")
+        .unwrap();
+}
+
+fn count_rows(conn: &mut Conn) -> i64 {
+    let count: Vec<(i64,)> = conn
+        .query_collect("SELECT COUNT(*) FROM copy_test")
+        .unwrap();
+    count[0].0
+}
+
+#[test]
+fn test_copy_in_text() {
+    let mut conn = get_conn();
+    create_table(&mut conn);
+
+    let mut writer = conn
+        .copy_in("COPY copy_test (id, name) FROM STDIN")
+        .unwrap();
+    writer.write_all(b"1\talice\n2\tbob\n").unwrap();
+    writer.write_all(b"3\t\\N\n").unwrap();
+    let rows = writer.finish().unwrap();
+    assert_eq!(rows, 3);
+
+    let rows: Vec<(i32, Option<String>)> = conn
+        .query_collect("SELECT id, name FROM copy_test ORDER BY id")
+        .unwrap();
+    assert_eq!(
+        rows,
+        vec![
+            (1, Some("alice".to_string())),
+            (2, Some("bob".to_string())),
+            (3, None),
+        ]
+    );
+}
+
+#[test]
+fn test_copy_in_large() {
+    let mut conn = get_conn();
+    create_table(&mut conn);
+
+    let mut writer = conn
+        .copy_in("COPY copy_test (id, name) FROM STDIN")
+        .unwrap();
+    for i in 0..100_000 {
+        writeln!(writer, "{}\tname_{}", i, i).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), 100_000);
+    assert_eq!(count_rows(&mut conn), 100_000);
+}
+
+#[test]
+fn test_copy_in_abort() {
+    let mut conn = get_conn();
+    create_table(&mut conn);
+
+    let mut writer = conn
+        .copy_in("COPY copy_test (id, name) FROM STDIN")
+        .unwrap();
+    writer.write_all(b"1\talice\n").unwrap();
+    writer.abort("cancelled by test").unwrap();
+
+    assert_eq!(count_rows(&mut conn), 0);
+}
+
+#[test]
+fn test_copy_in_drop_aborts() {
+    let mut conn = get_conn();
+    create_table(&mut conn);
+
+    {
+        let mut writer = conn
+            .copy_in("COPY copy_test (id, name) FROM STDIN")
+            .unwrap();
+        writer.write_all(b"1\talice\n").unwrap();
+    }
+
+    assert!(!conn.is_broken());
+    assert_eq!(count_rows(&mut conn), 0);
+}
+
+#[test]
+fn test_copy_in_bad_data() {
+    let mut conn = get_conn();
+    create_table(&mut conn);
+
+    let mut writer = conn
+        .copy_in("COPY copy_test (id, name) FROM STDIN")
+        .unwrap();
+    writer.write_all(b"not_a_number\talice\n").unwrap();
+    let err = writer.finish().unwrap_err();
+    assert!(matches!(err, Error::Server(_)));
+
+    // Connection is still usable
+    assert_eq!(count_rows(&mut conn), 0);
+}
+
+#[test]
+fn test_copy_in_not_copy_statement() {
+    let mut conn = get_conn();
+
+    let err = conn.copy_in("SELECT 1").err().unwrap();
+    assert!(matches!(err, Error::InvalidUsage(_)));
+
+    let rows: Vec<(i32,)> = conn.query_collect("SELECT 2").unwrap();
+    assert_eq!(rows, vec![(2,)]);
+}
+
+#[test]
+fn test_copy_out_read() {
+    let mut conn = get_conn();
+
+    let mut reader = conn
+        .copy_out("COPY (SELECT i, 'name_' || i FROM generate_series(1, 3) i) TO STDOUT")
+        .unwrap();
+    let mut data = String::new();
+    reader.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "1\tname_1\n2\tname_2\n3\tname_3\n");
+    assert_eq!(reader.finish().unwrap(), 3);
+}
+
+#[test]
+fn test_copy_out_chunks() {
+    let mut conn = get_conn();
+
+    let mut reader = conn
+        .copy_out("COPY (SELECT i FROM generate_series(1, 100000) i) TO STDOUT (FORMAT csv)")
+        .unwrap();
+    let mut count = 0;
+    let mut total = 0;
+    while let Some(chunk) = reader.next_chunk().unwrap() {
+        count += 1;
+        total += chunk.len();
+    }
+    assert_eq!(count, 100_000);
+    assert_eq!(total, 588_895);
+    assert_eq!(reader.finish().unwrap(), 100_000);
+}
+
+#[test]
+fn test_copy_out_small_reads() {
+    let mut conn = get_conn();
+
+    let mut reader = conn.copy_out("COPY (SELECT 'abcdef') TO STDOUT").unwrap();
+    let mut buf = [0u8; 4];
+    assert_eq!(reader.read(&mut buf).unwrap(), 4);
+    assert_eq!(&buf, b"abcd");
+    assert_eq!(reader.read(&mut buf).unwrap(), 3);
+    assert_eq!(&buf[..3], b"ef\n");
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn test_copy_out_table_roundtrip() {
+    let mut conn = get_conn();
+    create_table(&mut conn);
+
+    let mut writer = conn
+        .copy_in("COPY copy_test (id, name) FROM STDIN")
+        .unwrap();
+    writer.write_all(b"1\talice\n2\t\\N\n").unwrap();
+    writer.finish().unwrap();
+
+    let mut data = Vec::new();
+    let mut reader = conn.copy_out("COPY copy_test TO STDOUT").unwrap();
+    std::io::copy(&mut reader, &mut data).unwrap();
+    drop(reader);
+    assert_eq!(data, b"1\talice\n2\t\\N\n");
+}
+
+#[test]
+fn test_copy_out_drop_drains() {
+    let mut conn = get_conn();
+
+    {
+        let mut reader = conn
+            .copy_out("COPY (SELECT i FROM generate_series(1, 1000) i) TO STDOUT")
+            .unwrap();
+        assert!(reader.next_chunk().unwrap().is_some());
+    }
+
+    assert!(!conn.is_broken());
+    let rows: Vec<(i32,)> = conn.query_collect("SELECT 2").unwrap();
+    assert_eq!(rows, vec![(2,)]);
+}
+
+#[test]
+fn test_copy_out_server_error() {
+    let mut conn = get_conn();
+
+    let mut reader = conn
+        .copy_out("COPY (SELECT 1 / (i - 3) FROM generate_series(1, 5) i) TO STDOUT")
+        .unwrap();
+    let mut data = Vec::new();
+    assert!(reader.read_to_end(&mut data).is_err());
+    drop(reader);
+
+    // Connection is still usable
+    let rows: Vec<(i32,)> = conn.query_collect("SELECT 2").unwrap();
+    assert_eq!(rows, vec![(2,)]);
+}
+
+#[test]
+fn test_copy_out_not_copy_statement() {
+    let mut conn = get_conn();
+    create_table(&mut conn);
+
+    let err = conn.copy_out("COPY copy_test FROM STDIN").err().unwrap();
+    assert!(matches!(err, Error::InvalidUsage(_)));
+
+    let rows: Vec<(i32,)> = conn.query_collect("SELECT 2").unwrap();
+    assert_eq!(rows, vec![(2,)]);
+}
+
+#[test]
+fn test_binary_copy_in() {
+    let mut conn = get_conn();
+    conn.query_drop(
+        "CREATE TEMP TABLE copy_binary_test (id INT8, name TEXT, score FLOAT8, active BOOL)",
+    )
+    .unwrap();
+
+    let copy_in = conn
+        .copy_in("COPY copy_binary_test FROM STDIN (FORMAT binary)")
+        .unwrap();
+    let mut writer =
+        BinaryCopyInWriter::new(copy_in, &[oid::INT8, oid::TEXT, oid::FLOAT8, oid::BOOL]).unwrap();
+    for i in 0..10_000_i64 {
+        writer
+            .write_row(&(i, format!("name_{}", i), i as f64 / 2.0, i % 2 == 0))
+            .unwrap();
+    }
+    writer
+        .write_row(&(-1_i64, None::<String>, None::<f64>, None::<bool>))
+        .unwrap();
+    assert_eq!(writer.finish().unwrap(), 10_001);
+
+    let rows: Vec<(i64, Option<String>, Option<f64>, Option<bool>)> = conn
+        .query_collect("SELECT id, name, score, active FROM copy_binary_test WHERE id IN (-1, 3) ORDER BY id DESC")
+        .unwrap();
+    assert_eq!(
+        rows,
+        vec![
+            (3, Some("name_3".to_string()), Some(1.5), Some(false)),
+            (-1, None, None, None),
+        ]
+    );
+}
+
+#[test]
+fn test_binary_copy_in_requires_binary_format() {
+    let mut conn = get_conn();
+    create_table(&mut conn);
+
+    let copy_in = conn.copy_in("COPY copy_test FROM STDIN").unwrap();
+    let err = BinaryCopyInWriter::new(copy_in, &[oid::INT4, oid::TEXT])
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::InvalidUsage(_)));
+
+    // The COPY is aborted when the CopyIn is dropped
+    assert_eq!(count_rows(&mut conn), 0);
+}
+
+#[test]
+fn test_binary_copy_out() {
+    let mut conn = get_conn();
+
+    let copy_out = conn
+        .copy_out(
+            "COPY (SELECT i::int8, 'name_' || i, CASE WHEN i % 2 = 0 THEN i / 2.0::float8 END \
+             FROM generate_series(1, 10000) i) TO STDOUT (FORMAT binary)",
+        )
+        .unwrap();
+    let mut reader =
+        BinaryCopyOutReader::new(copy_out, &[oid::INT8, oid::TEXT, oid::FLOAT8]).unwrap();
+    let mut rows = Vec::new();
+    while let Some(row) = reader.read_row::<(i64, String, Option<f64>)>().unwrap() {
+        rows.push(row);
+    }
+    assert_eq!(reader.finish().unwrap(), 10_000);
+
+    assert_eq!(rows.len(), 10_000);
+    assert_eq!(rows[0], (1, "name_1".to_string(), None));
+    assert_eq!(rows[1], (2, "name_2".to_string(), Some(1.0)));
+    assert_eq!(
+        rows[9_999],
+        (10_000, "name_10000".to_string(), Some(5_000.0))
+    );
+}
+
+#[test]
+fn test_binary_copy_roundtrip() {
+    let mut conn = get_conn();
+    create_table(&mut conn);
+
+    let copy_in = conn
+        .copy_in("COPY copy_test FROM STDIN (FORMAT binary)")
+        .unwrap();
+    let mut writer = BinaryCopyInWriter::new(copy_in, &[oid::INT4, oid::TEXT]).unwrap();
+    writer.write_row(&(1, "alice")).unwrap();
+    writer.write_row(&(2, None::<&str>)).unwrap();
+    writer.finish().unwrap();
+
+    let copy_out = conn
+        .copy_out("COPY copy_test TO STDOUT (FORMAT binary)")
+        .unwrap();
+    let mut reader = BinaryCopyOutReader::new(copy_out, &[oid::INT4, oid::TEXT]).unwrap();
+    assert_eq!(
+        reader.read_row::<(i32, Option<String>)>().unwrap(),
+        Some((1, Some("alice".to_string())))
+    );
+    assert_eq!(
+        reader.read_row::<(i32, Option<String>)>().unwrap(),
+        Some((2, None))
+    );
+    assert_eq!(reader.read_row::<(i32, Option<String>)>().unwrap(), None);
+    assert_eq!(reader.finish().unwrap(), 2);
+}
+
+fn text_copy_roundtrip(options: CopyTextOptions, copy_options: &str) {
+    let mut conn = get_conn();
+    create_table(&mut conn);
+
+    let values = [
+        Some("plain"),
+        None,
+        Some(""),
+        Some("tab\there\nnewline\r\\backslash"),
+        Some("comma, \"quote\" 'single' | pipe"),
+        Some("\\N"),
+        Some("NULL"),
+        Some("\\."),
+    ];
+
+    let copy_in = conn
+        .copy_in(&format!("COPY copy_test FROM STDIN ({})", copy_options))
+        .unwrap();
+    let mut writer = TextCopyInWriter::new(copy_in, options.clone()).unwrap();
+    for (i, value) in values.iter().enumerate() {
+        writer.write_row(&(i as i32, *value)).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), values.len() as u64);
+
+    // The server parsed the values as written
+    let rows: Vec<(i32, Option<String>)> = conn
+        .query_collect("SELECT id, name FROM copy_test ORDER BY id")
+        .unwrap();
+    let expected: Vec<(i32, Option<String>)> = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| (i as i32, value.map(str::to_string)))
+        .collect();
+    assert_eq!(rows, expected);
+
+    // The server output is parsed back to the same values
+    let copy_out = conn
+        .copy_out(&format!(
+            "COPY (SELECT * FROM copy_test ORDER BY id) TO STDOUT ({})",
+            copy_options
+        ))
+        .unwrap();
+    let mut reader = TextCopyOutReader::new(copy_out, options, &[oid::INT4, oid::TEXT]).unwrap();
+    let mut rows = Vec::new();
+    while let Some(row) = reader.read_row::<(i32, Option<String>)>().unwrap() {
+        rows.push(row);
+    }
+    assert_eq!(rows, expected);
+    assert_eq!(reader.finish().unwrap(), values.len() as u64);
+}
+
+#[test]
+fn test_text_copy_roundtrip() {
+    text_copy_roundtrip(CopyTextOptions::text(), "FORMAT text");
+}
+
+#[test]
+fn test_text_copy_roundtrip_custom() {
+    let options = CopyTextOptions {
+        delimiter: b'|',
+        null: "<null>".into(),
+        ..CopyTextOptions::text()
+    };
+    text_copy_roundtrip(options, "FORMAT text, DELIMITER '|', NULL '<null>'");
+}
+
+#[test]
+fn test_text_copy_value_equal_to_null() {
+    let mut conn = get_conn();
+    create_table(&mut conn);
+
+    let options = CopyTextOptions {
+        null: "NULL".into(),
+        ..CopyTextOptions::text()
+    };
+    let copy_in = conn
+        .copy_in("COPY copy_test FROM STDIN (FORMAT text, NULL 'NULL')")
+        .unwrap();
+    let mut writer = TextCopyInWriter::new(copy_in, options).unwrap();
+    writer.write_row(&(1, Some("NULL"))).unwrap();
+    writer.write_row(&(2, None::<&str>)).unwrap();
+    assert_eq!(writer.finish().unwrap(), 2);
+
+    let rows: Vec<(i32, Option<String>)> = conn
+        .query_collect("SELECT id, name FROM copy_test ORDER BY id")
+        .unwrap();
+    assert_eq!(rows, vec![(1, Some("NULL".to_string())), (2, None)]);
+
+    // An empty string can't be written with an empty NULL string
+    let options = CopyTextOptions {
+        null: String::new(),
+        ..CopyTextOptions::text()
+    };
+    let copy_in = conn
+        .copy_in("COPY copy_test FROM STDIN (FORMAT text, NULL '')")
+        .unwrap();
+    let Err(Error::InvalidUsage(_)) = TextCopyInWriter::new(copy_in, options) else {
+        panic!("expected InvalidUsage error");
+    };
+}
+
+#[test]
+fn test_csv_copy_roundtrip() {
+    text_copy_roundtrip(CopyTextOptions::csv(), "FORMAT csv");
+}
+
+#[test]
+fn test_csv_copy_roundtrip_custom() {
+    let options = CopyTextOptions {
+        delimiter: b'|',
+        null: "NULL".into(),
+        quote: b'\'',
+        escape: b'\\',
+        ..CopyTextOptions::csv()
+    };
+    text_copy_roundtrip(
+        options,
+        "FORMAT csv, DELIMITER '|', NULL 'NULL', QUOTE '''', ESCAPE '\\'",
+    );
+}
@@ -0,0 +1,95 @@
+//! Tests for multiple hosts and target_session_attrs
+
+use std::env;
+use zero_postgres::sync::Conn;
+use zero_postgres::{Error, LoadBalanceHosts, Opts, TargetSessionAttrs};
+
+fn get_opts() -> Opts {
+    let mut db_url =
+        env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://localhost/postgres".to_string());
+    if !db_url.contains("sslmode=") {
+        if db_url.contains('?') {
+            db_url.push_str("&sslmode=disable");
+        } else {
+            db_url.push_str("?sslmode=disable");
+        }
+    }
+    Opts::try_from(db_url.as_str()).expect("Failed to parse DATABASE_URL")
+}
+
+/// Options listing a closed port before the test server.
+fn get_multi_host_opts() -> Opts {
+    let mut opts = get_opts();
+    opts.hosts = vec![("127.0.0.1".to_string(), 1), (opts.host.clone(), opts.port)];
+    opts
+}
+
+#[test]
+fn test_failover_to_next_host() {
+    let mut conn = Conn::new(get_multi_host_opts()).expect("Failed to connect");
+    let row: Option<(i32,)> = conn.query_first("SELECT 1").unwrap();
+    assert_eq!(row, Some((1,)));
+}
+
+#[test]
+fn test_load_balance_random() {
+    let mut opts = get_multi_host_opts();
+    opts.load_balance_hosts = LoadBalanceHosts::Random;
+    for _ in 0..4 {
+        let mut conn = Conn::new(opts.clone()).expect("Failed to connect");
+        let row: Option<(i32,)> = conn.query_first("SELECT 1").unwrap();
+        assert_eq!(row, Some((1,)));
+    }
+}
+
+#[test]
+fn test_all_hosts_unreachable() {
+    let mut opts = get_opts();
+    opts.hosts = vec![("127.0.0.1".to_string(), 1), ("127.0.0.1".to_string(), 2)];
+    assert!(Conn::new(opts).is_err());
+}
+
+#[test]
+fn test_target_session_attrs_primary() {
+    for attrs in [
+        TargetSessionAttrs::Any,
+        TargetSessionAttrs::ReadWrite,
+        TargetSessionAttrs::Primary,
+        TargetSessionAttrs::PreferStandby,
+    ] {
+        let mut opts = get_multi_host_opts();
+        opts.target_session_attrs = attrs;
+        Conn::new(opts).unwrap_or_else(|e| panic!("{:?} failed: {}", attrs, e));
+    }
+}
+
+#[test]
+fn test_target_session_attrs_mismatch() {
+    for attrs in [TargetSessionAttrs::Standby, TargetSessionAttrs::ReadOnly] {
+        let mut opts = get_opts();
+        opts.target_session_attrs = attrs;
+        let err = Conn::new(opts).err().expect("Expected mismatch");
+        assert!(
+            matches!(err, Error::SessionAttrsMismatch(_)),
+            "{:?}: unexpected error: {}",
+            attrs,
+            err
+        );
+    }
+}
+
+#[test]
+fn test_target_session_attrs_read_only_session() {
+    let mut opts = get_opts();
+    opts.params.push((
+        "default_transaction_read_only".to_string(),
+        "on".to_string(),
+    ));
+
+    opts.target_session_attrs = TargetSessionAttrs::ReadOnly;
+    Conn::new(opts.clone()).expect("read-only session should match read-only");
+
+    opts.target_session_attrs = TargetSessionAttrs::ReadWrite;
+    let err = Conn::new(opts).err().expect("Expected mismatch");
+    assert!(matches!(err, Error::SessionAttrsMismatch(_)));
+}
@@ -0,0 +1,138 @@
+//! Tests for OAUTHBEARER authentication against a scripted fake server
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use zero_postgres::sync::Conn;
+use zero_postgres::{Error, Opts};
+
+const DISCOVERY: &str = r#"{"status":"invalid_token","openid-configuration":"https://sso.example.com/.well-known/openid-configuration","scope":"openid db"}"#;
+
+fn get_opts(port: u16, token: Option<&'static str>) -> Opts {
+    let url = format!("postgres://app@127.0.0.1:{}/app?sslmode=disable", port);
+    let mut opts = Opts::try_from(url.as_str()).unwrap();
+    opts.prefer_unix_socket = false;
+    opts.oauth_token_provider = Some(Arc::new(move |_: &Opts| Ok(token.map(String::from))));
+    opts
+}
+
+/// Run a fake server for one connection, returning its port and the SASL responses it received.
+fn fake_server(accept_token: &'static str) -> (u16, JoinHandle<Vec<Vec<u8>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+
+        // StartupMessage
+        let len = read_i32(&mut stream);
+        read_bytes(&mut stream, len - 4);
+
+        write_message(&mut stream, b'R', &sasl_mechanisms(&["OAUTHBEARER"]));
+        let (type_byte, payload) = read_message(&mut stream);
+        assert_eq!(type_byte, b'p');
+        let initial_response = payload[b"OAUTHBEARER\0".len() + 4..].to_vec();
+        received.push(initial_response.clone());
+
+        let expected = format!("n,,\x01auth=Bearer {}\x01\x01", accept_token);
+        if initial_response == expected.as_bytes() {
+            write_message(&mut stream, b'R', &0_i32.to_be_bytes());
+            write_message(&mut stream, b'K', &[0, 0, 0, 42, 1, 2, 3, 4]);
+            write_message(&mut stream, b'Z', b"I");
+        } else {
+            let mut payload = 11_i32.to_be_bytes().to_vec();
+            payload.extend_from_slice(DISCOVERY.as_bytes());
+            write_message(&mut stream, b'R', &payload);
+            let (type_byte, payload) = read_message(&mut stream);
+            assert_eq!(type_byte, b'p');
+            received.push(payload);
+            write_message(
+                &mut stream,
+                b'E',
+                b"SFATAL\0C28000\0MOAuth bearer authentication failed for user \"app\"\0\0",
+            );
+        }
+        received
+    });
+    (port, handle)
+}
+
+fn sasl_mechanisms(mechanisms: &[&str]) -> Vec<u8> {
+    let mut payload = 10_i32.to_be_bytes().to_vec();
+    for mechanism in mechanisms {
+        payload.extend_from_slice(mechanism.as_bytes());
+        payload.push(0);
+    }
+    payload.push(0);
+    payload
+}
+
+fn read_i32(stream: &mut TcpStream) -> usize {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len).unwrap();
+    i32::from_be_bytes(len) as usize
+}
+
+fn read_bytes(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    stream.read_exact(&mut buf).unwrap();
+    buf
+}
+
+fn read_message(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut type_byte = [0];
+    stream.read_exact(&mut type_byte).unwrap();
+    let len = read_i32(stream);
+    (type_byte[0], read_bytes(stream, len - 4))
+}
+
+fn write_message(stream: &mut TcpStream, type_byte: u8, payload: &[u8]) {
+    let mut msg = vec![type_byte];
+    msg.extend_from_slice(&(payload.len() as i32 + 4).to_be_bytes());
+    msg.extend_from_slice(payload);
+    stream.write_all(&msg).unwrap();
+}
+
+#[test]
+fn test_oauth_bearer_token() {
+    let (port, server) = fake_server("abc.def");
+    let conn = Conn::new(get_opts(port, Some("abc.def"))).expect("Failed to connect");
+    assert_eq!(conn.backend_key().unwrap().process_id(), 42);
+    drop(conn);
+
+    let received = server.join().unwrap();
+    assert_eq!(
+        received,
+        vec![b"n,,\x01auth=Bearer abc.def\x01\x01".to_vec()]
+    );
+}
+
+#[test]
+fn test_oauth_bearer_token_rejected() {
+    let (port, server) = fake_server("abc.def");
+    let Err(Error::OAuthBearer(discovery)) = Conn::new(get_opts(port, Some("expired"))) else {
+        panic!("expected OAuthBearer error");
+    };
+    assert_eq!(discovery.status, "invalid_token");
+    assert_eq!(discovery.scope.as_deref(), Some("openid db"));
+
+    // The error response is acknowledged with a single 0x01
+    let received = server.join().unwrap();
+    assert_eq!(received[1], b"\x01");
+}
+
+#[test]
+fn test_oauth_bearer_discovery() {
+    let (port, server) = fake_server("abc.def");
+    let Err(Error::OAuthBearer(discovery)) = Conn::new(get_opts(port, None)) else {
+        panic!("expected OAuthBearer error");
+    };
+    assert_eq!(
+        discovery.openid_configuration.as_deref(),
+        Some("https://sso.example.com/.well-known/openid-configuration")
+    );
+
+    let received = server.join().unwrap();
+    assert_eq!(received[0], b"n,,\x01\x01");
+}
@@ -0,0 +1,481 @@
+//! Tests for connection pool lifecycle limits, statistics, hooks and shutdown
+
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use zero_postgres::sync::{Conn, Pool, PoolHooks};
+use zero_postgres::{Error, Opts, PoolResetPolicy, Result};
+
+fn get_opts() -> Opts {
+    let mut db_url =
+        env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://localhost/postgres".to_string());
+    if !db_url.contains("sslmode=") {
+        if db_url.contains('?') {
+            db_url.push_str("&sslmode=disable");
+        } else {
+            db_url.push_str("?sslmode=disable");
+        }
+    }
+    Opts::try_from(db_url.as_str()).expect("Failed to parse DATABASE_URL")
+}
+
+fn backend_pid(pool: &Pool) -> u32 {
+    let conn = pool.get().expect("Failed to get connection");
+    conn.backend_key().unwrap().process_id()
+}
+
+#[test]
+fn test_pool_reuses_connection() {
+    let pool = Pool::new(get_opts());
+    assert_eq!(backend_pid(&pool), backend_pid(&pool));
+}
+
+#[test]
+fn test_pool_idle_timeout() {
+    let mut opts = get_opts();
+    opts.pool_idle_timeout = Some(Duration::from_millis(200));
+    let pool = Pool::new(opts);
+
+    let pid = backend_pid(&pool);
+    assert_eq!(backend_pid(&pool), pid);
+    thread::sleep(Duration::from_millis(500));
+    assert_ne!(backend_pid(&pool), pid);
+}
+
+#[test]
+fn test_pool_max_lifetime() {
+    let mut opts = get_opts();
+    opts.pool_max_lifetime = Some(Duration::from_millis(300));
+    let pool = Pool::new(opts);
+
+    let mut conn = pool.get().unwrap();
+    let pid = conn.backend_key().unwrap().process_id();
+    thread::sleep(Duration::from_millis(400));
+    conn.query_drop("SELECT 1").unwrap();
+    // Not returned to the pool after its lifetime
+    drop(conn);
+    assert_ne!(backend_pid(&pool), pid);
+}
+
+#[test]
+fn test_pool_min_idle() {
+    let application_name = "zero_postgres_pool_min_idle";
+    let mut opts = get_opts();
+    opts.application_name = Some(application_name.into());
+    opts.pool_min_idle = 2;
+    let pool = Pool::new(opts);
+
+    let mut conn = Conn::new(get_opts()).unwrap();
+    let count_sql = format!(
+        "SELECT count(*)::int4 FROM pg_stat_activity WHERE application_name = '{}'",
+        application_name
+    );
+    // The reaper opens the idle connections
+    thread::sleep(Duration::from_millis(1500));
+    let count: Option<(i32,)> = conn.query_first(&count_sql).unwrap();
+    assert_eq!(count, Some((2,)));
+
+    // Connections are closed with the pool
+    drop(pool);
+    thread::sleep(Duration::from_millis(300));
+    let count: Option<(i32,)> = conn.query_first(&count_sql).unwrap();
+    assert_eq!(count, Some((0,)));
+}
+
+#[test]
+fn test_pool_get_timeout() {
+    let mut opts = get_opts();
+    opts.pool_max_concurrency = Some(1);
+    let pool = Pool::new(opts);
+
+    let conn = pool.get_timeout(Duration::from_secs(5)).unwrap();
+    let Err(Error::PoolExhausted) = pool.get_timeout(Duration::from_millis(100)) else {
+        panic!("expected PoolExhausted error");
+    };
+    drop(conn);
+    assert!(pool.get_timeout(Duration::from_millis(100)).is_ok());
+}
+
+#[tokio::test]
+async fn test_tokio_pool_cancelled_wait() {
+    let mut opts = get_opts();
+    opts.pool_max_concurrency = Some(1);
+    let pool = zero_postgres::tokio::Pool::new(opts);
+
+    let conn = pool.get().await.unwrap();
+    // Dropping a waiting `get` future ends its wait
+    let waited = tokio::time::timeout(Duration::from_millis(100), pool.get()).await;
+    assert!(waited.is_err());
+    let stats = pool.stats();
+    assert_eq!(stats.waiting, 0);
+    assert_eq!(stats.wait_time.count(), 2);
+    drop(conn);
+}
+
+#[test]
+fn test_pool_stats() {
+    let mut opts = get_opts();
+    opts.pool_max_concurrency = Some(2);
+    let pool = Pool::new(opts);
+
+    let conn = pool.get().unwrap();
+    let stats = pool.stats();
+    assert_eq!(stats.idle, 0);
+    assert_eq!(stats.in_use, 1);
+    assert_eq!(stats.waiting, 0);
+    assert_eq!(stats.total_created, 1);
+    drop(conn);
+
+    let _conn = pool.get().unwrap();
+    let stats = pool.stats();
+    assert_eq!(stats.in_use, 1);
+    assert_eq!(stats.total_created, 1);
+    assert_eq!(stats.total_closed_on_error, 0);
+    assert_eq!(stats.wait_time.count(), 2);
+}
+
+#[test]
+fn test_pool_reset_policy() {
+    let mut opts = get_opts();
+    opts.pool_reset_policy = PoolResetPolicy::ResetAll;
+    let pool = Pool::new(opts);
+
+    let mut conn = pool.get().unwrap();
+    let stmt = conn.prepare("SELECT $1::int4").unwrap();
+    conn.query_drop("SET application_name = 'zero_postgres_reset'")
+        .unwrap();
+    conn.query_drop("BEGIN").unwrap();
+    drop(conn);
+
+    // The prepared statement survives, the session parameter and transaction don't
+    let mut conn = pool.get().unwrap();
+    assert!(!conn.in_transaction());
+    let row: Option<(i32,)> = conn.exec_first(&stmt, (7,)).unwrap();
+    assert_eq!(row, Some((7,)));
+    let row: Option<(String,)> = conn.query_first("SHOW application_name").unwrap();
+    assert_ne!(row, Some(("zero_postgres_reset".to_string(),)));
+}
+
+struct SearchPathHooks(Arc<AtomicUsize>);
+
+impl PoolHooks for SearchPathHooks {
+    fn after_connect(&self, conn: &mut Conn) -> Result<()> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        conn.query_drop("SET search_path TO pg_catalog")?;
+        Ok(())
+    }
+}
+
+fn search_path(conn: &mut Conn) -> String {
+    let row: Option<(String,)> = conn.query_first("SHOW search_path").unwrap();
+    row.unwrap().0
+}
+
+#[test]
+fn test_pool_after_connect_kept_without_reset() {
+    let connects = Arc::new(AtomicUsize::new(0));
+    let mut opts = get_opts();
+    opts.pool_reset_policy = PoolResetPolicy::RollbackIfInTx;
+    let pool = Pool::with_hooks(opts, SearchPathHooks(Arc::clone(&connects)));
+
+    let mut conn = pool.get().unwrap();
+    let pid = conn.backend_key().unwrap().process_id();
+    assert_eq!(search_path(&mut conn), "pg_catalog");
+    drop(conn);
+
+    // The session state survives reuse, set up once
+    for _ in 0..2 {
+        let mut conn = pool.get().unwrap();
+        assert_eq!(conn.backend_key().unwrap().process_id(), pid);
+        assert_eq!(search_path(&mut conn), "pg_catalog");
+    }
+    assert_eq!(connects.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn test_pool_after_connect_rerun_after_reset() {
+    let connects = Arc::new(AtomicUsize::new(0));
+    let pool = Pool::with_hooks(get_opts(), SearchPathHooks(Arc::clone(&connects)));
+
+    // Not reset, as nothing ran on it
+    let conn = pool.get().unwrap();
+    let pid = conn.backend_key().unwrap().process_id();
+    drop(conn);
+    assert_eq!(connects.load(Ordering::Relaxed), 1);
+
+    // Reset with DISCARD ALL after use, then set up again
+    let mut conn = pool.get().unwrap();
+    conn.query_drop("SET search_path TO public").unwrap();
+    drop(conn);
+    assert_eq!(connects.load(Ordering::Relaxed), 2);
+
+    let mut conn = pool.get().unwrap();
+    assert_eq!(conn.backend_key().unwrap().process_id(), pid);
+    assert_eq!(search_path(&mut conn), "pg_catalog");
+}
+
+#[test]
+fn test_pool_reset_none_in_transaction() {
+    let mut opts = get_opts();
+    opts.pool_reset_policy = PoolResetPolicy::None;
+    let pool = Pool::new(opts);
+
+    let pid = backend_pid(&pool);
+    assert_eq!(backend_pid(&pool), pid);
+
+    // A connection returned in a transaction is closed
+    let mut conn = pool.get().unwrap();
+    conn.query_drop("BEGIN").unwrap();
+    drop(conn);
+    assert_ne!(backend_pid(&pool), pid);
+    assert_eq!(pool.stats().total_created, 2);
+}
+
+#[derive(Default)]
+struct HookCounts {
+    acquired: AtomicUsize,
+    released: AtomicUsize,
+}
+
+struct CountingHooks(Arc<HookCounts>);
+
+impl PoolHooks for CountingHooks {
+    fn after_connect(&self, conn: &mut Conn) -> Result<()> {
+        conn.query_drop("SET search_path TO pg_catalog")?;
+        Ok(())
+    }
+
+    fn before_acquire(&self, _conn: &mut Conn) -> Result<()> {
+        // Reject every other idle connection
+        if self.0.acquired.fetch_add(1, Ordering::Relaxed) % 2 == 1 {
+            return Err(Error::InvalidUsage("rejected".into()));
+        }
+        Ok(())
+    }
+
+    fn after_release(&self, conn: &mut Conn) -> Result<()> {
+        self.0.released.fetch_add(1, Ordering::Relaxed);
+        conn.query_drop("SET search_path TO pg_catalog")?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_pool_hooks() {
+    let hooks = Arc::new(HookCounts::default());
+    let pool = Pool::with_hooks(get_opts(), CountingHooks(Arc::clone(&hooks)));
+
+    let mut conn = pool.get().unwrap();
+    let row: Option<(String,)> = conn.query_first("SHOW search_path").unwrap();
+    assert_eq!(row, Some(("pg_catalog".to_string(),)));
+    let pid = conn.backend_key().unwrap().process_id();
+    drop(conn);
+    assert_eq!(hooks.released.load(Ordering::Relaxed), 1);
+
+    // Accepted by before_acquire, with search_path restored after DISCARD ALL
+    let mut conn = pool.get().unwrap();
+    assert_eq!(conn.backend_key().unwrap().process_id(), pid);
+    let row: Option<(String,)> = conn.query_first("SHOW search_path").unwrap();
+    assert_eq!(row, Some(("pg_catalog".to_string(),)));
+    drop(conn);
+
+    // Rejected by before_acquire
+    assert_ne!(backend_pid(&pool), pid);
+    assert_eq!(hooks.acquired.load(Ordering::Relaxed), 2);
+    assert_eq!(pool.stats().total_closed_on_error, 1);
+}
+
+#[test]
+fn test_pool_close() {
+    let application_name = "zero_postgres_pool_close";
+    let mut opts = get_opts();
+    opts.application_name = Some(application_name.into());
+    // Don't leave the TCP connection of a Unix socket upgrade behind in pg_stat_activity
+    opts.prefer_unix_socket = false;
+    let pool = Pool::new(opts);
+
+    let mut conn = Conn::new(get_opts()).unwrap();
+    let count_sql = format!(
+        "SELECT count(*)::int4 FROM pg_stat_activity WHERE application_name = '{}'",
+        application_name
+    );
+    let first = pool.get().unwrap();
+    drop(pool.get().unwrap());
+    let count: Option<(i32,)> = conn.query_first(&count_sql).unwrap();
+    assert_eq!(count, Some((2,)));
+
+    // Idle connections are closed now, checked-out ones when returned
+    assert!(!pool.close(Duration::from_millis(100)));
+    assert!(pool.is_closed());
+    assert_eq!(pool.stats().idle, 0);
+    let Err(Error::PoolClosed) = pool.get() else {
+        panic!("expected PoolClosed error");
+    };
+    drop(first);
+    assert!(pool.close(Duration::ZERO));
+    thread::sleep(Duration::from_millis(300));
+    let count: Option<(i32,)> = conn.query_first(&count_sql).unwrap();
+    assert_eq!(count, Some((0,)));
+}
+
+#[test]
+fn test_pool_close_waits_for_connections() {
+    let mut opts = get_opts();
+    opts.pool_max_concurrency = Some(1);
+    let pool = Arc::new(Pool::new(opts));
+
+    let conn = pool.get().unwrap();
+    let waiter = {
+        let pool = Arc::clone(&pool);
+        thread::spawn(move || pool.get().map(drop))
+    };
+    let holder = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        drop(conn);
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    let start = Instant::now();
+    assert!(pool.close(Duration::from_secs(5)));
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(pool.stats().in_use, 0);
+
+    // Waiters are woken with an error
+    let Err(Error::PoolClosed) = waiter.join().unwrap() else {
+        panic!("expected PoolClosed error");
+    };
+    holder.join().unwrap();
+}
+
+struct SlowConnectHooks;
+
+impl PoolHooks for SlowConnectHooks {
+    fn after_connect(&self, _conn: &mut Conn) -> Result<()> {
+        thread::sleep(Duration::from_millis(300));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_pool_close_during_checkout() {
+    let pool = Arc::new(Pool::with_hooks(get_opts(), SlowConnectHooks));
+
+    let getter = {
+        let pool = Arc::clone(&pool);
+        thread::spawn(move || pool.get().map(drop))
+    };
+    thread::sleep(Duration::from_millis(100));
+
+    // Waits for the connection being opened, which isn't handed out
+    let start = Instant::now();
+    assert!(pool.close(Duration::from_secs(5)));
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    let Err(Error::PoolClosed) = getter.join().unwrap() else {
+        panic!("expected PoolClosed error");
+    };
+    let stats = pool.stats();
+    assert_eq!((stats.idle, stats.in_use, stats.total_created), (0, 0, 1));
+}
+
+#[tokio::test]
+async fn test_tokio_pool_close() {
+    let mut opts = get_opts();
+    opts.pool_max_concurrency = Some(1);
+    let pool = Arc::new(zero_postgres::tokio::Pool::new(opts));
+
+    let conn = pool.get().await.unwrap();
+    let waiter = {
+        let pool = Arc::clone(&pool);
+        tokio::spawn(async move { pool.get().await.map(drop) })
+    };
+    let holder = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        drop(conn);
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Waits for the checked-out connection to be returned and closed
+    let start = Instant::now();
+    assert!(pool.close(Duration::from_secs(5)).await);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    let stats = pool.stats();
+    assert_eq!((stats.idle, stats.in_use), (0, 0));
+
+    let Err(Error::PoolClosed) = waiter.await.unwrap() else {
+        panic!("expected PoolClosed error");
+    };
+    let Err(Error::PoolClosed) = pool.get().await else {
+        panic!("expected PoolClosed error");
+    };
+    holder.await.unwrap();
+}
+
+struct AsyncSearchPathHooks;
+
+impl zero_postgres::tokio::PoolHooks for AsyncSearchPathHooks {
+    fn after_connect<'a>(
+        &'a self,
+        conn: &'a mut zero_postgres::tokio::Conn,
+    ) -> zero_postgres::tokio::PoolHookFuture<'a> {
+        Box::pin(async move {
+            conn.query_drop("SET search_path TO pg_catalog").await?;
+            Ok(())
+        })
+    }
+}
+
+#[test]
+fn test_tokio_pool_hooks_and_reaper() {
+    let mut opts = get_opts();
+    opts.pool_idle_timeout = Some(Duration::from_millis(200));
+    // Created outside a runtime, so the reaper starts on the first `get`
+    let pool = zero_postgres::tokio::Pool::with_hooks(opts, AsyncSearchPathHooks);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut conn = pool.get().await.unwrap();
+        let row: Option<(String,)> = conn.query_first("SHOW search_path").await.unwrap();
+        assert_eq!(row, Some(("pg_catalog".to_string(),)));
+        drop(conn);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(pool.stats().idle, 1);
+
+        // Closed by the reaper after the idle timeout
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(pool.stats().idle, 0);
+        assert_eq!(pool.stats().total_created, 1);
+    });
+}
+
+struct AsyncSlowConnectHooks;
+
+impl zero_postgres::tokio::PoolHooks for AsyncSlowConnectHooks {
+    fn after_connect<'a>(
+        &'a self,
+        _conn: &'a mut zero_postgres::tokio::Conn,
+    ) -> zero_postgres::tokio::PoolHookFuture<'a> {
+        Box::pin(async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Ok(())
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_tokio_pool_cancelled_checkout() {
+    let pool = zero_postgres::tokio::Pool::with_hooks(get_opts(), AsyncSlowConnectHooks);
+
+    // Cancelled while connecting
+    let get = tokio::time::timeout(Duration::from_millis(100), pool.get()).await;
+    assert!(get.is_err());
+    assert_eq!(pool.stats().in_use, 0);
+
+    let start = Instant::now();
+    assert!(pool.close(Duration::from_secs(5)).await);
+    assert!(start.elapsed() < Duration::from_millis(100));
+}
@@ -0,0 +1,83 @@
+//! Tests for protocol version negotiation
+
+use std::env;
+use std::thread;
+use std::time::Duration;
+use zero_postgres::sync::Conn;
+use zero_postgres::{Error, Opts, ProtocolVersion};
+
+fn get_opts() -> Opts {
+    let mut db_url =
+        env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://localhost/postgres".to_string());
+    if !db_url.contains("sslmode=") {
+        if db_url.contains('?') {
+            db_url.push_str("&sslmode=disable");
+        } else {
+            db_url.push_str("?sslmode=disable");
+        }
+    }
+    Opts::try_from(db_url.as_str()).expect("Failed to parse DATABASE_URL")
+}
+
+/// Whether the server supports protocol 3.2 (PostgreSQL 18+).
+fn server_supports_3_2(conn: &Conn) -> bool {
+    conn.server_params()
+        .iter()
+        .find(|(name, _)| name == "server_version")
+        .and_then(|(_, version)| version.split('.').next()?.parse::<u32>().ok())
+        .is_some_and(|major| major >= 18)
+}
+
+#[test]
+fn test_negotiated_protocol_version() {
+    let mut conn = Conn::new(get_opts()).expect("Failed to connect");
+    let expected = if server_supports_3_2(&conn) {
+        ProtocolVersion::V3_2
+    } else {
+        ProtocolVersion::V3_0
+    };
+    assert_eq!(conn.protocol_version(), expected);
+    if expected == ProtocolVersion::V3_0 {
+        assert_eq!(conn.backend_key().unwrap().secret_key().len(), 4);
+    }
+
+    let row: Option<(i32,)> = conn.query_first("SELECT 1").unwrap();
+    assert_eq!(row, Some((1,)));
+}
+
+#[test]
+fn test_max_protocol_version_3_0() {
+    let mut opts = get_opts();
+    opts.max_protocol_version = ProtocolVersion::V3_0;
+    let mut conn = Conn::new(opts).expect("Failed to connect");
+    assert_eq!(conn.protocol_version(), ProtocolVersion::V3_0);
+    assert_eq!(conn.backend_key().unwrap().secret_key().len(), 4);
+
+    // Cancellation works with the 4-byte cancel key
+    let token = conn.cancel_token();
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        token.cancel().unwrap();
+    });
+    let err = conn.query_drop("SELECT pg_sleep(10)").unwrap_err();
+    assert_eq!(err.sqlstate(), Some("57014"));
+    canceller.join().unwrap();
+}
+
+#[test]
+fn test_min_protocol_version_3_2() {
+    let supports_3_2 = server_supports_3_2(&Conn::new(get_opts()).expect("Failed to connect"));
+
+    let mut opts = get_opts();
+    opts.min_protocol_version = ProtocolVersion::V3_2;
+    match Conn::new(opts) {
+        Ok(conn) => {
+            assert!(supports_3_2);
+            assert_eq!(conn.protocol_version(), ProtocolVersion::V3_2);
+        }
+        Err(e) => {
+            assert!(!supports_3_2);
+            assert!(matches!(e, Error::Unsupported(_)), "{:?}", e);
+        }
+    }
+}
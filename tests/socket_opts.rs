@@ -0,0 +1,100 @@
+//! Tests for connect timeout, keepalive and socket timeout options
+
+use std::env;
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+use zero_postgres::Opts;
+use zero_postgres::sync::Conn;
+
+fn get_opts() -> Opts {
+    let mut db_url =
+        env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://localhost/postgres".to_string());
+    if !db_url.contains("sslmode=") {
+        if db_url.contains('?') {
+            db_url.push_str("&sslmode=disable");
+        } else {
+            db_url.push_str("?sslmode=disable");
+        }
+    }
+    Opts::try_from(db_url.as_str()).expect("Failed to parse DATABASE_URL")
+}
+
+#[test]
+fn test_connect_with_socket_options() {
+    let mut opts = get_opts();
+    opts.connect_timeout = Some(Duration::from_secs(5));
+    opts.keepalives_idle = Some(Duration::from_secs(30));
+    opts.keepalives_interval = Some(Duration::from_secs(5));
+    opts.keepalives_count = Some(3);
+    opts.tcp_user_timeout = Some(Duration::from_secs(10));
+    opts.read_timeout = Some(Duration::from_secs(5));
+    opts.write_timeout = Some(Duration::from_secs(5));
+
+    let mut conn = Conn::new(opts).unwrap();
+    conn.query_drop("SELECT 1").unwrap();
+}
+
+#[test]
+fn test_connect_timeout() {
+    let opts = Opts {
+        // TEST-NET-1, not routable
+        host: "192.0.2.1".into(),
+        connect_timeout: Some(Duration::from_millis(300)),
+        ..Opts::default()
+    };
+
+    let start = Instant::now();
+    assert!(Conn::new(opts).is_err());
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_connect_timeout_stalled_startup() {
+    // Accepts the connection but never answers the startup message
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        thread::sleep(Duration::from_secs(2));
+        drop(stream);
+    });
+
+    let opts = Opts {
+        host: "127.0.0.1".into(),
+        port,
+        user: "app".into(),
+        connect_timeout: Some(Duration::from_millis(300)),
+        ..Opts::default()
+    };
+    let start = Instant::now();
+    let Err(zero_postgres::Error::Io(err)) = Conn::new(opts) else {
+        panic!("expected connection setup to time out");
+    };
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(1));
+    server.join().unwrap();
+}
+
+#[test]
+fn test_read_timeout_breaks_connection() {
+    let mut opts = get_opts();
+    opts.read_timeout = Some(Duration::from_millis(300));
+
+    let mut conn = Conn::new(opts).unwrap();
+    let err = conn.query_drop("SELECT pg_sleep(3)").unwrap_err();
+    assert!(err.is_connection_broken(), "{err:?}");
+    assert!(conn.is_broken());
+}
+
+#[test]
+fn test_query_timeout_shorter_than_read_timeout() {
+    let mut opts = get_opts();
+    opts.read_timeout = Some(Duration::from_secs(5));
+    opts.query_timeout = Some(Duration::from_millis(200));
+
+    let mut conn = Conn::new(opts).unwrap();
+    let err = conn.query_drop("SELECT pg_sleep(3)").unwrap_err();
+    assert!(matches!(err, zero_postgres::Error::Timeout), "{err:?}");
+    conn.query_drop("SELECT 1").unwrap();
+}
@@ -0,0 +1,136 @@
+//! Tests for query timeouts
+
+use std::env;
+use std::time::{Duration, Instant};
+use zero_postgres::Error;
+use zero_postgres::sync::Conn;
+
+fn get_conn() -> Conn {
+    let mut db_url =
+        env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://localhost/postgres".to_string());
+    if !db_url.contains("sslmode=") {
+        if db_url.contains('?') {
+            db_url.push_str("&sslmode=disable");
+        } else {
+            db_url.push_str("?sslmode=disable");
+        }
+    }
+    let mut conn = Conn::new(db_url.as_str()).expect("Failed to connect");
+    conn.set_query_timeout(Some(Duration::from_millis(200)));
+    conn
+}
+
+fn assert_usable(conn: &mut Conn) {
+    assert!(!conn.is_broken());
+    let row: Option<(i32,)> = conn.query_first("SELECT 1").unwrap();
+    assert_eq!(row, Some((1,)));
+    let row: Option<(i32,)> = conn.exec_first("SELECT $1::int", (2,)).unwrap();
+    assert_eq!(row, Some((2,)));
+}
+
+#[test]
+fn test_query_timeout() {
+    let mut conn = get_conn();
+
+    let start = Instant::now();
+    let err = conn.query_drop("SELECT pg_sleep(10)").unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(matches!(err, Error::Timeout), "{err:?}");
+
+    assert_usable(&mut conn);
+}
+
+#[test]
+fn test_exec_timeout() {
+    let mut conn = get_conn();
+
+    let err = conn
+        .exec_drop("SELECT pg_sleep($1)", (10.0_f64,))
+        .unwrap_err();
+    assert!(matches!(err, Error::Timeout), "{err:?}");
+
+    assert_usable(&mut conn);
+}
+
+#[test]
+fn test_exec_iter_timeout() {
+    let mut conn = get_conn();
+
+    let err = conn
+        .exec_iter("SELECT pg_sleep(10)", (), |portal| {
+            let mut handler = zero_postgres::handler::DropHandler::new();
+            while portal.fetch(10, &mut handler)? {}
+            Ok(())
+        })
+        .unwrap_err();
+    assert!(matches!(err, Error::Timeout), "{err:?}");
+
+    assert_usable(&mut conn);
+}
+
+#[test]
+fn test_pipeline_timeout() {
+    let mut conn = get_conn();
+
+    let err = conn
+        .run_pipeline(|p| {
+            let t1 = p.exec("SELECT 1::int", ())?;
+            let t2 = p.exec("SELECT pg_sleep(10)", ())?;
+            let t3 = p.exec("SELECT 3::int", ())?;
+            p.sync()?;
+
+            let r1: Option<(i32,)> = p.claim_one(t1)?;
+            assert_eq!(r1, Some((1,)));
+            p.claim_drop(t2)?;
+            p.claim_drop(t3)?;
+            Ok(())
+        })
+        .unwrap_err();
+    assert!(matches!(err, Error::Timeout), "{err:?}");
+
+    assert_usable(&mut conn);
+}
+
+#[test]
+fn test_transaction_after_timeout() {
+    let mut conn = get_conn();
+
+    conn.query_drop("BEGIN").unwrap();
+    let err = conn.query_drop("SELECT pg_sleep(10)").unwrap_err();
+    assert!(matches!(err, Error::Timeout), "{err:?}");
+    assert!(conn.in_transaction());
+    conn.query_drop("ROLLBACK").unwrap();
+
+    assert_usable(&mut conn);
+}
+
+#[test]
+fn test_fast_query_within_timeout() {
+    let mut conn = get_conn();
+
+    for _ in 0..3 {
+        conn.query_drop("SELECT pg_sleep(0.05)").unwrap();
+    }
+    assert_usable(&mut conn);
+}
+
+#[test]
+fn test_no_timeout() {
+    let mut conn = get_conn();
+    conn.set_query_timeout(None);
+    assert_eq!(conn.query_timeout(), None);
+
+    conn.query_drop("SELECT pg_sleep(0.3)").unwrap();
+}
+
+#[test]
+fn test_server_error_leaves_connection_usable() {
+    let mut conn = get_conn();
+
+    let err = conn.query_drop("SELECT 1/0").unwrap_err();
+    assert_eq!(err.sqlstate(), Some("22012"));
+    let err = conn.exec_drop("SELECT 1/$1::int", (0,)).unwrap_err();
+    assert_eq!(err.sqlstate(), Some("22012"));
+
+    assert_usable(&mut conn);
+}